@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::proc::{build_children_index, find_descendants};
+use crate::template_engine::{format_memory, Peaks};
+
+
+#[derive(Debug, PartialEq)]
+pub struct NameGroup {
+    pub name: String,
+    pub total: u64,
+    pub count: u64,
+}
+
+
+pub fn group_by_name(processes: &[(String, u64)]) -> Vec<NameGroup> {
+    // processes are (name, memory) pairs, groups are sorted by total memory, biggest first
+    let mut groups: HashMap<&str, NameGroup> = HashMap::new();
+    for (name, memory) in processes {
+        let group = groups.entry(name.as_str()).or_insert_with(|| NameGroup{name: name.clone(), total: 0, count: 0});
+        group.total = group.total.saturating_add(*memory);
+        group.count += 1;
+    }
+    let mut groups: Vec<NameGroup> = groups.into_values().collect();
+    groups.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.name.cmp(&b.name)));
+    groups
+}
+
+
+pub fn format_groups(title: &str, groups: &[NameGroup], precision: usize) -> String {
+    let mut out = format!("{} breakdown by name:\n", title);
+    for group in groups {
+        out.push_str(&format!(
+            "  {}: {} ({} proc{})\n",
+            group.name,
+            format_memory(group.total, precision),
+            group.count,
+            if group.count == 1 { "" } else { "s" },
+        ));
+    }
+    out
+}
+
+
+// --largest-process: the biggest any single process of the tree ever got
+#[derive(Debug, Default, PartialEq)]
+pub struct LargestProcess {
+    pub pid: i32,
+    pub name: String,
+    pub memory: u64,
+}
+
+impl LargestProcess {
+    pub fn offer(&mut self, processes: &[(i32, u64)], name_of: impl Fn(i32) -> Option<String>) {
+        // the name is only read for a new record, most samples just compare numbers
+        let Some(&(pid, memory)) = processes.iter().max_by_key(|(_, memory)| *memory) else {
+            return;
+        };
+        if memory > self.memory
+            && let Some(name) = name_of(pid) {
+                *self = LargestProcess{pid, name, memory};
+            }
+    }
+
+    pub fn format(&self, precision: usize) -> String {
+        format!("largest single process: {} {} (pid {})\n", self.name, format_memory(self.memory, precision), self.pid)
+    }
+}
+
+
+pub fn format_peaks(peaks: &Peaks, precision: usize) -> String {
+    // the metrics which were measured, each at its own peak
+    let mut out = format!(
+        "peaks: private {}, vsz {}",
+        format_memory(peaks.private_bytes, precision),
+        format_memory(peaks.vsz, precision),
+    );
+    let memory = [
+        ("uss", peaks.uss),
+        ("pss", peaks.pss),
+        ("swap", peaks.swap),
+        ("hugepages", peaks.huge_pages),
+        ("rss anon", peaks.rss_anon),
+        ("rss file", peaks.rss_file),
+        ("rss shmem", peaks.rss_shmem),
+    ];
+    for (name, peak) in memory {
+        if let Some(peak) = peak {
+            out.push_str(&format!(", {} {}", name, format_memory(peak, precision)));
+        }
+    }
+    if let Some(count) = peaks.map_count {
+        out.push_str(&format!(", map count {}", count));
+    }
+    out.push('\n');
+    out
+}
+
+
+// --top: the heaviest process trees of the system, a tree being a child of init and
+// its descendants, like a service or a login session
+#[derive(Debug, PartialEq)]
+pub struct TreeTotal {
+    pub root: i32,
+    pub total: u64,
+    pub count: usize,
+}
+
+
+pub fn rank_trees(parent_of: &HashMap<i32, i32>, resident: &HashMap<i32, u64>, top: usize) -> Vec<TreeTotal> {
+    // processes without a resident value (exited, kernel threads) count for nothing
+    let children_of = build_children_index(parent_of);
+    let mut trees: Vec<TreeTotal> = parent_of.iter()
+        .filter(|(_, ppid)| **ppid == 1)
+        .map(|(root, _)| {
+            let descendants = find_descendants(&children_of, &[*root]);
+            let total = descendants.iter()
+                .filter_map(|pid| resident.get(pid))
+                .fold(0u64, |total, memory| total.saturating_add(*memory));
+            TreeTotal{root: *root, total, count: descendants.len()}
+        })
+        .collect();
+    trees.sort_by(|a, b| b.total.cmp(&a.total).then(a.root.cmp(&b.root)));
+    trees.truncate(top);
+    trees
+}
+
+
+pub fn format_top(trees: &[TreeTotal], name_of: impl Fn(i32) -> Option<String>, precision: usize) -> String {
+    let mut out = String::new();
+    for (rank, tree) in trees.iter().enumerate() {
+        out.push_str(&format!(
+            "{:>3}. {} (pid {}): {} ({} proc{})\n",
+            rank + 1,
+            name_of(tree.root).unwrap_or_else(|| "?".to_string()),
+            tree.root,
+            format_memory(tree.total, precision),
+            tree.count,
+            if tree.count == 1 { "" } else { "s" },
+        ));
+    }
+    out
+}
+
+
+// --folded: the tree in the collapsed stack format of flamegraph.pl and inferno,
+// one line per process, "target;child;grandchild <rss>". The width of a frame is the
+// memory of the process and of its descendants.
+pub fn fold_tree(processes: &[(i32, u64)], parent_of: &HashMap<i32, i32>, name_of: impl Fn(i32) -> String) -> String {
+    let in_tree: HashSet<i32> = processes.iter().map(|(pid, _)| *pid).collect();
+    let mut lines: Vec<String> = processes.iter()
+        .filter(|(_, memory)| *memory > 0)
+        .map(|(pid, memory)| {
+            // up to the first process outside the tree, bounded in case the pids were reused into a loop
+            let mut path = vec![*pid];
+            while let Some(ppid) = parent_of.get(path.last().unwrap())
+                && in_tree.contains(ppid)
+                && path.len() <= in_tree.len() {
+                    path.push(*ppid);
+                }
+            let frames: Vec<String> = path.iter().rev()
+                .map(|pid| format!("{}-{}", name_of(*pid).replace(';', "_"), pid))
+                .collect();
+            format!("{} {}\n", frames.join(";"), memory)
+        })
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_by_name() {
+        let processes = vec![
+            ("(nginx)".to_string(), 100),
+            ("(python)".to_string(), 1000),
+            ("(nginx)".to_string(), 200),
+            ("(python)".to_string(), 3000),
+            ("(bash)".to_string(), 300),
+        ];
+        let groups = group_by_name(&processes);
+        assert_eq!(groups, vec![
+            NameGroup{name: "(python)".to_string(), total: 4000, count: 2},
+            NameGroup{name: "(bash)".to_string(), total: 300, count: 1},
+            NameGroup{name: "(nginx)".to_string(), total: 300, count: 2},
+        ]);
+    }
+
+    #[test]
+    fn test_group_by_name_empty() {
+        assert!(group_by_name(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_format_groups() {
+        let groups = vec![
+            NameGroup{name: "(python)".to_string(), total: 4 * 1024 * 1024, count: 12},
+            NameGroup{name: "(bash)".to_string(), total: 300, count: 1},
+        ];
+        assert_eq!(format_groups("peak", &groups, 0), "peak breakdown by name:\n  (python): 4MiB (12 procs)\n  (bash): 300B (1 proc)\n");
+    }
+
+    #[test]
+    fn test_largest_process_keeps_the_record() {
+        let mut largest = LargestProcess::default();
+        largest.offer(&[(10, 100), (11, 300)], |pid| Some(format!("w{}", pid)));
+        largest.offer(&[(10, 200), (12, 250)], |_| panic!("no new record, no name to read"));
+        assert_eq!(largest, LargestProcess{pid: 11, name: "w11".to_string(), memory: 300});
+        assert_eq!(largest.format(0), "largest single process: w11 300B (pid 11)\n");
+    }
+
+    #[test]
+    fn test_largest_process_skips_exited() {
+        // a process gone before its name is read is not a record
+        let mut largest = LargestProcess::default();
+        largest.offer(&[(10, 100)], |_| None);
+        largest.offer(&[], |_| Some("never".to_string()));
+        assert_eq!(largest, LargestProcess::default());
+    }
+
+    #[test]
+    fn test_format_peaks() {
+        let peaks = Peaks{private_bytes: 2048, rss_anon: Some(1024), map_count: Some(731), vsz: 8192, ..Default::default()};
+        assert_eq!(format_peaks(&peaks, 0), "peaks: private 2KiB, vsz 8KiB, rss anon 1KiB, map count 731\n");
+        assert_eq!(format_peaks(&Peaks::default(), 0), "peaks: private 0B, vsz 0B\n");
+        let peaks = Peaks{uss: Some(3072), ..Default::default()};
+        assert_eq!(format_peaks(&peaks, 0), "peaks: private 0B, vsz 0B, uss 3KiB\n");
+        let peaks = Peaks{uss: Some(3072), pss: Some(4096), ..Default::default()};
+        assert_eq!(format_peaks(&peaks, 0), "peaks: private 0B, vsz 0B, uss 3KiB, pss 4KiB\n");
+        let peaks = Peaks{swap: Some(0), ..Default::default()};
+        assert_eq!(format_peaks(&peaks, 0), "peaks: private 0B, vsz 0B, swap 0B\n");
+        let peaks = Peaks{huge_pages: Some(2 * 1024 * 1024), ..Default::default()};
+        assert_eq!(format_peaks(&peaks, 0), "peaks: private 0B, vsz 0B, hugepages 2MiB\n");
+    }
+
+    #[test]
+    fn test_rank_trees() {
+        // 1 is init, 10 and 20 are its children, 11 and 12 belong to the 10 tree
+        let parent_of = HashMap::from([(1, 0), (10, 1), (11, 10), (12, 11), (20, 1), (30, 1)]);
+        let resident = HashMap::from([(1, 5000), (10, 100), (11, 200), (12, 300), (20, 400), (30, 50)]);
+        assert_eq!(rank_trees(&parent_of, &resident, 2), vec![
+            TreeTotal{root: 10, total: 600, count: 3},
+            TreeTotal{root: 20, total: 400, count: 1},
+        ]);
+        assert_eq!(rank_trees(&parent_of, &resident, 10).len(), 3);
+    }
+
+    #[test]
+    fn test_format_top() {
+        let trees = vec![TreeTotal{root: 10, total: 2048, count: 3}, TreeTotal{root: 20, total: 400, count: 1}];
+        let name_of = |pid| (pid == 10).then(|| "postgres".to_string());
+        assert_eq!(format_top(&trees, name_of, 0), "  1. postgres (pid 10): 2KiB (3 procs)\n  2. ? (pid 20): 400B (1 proc)\n");
+    }
+
+    #[test]
+    fn test_fold_tree() {
+        // 10 is the target, its parent 1 is outside the tree
+        let parent_of = HashMap::from([(1, 0), (10, 1), (11, 10), (12, 11), (13, 10)]);
+        let processes = vec![(10, 100), (11, 0), (12, 300), (13, 50)];
+        let name_of = |pid| if pid == 13 { "a;b".to_string() } else { format!("p{}", pid) };
+        assert_eq!(
+            fold_tree(&processes, &parent_of, name_of),
+            "p10-10 100\np10-10;a_b-13 50\np10-10;p11-11;p12-12 300\n",
+        );
+        assert_eq!(fold_tree(&[], &parent_of, |_| String::new()), "");
+    }
+}
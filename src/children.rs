@@ -0,0 +1,180 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::proc::{find_descendants, Statm};
+use crate::template_engine::{format_memory, MemorySample};
+
+
+// --per-child: every direct child of the targets is tracked on its own,
+// with the memory of its whole subtree.
+
+#[derive(Debug, PartialEq)]
+pub struct ChildStream {
+    pub pid: i32,
+    pub name: String,
+    pub current: u64,
+    pub previous: Option<u64>,   // for the Delta field, None on the first update
+    pub private: u64,
+    pub max: u64,
+    pub process_count: u64,
+    total: u128,    // sum of every sample, for the average
+    samples: u64,
+}
+
+impl ChildStream {
+    fn new(pid: i32, name: String) -> Self {
+        ChildStream{pid, name, current: 0, previous: None, private: 0, max: 0, process_count: 0, total: 0, samples: 0}
+    }
+
+    pub fn average(&self) -> u64 {
+        match self.samples {
+            0 => 0,
+            n => (self.total / n as u128) as u64,
+        }
+    }
+
+    pub fn sample(&self, tree: &MemorySample, is_final: bool) -> MemorySample<'_> {
+        // the timestamp and the sample number are the ones of the whole tree
+        MemorySample{
+            pid: self.pid,
+            process_name: &self.name,
+            current_bytes: self.current,
+            previous_bytes: self.previous,
+            max_bytes: self.max,
+            private_bytes: self.private,
+            process_count: self.process_count,
+            timestamp: tree.timestamp,
+            sample_number: tree.sample_number,
+            is_final,
+            ..Default::default()
+        }
+    }
+
+    pub fn ended_report(&self, precision: usize) -> String {
+        format!(
+            "memimpact: child {} {} ended, max {}, avg {}",
+            self.pid,
+            self.name,
+            format_memory(self.max, precision),
+            format_memory(self.average(), precision),
+        )
+    }
+}
+
+
+pub fn direct_children(children_of: &HashMap<i32, Vec<i32>>, parents: &[i32]) -> Vec<i32> {
+    let mut children: Vec<i32> = parents.iter()
+        .filter_map(|parent| children_of.get(parent))
+        .flatten()
+        .copied()
+        .collect();
+    children.sort();
+    children
+}
+
+
+#[derive(Debug, Default)]
+pub struct ChildTracker {
+    streams: BTreeMap<i32, ChildStream>,   // sorted by pid, the output order is stable
+}
+
+impl ChildTracker {
+    pub fn new() -> Self {
+        ChildTracker::default()
+    }
+
+    pub fn update(
+        &mut self,
+        children_of: &HashMap<i32, Vec<i32>>,
+        parents: &[i32],
+        statms: &HashMap<i32, &Statm>,
+        name_of: impl Fn(i32) -> String,
+    ) -> Vec<ChildStream> {
+        // returns the streams of the children which exited since the previous update
+        let children = direct_children(children_of, parents);
+        let gone: Vec<i32> = self.streams.keys().filter(|pid| !children.contains(pid)).copied().collect();
+        let ended = gone.iter().filter_map(|pid| self.streams.remove(pid)).collect();
+        for child in children {
+            let stream = self.streams.entry(child).or_insert_with(|| ChildStream::new(child, name_of(child)));
+            let subtree = find_descendants(children_of, &[child]);
+            let measured: Vec<&&Statm> = subtree.iter().filter_map(|pid| statms.get(pid)).collect();
+            stream.previous = (stream.samples > 0).then_some(stream.current);
+            stream.current = measured.iter().fold(0u64, |sum, statm| sum.saturating_add(statm.resident));
+            stream.private = measured.iter().fold(0u64, |sum, statm| sum.saturating_add(statm.private()));
+            stream.process_count = subtree.len() as u64;
+            stream.max = stream.max.max(stream.current);
+            stream.total += stream.current as u128;
+            stream.samples += 1;
+        }
+        ended
+    }
+
+    pub fn streams(&self) -> impl Iterator<Item = &ChildStream> {
+        self.streams.values()
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proc::build_children_index;
+
+    fn index(parent_of: &[(i32, i32)]) -> HashMap<i32, Vec<i32>> {
+        build_children_index(&parent_of.iter().copied().collect())
+    }
+
+    fn statm(resident: u64) -> Statm {
+        Statm{size: resident, resident, shared: 0}
+    }
+
+    #[test]
+    fn test_direct_children() {
+        let children_of = index(&[(10, 1), (11, 10), (12, 10), (13, 11), (20, 1)]);
+        assert_eq!(direct_children(&children_of, &[10]), vec![11, 12]);
+        assert_eq!(direct_children(&children_of, &[13]), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_tracker_sums_subtrees() {
+        let children_of = index(&[(10, 1), (11, 10), (12, 10), (13, 11)]);
+        let memory = [statm(100), statm(200), statm(50), statm(7)];
+        let statms = HashMap::from([(10, &memory[0]), (11, &memory[1]), (12, &memory[2]), (13, &memory[3])]);
+        let mut tracker = ChildTracker::new();
+
+        let ended = tracker.update(&children_of, &[10], &statms, |pid| format!("(w{})", pid));
+
+        assert!(ended.is_empty());
+        let streams: Vec<(i32, &str, u64, u64)> = tracker.streams()
+            .map(|s| (s.pid, s.name.as_str(), s.current, s.process_count))
+            .collect();
+        assert_eq!(streams, vec![(11, "(w11)", 207, 2), (12, "(w12)", 50, 1)]);
+    }
+
+    #[test]
+    fn test_tracker_reports_ended_and_new_children() {
+        let memory = [statm(100), statm(300), statm(40)];
+        let mut tracker = ChildTracker::new();
+
+        let children_of = index(&[(10, 1), (11, 10)]);
+        tracker.update(&children_of, &[10], &HashMap::from([(11, &memory[0])]), |_| "(w)".to_string());
+        tracker.update(&children_of, &[10], &HashMap::from([(11, &memory[1])]), |_| "(w)".to_string());
+        let children_of = index(&[(10, 1), (12, 10)]);
+        let ended = tracker.update(&children_of, &[10], &HashMap::from([(12, &memory[2])]), |_| "(w)".to_string());
+
+        assert_eq!(ended.len(), 1);
+        assert_eq!((ended[0].pid, ended[0].max, ended[0].average()), (11, 300, 200));
+        assert_eq!((ended[0].previous, ended[0].current), (Some(100), 300));
+        assert_eq!(tracker.streams().map(|s| (s.pid, s.previous)).collect::<Vec<_>>(), vec![(12, None)]);
+    }
+
+    #[test]
+    fn test_ended_report() {
+        let mut stream = ChildStream::new(42, "(worker)".to_string());
+        stream.max = 2048;
+        stream.total = 2048;
+        stream.samples = 2;
+        assert_eq!(stream.ended_report(0), "memimpact: child 42 (worker) ended, max 2KiB, avg 1KiB");
+    }
+}
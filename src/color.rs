@@ -0,0 +1,113 @@
+// --color: the decision is kept apart from the escape codes, so that every
+// combination of flag, NO_COLOR and terminal can be tested.
+
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto,     // only on a terminal, unless NO_COLOR is set
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<ColorChoice, Self::Err> {
+        match input {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice {:?}", input)),
+        }
+    }
+}
+
+
+pub fn no_color_env(value: Option<&str>) -> bool {
+    // see https://no-color.org: set and not empty, whatever its value
+    value.is_some_and(|v| !v.is_empty())
+}
+
+
+pub fn should_colorize(choice: ColorChoice, no_color: bool, is_tty: bool) -> bool {
+    // an explicit flag wins over NO_COLOR, as no-color.org recommends
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_tty && !no_color,
+    }
+}
+
+
+pub fn bold(record: &mut String) {
+    // the style ends before the record separator, a line never leaves the terminal bold
+    let separator = record.ends_with('\n');
+    if separator {
+        record.pop();
+    }
+    record.insert_str(0, BOLD);
+    record.push_str(RESET);
+    if separator {
+        record.push('\n');
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_choice_from_str() {
+        assert_eq!("auto".parse::<ColorChoice>(), Ok(ColorChoice::Auto));
+        assert_eq!("always".parse::<ColorChoice>(), Ok(ColorChoice::Always));
+        assert_eq!("never".parse::<ColorChoice>(), Ok(ColorChoice::Never));
+        assert!("yes".parse::<ColorChoice>().is_err());
+    }
+
+    #[test]
+    fn test_no_color_env() {
+        assert!(!no_color_env(None));
+        assert!(!no_color_env(Some("")));
+        assert!(no_color_env(Some("1")));
+        assert!(no_color_env(Some("0")));
+    }
+
+    #[test]
+    fn test_should_colorize_every_combination() {
+        let cases = [
+            // choice, NO_COLOR, tty, expected
+            (ColorChoice::Auto, false, true, true),
+            (ColorChoice::Auto, false, false, false),
+            (ColorChoice::Auto, true, true, false),
+            (ColorChoice::Auto, true, false, false),
+            (ColorChoice::Always, false, true, true),
+            (ColorChoice::Always, false, false, true),
+            (ColorChoice::Always, true, true, true),
+            (ColorChoice::Always, true, false, true),
+            (ColorChoice::Never, false, true, false),
+            (ColorChoice::Never, false, false, false),
+            (ColorChoice::Never, true, true, false),
+            (ColorChoice::Never, true, false, false),
+        ];
+        for (choice, no_color, is_tty, expected) in cases {
+            assert_eq!(should_colorize(choice, no_color, is_tty), expected, "{:?} {} {}", choice, no_color, is_tty);
+        }
+    }
+
+    #[test]
+    fn test_bold() {
+        let mut record = "max 2MiB\n".to_string();
+        bold(&mut record);
+        assert_eq!(record, "\x1b[1mmax 2MiB\x1b[0m\n");
+
+        let mut record = "max 2MiB".to_string();
+        bold(&mut record);
+        assert_eq!(record, "\x1b[1mmax 2MiB\x1b[0m");
+    }
+}
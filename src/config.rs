@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::Path;
+
+
+// --config: the options of a run kept in a file, one per line, named as on the command line
+// without the leading dashes:
+//
+//     # comment
+//     hertz = 10
+//     output-file = /var/log/memimpact.csv
+//     template = "{Timestamp},{CurrentBytes}\n"
+//     final = true
+//
+// The file is turned into command line arguments, so every option is available there with
+// no list to maintain. An option given on the command line replaces the one of the file.
+
+pub fn parse_config(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut entries = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected key = value", index + 1));
+        };
+        let key = key.trim();
+        if key.is_empty() || key.starts_with('-') || key.contains(char::is_whitespace) {
+            return Err(format!("line {}: invalid key {:?}", index + 1, key));
+        }
+        let value = value.trim();
+        // quotes keep the leading and trailing spaces of a template, they are not escapes
+        let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+        entries.push((key.to_string(), value.to_string()));
+    }
+    Ok(entries)
+}
+
+
+pub fn config_arguments(entries: &[(String, String)], command_line: &[String]) -> Vec<String> {
+    // the arguments of the file, without the options the command line sets again
+    let mut arguments = Vec::new();
+    for (key, value) in entries {
+        if key == "pid" {
+            // the only positional argument, a pid on the command line replaces it
+            if !command_line.iter().skip(1).any(|arg| arg.parse::<i32>().is_ok()) {
+                arguments.push(value.clone());
+            }
+            continue;
+        }
+        let option = format!("--{}", key);
+        if command_line.contains(&option) {
+            continue;
+        }
+        match value.as_str() {
+            "true" => arguments.push(option),
+            "false" => (),
+            _ => {
+                arguments.push(option);
+                arguments.push(value.clone());
+            }
+        }
+    }
+    arguments
+}
+
+
+pub fn expand_config(raw_args: Vec<String>) -> Result<Vec<String>, String> {
+    // --config <path> is replaced by the options of the file, placed before the other arguments
+    let Some(position) = raw_args.iter().position(|arg| arg == "--config") else {
+        return Ok(raw_args);
+    };
+    let path = raw_args.get(position + 1).ok_or("missing config")?;
+    let content = fs::read_to_string(Path::new(path))
+        .map_err(|e| format!("could not read the config {}: {}", path, e))?;
+    let entries = parse_config(&content).map_err(|e| format!("invalid config {}: {}", path, e))?;
+    let mut command_line = raw_args.clone();
+    command_line.drain(position..position + 2);
+    let mut expanded = command_line[..1].to_vec();
+    expanded.extend(config_arguments(&entries, &command_line));
+    expanded.extend_from_slice(&command_line[1..]);
+    Ok(expanded)
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(input: &[&str]) -> Vec<String> {
+        input.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let content = "# nightly job\n\nhertz = 10\ntemplate = \" {Pid} \"\nfinal=true\noutput-file = a=b.csv\n";
+        assert_eq!(parse_config(content).unwrap(), vec![
+            ("hertz".to_string(), "10".to_string()),
+            ("template".to_string(), " {Pid} ".to_string()),
+            ("final".to_string(), "true".to_string()),
+            ("output-file".to_string(), "a=b.csv".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_config_errors() {
+        assert_eq!(parse_config("hertz = 10\nfinal\n").unwrap_err(), "line 2: expected key = value");
+        assert_eq!(parse_config("--hertz = 10\n").unwrap_err(), "line 1: invalid key \"--hertz\"");
+    }
+
+    #[test]
+    fn test_command_line_overrides_config() {
+        let entries = parse_config("hertz = 10\nfinal = true\nno-final = false\npid = 42\nname = bash\n").unwrap();
+        let command_line = args(&["memimpact", "--hertz", "2", "1234"]);
+        assert_eq!(config_arguments(&entries, &command_line), args(&["--final", "--name", "bash"]));
+        assert_eq!(config_arguments(&entries, &args(&["memimpact"])), args(&["--hertz", "10", "--final", "42", "--name", "bash"]));
+    }
+
+    #[test]
+    fn test_expand_config() {
+        let path = std::env::temp_dir().join(format!("memimpact-config-{}.conf", std::process::id()));
+        fs::write(&path, "hertz = 10\nfinal = true\n").unwrap();
+        let raw_args = args(&["memimpact", "--config", path.to_str().unwrap(), "--hertz", "2", "1234"]);
+        let expanded = expand_config(raw_args);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(expanded.unwrap(), args(&["memimpact", "--final", "--hertz", "2", "1234"]));
+        assert_eq!(expand_config(args(&["memimpact", "1234"])).unwrap(), args(&["memimpact", "1234"]));
+        assert!(expand_config(args(&["memimpact", "--config", "/nonexistent/memimpact.conf"])).is_err());
+    }
+}
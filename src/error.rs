@@ -0,0 +1,177 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::template_engine::format_memory;
+
+
+#[derive(Debug)]
+pub enum ParseArgError {
+    MissingValue(&'static str),
+    InvalidValue(&'static str),
+    ConflictingFlags(&'static str, &'static str),
+    Unconfirmed(&'static str, &'static str),   // what the arguments would do, the flag confirming it
+}
+
+impl fmt::Display for ParseArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseArgError::MissingValue(name) => write!(f, "missing {}", name),
+            ParseArgError::InvalidValue(name) => write!(f, "invalid {}", name),
+            ParseArgError::ConflictingFlags(a, b) => write!(f, "--{} cannot be combined with --{}", a, b),
+            ParseArgError::Unconfirmed(what, flag) => write!(f, "{}, pass --{} to confirm", what, flag),
+        }
+    }
+}
+
+
+// The exit codes scripts can rely on, listed in --help under EXIT CODES.
+// A new error is mapped to one of them, never to a new number without documenting it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    Failure = 1,            // anything else: output, sqlite, kernel support...
+    Usage = 2,              // invalid arguments or template
+    TargetNotFound = 3,     // no target at start, or gone with --fail-if-absent
+    ThresholdBreached = 4,  // --baseline, --threshold
+    KilledOnThreshold = 5,  // --kill-on-threshold
+    Hung = 6,               // --watchdog
+    OomKilled = 7,          // the kernel log names a process of the tree
+}
+
+
+// Every way memimpact can stop early, mapped to a message and an exit code by main
+#[derive(Debug)]
+pub enum MemimpactError {
+    Args(ParseArgError),
+    Config(String),
+    Output(io::Error),
+    Sqlite(PathBuf, io::Error),
+    PidNotFound(i32),
+    NoMatchingProcess,
+    PidNamespace(String),
+    UnsupportedKernel(String),
+    Template(&'static str, String),   // the flag the template came from, the parse error
+    Diff(String),
+    Replay(String),
+    KilledOnThreshold,
+    AboveBaseline(u64, u64),   // --baseline, the peak and the limit in bytes
+    AboveThreshold(u64, u64),  // --threshold, the peak and the limit in bytes
+    TargetExited,   // --fail-if-absent
+    WaitTimeout(String, u64),   // --wait-for, the name and the --wait-timeout in seconds
+    Hung(u64),      // --watchdog, in seconds
+    Spawn(String, io::Error),   // --measure, the command which could not be started
+    OomKilled(i32, String, u64),   // the pid and name killed, the peak of the tree in bytes
+}
+
+impl MemimpactError {
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            MemimpactError::Args(_) | MemimpactError::Config(_) | MemimpactError::Template(..) => ExitCode::Usage,
+            MemimpactError::PidNotFound(_)
+            | MemimpactError::NoMatchingProcess
+            | MemimpactError::TargetExited
+            | MemimpactError::WaitTimeout(..) => ExitCode::TargetNotFound,
+            MemimpactError::AboveBaseline(..) | MemimpactError::AboveThreshold(..) => ExitCode::ThresholdBreached,
+            MemimpactError::KilledOnThreshold => ExitCode::KilledOnThreshold,
+            MemimpactError::Hung(_) => ExitCode::Hung,
+            MemimpactError::OomKilled(..) => ExitCode::OomKilled,
+            MemimpactError::Output(_)
+            | MemimpactError::Sqlite(..)
+            | MemimpactError::PidNamespace(_)
+            | MemimpactError::UnsupportedKernel(_)
+            | MemimpactError::Diff(_)
+            | MemimpactError::Replay(_)
+            | MemimpactError::Spawn(..) => ExitCode::Failure,
+        }
+    }
+}
+
+impl fmt::Display for MemimpactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemimpactError::Args(e) => write!(f, "invalid arguments: {}", e),
+            MemimpactError::Output(e) => write!(f, "could not open the output: {}", e),
+            MemimpactError::Sqlite(path, e) => write!(f, "could not start sqlite3 for {}: {}", path.display(), e),
+            MemimpactError::PidNotFound(pid) => write!(f, "no process with pid {}", pid),
+            MemimpactError::NoMatchingProcess => write!(f, "no process matches the requested name, command line or cgroup"),
+            MemimpactError::Config(msg) | MemimpactError::PidNamespace(msg) | MemimpactError::Diff(msg) | MemimpactError::Replay(msg) => write!(f, "{}", msg),
+            MemimpactError::UnsupportedKernel(msg) => write!(f, "{}", msg),
+            MemimpactError::Template(flag, msg) => write!(f, "invalid --{}: {}", flag, msg),
+            MemimpactError::KilledOnThreshold => write!(f, "the target was killed for going above --max-threshold"),
+            MemimpactError::AboveBaseline(peak, limit) => write!(
+                f,
+                "the peak went above --baseline and its --tolerance ({} > {})",
+                format_memory(*peak, 2),
+                format_memory(*limit, 2),
+            ),
+            MemimpactError::AboveThreshold(peak, limit) => write!(
+                f,
+                "the peak went above --threshold ({} > {})",
+                format_memory(*peak, 2),
+                format_memory(*limit, 2),
+            ),
+            MemimpactError::TargetExited => write!(f, "the target exited while it was monitored"),
+            MemimpactError::WaitTimeout(name, seconds) => write!(f, "no process named {:?} started within {}s (--wait-timeout)", name, seconds),
+            MemimpactError::Hung(seconds) => write!(f, "no sample completed for {}s, giving up (--watchdog)", seconds),
+            MemimpactError::Spawn(command, e) => write!(f, "could not run {}: {}", command, e),
+            MemimpactError::OomKilled(pid, name, peak) => write!(
+                f,
+                "{} (pid {}) was OOM-killed, the tree peaked at {}",
+                name,
+                pid,
+                format_memory(*peak, 2),
+            ),
+        }
+    }
+}
+
+impl From<ParseArgError> for MemimpactError {
+    fn from(e: ParseArgError) -> Self {
+        MemimpactError::Args(e)
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(MemimpactError::KilledOnThreshold.exit_code() as i32, 5);
+        assert_eq!(MemimpactError::TargetExited.exit_code() as i32, 3);
+        assert_eq!(MemimpactError::Hung(10).exit_code() as i32, 6);
+        assert_eq!(MemimpactError::OomKilled(42, "java".to_string(), 1024).exit_code() as i32, 7);
+        assert_eq!(MemimpactError::AboveBaseline(2048, 1024).exit_code() as i32, 4);
+        assert_eq!(MemimpactError::AboveThreshold(2048, 1024).exit_code() as i32, 4);
+        assert_eq!(MemimpactError::PidNotFound(42).exit_code() as i32, 3);
+        assert_eq!(MemimpactError::NoMatchingProcess.exit_code() as i32, 3);
+        assert_eq!(MemimpactError::WaitTimeout("java".to_string(), 60).exit_code() as i32, 3);
+        assert_eq!(MemimpactError::Args(ParseArgError::MissingValue("pid")).exit_code() as i32, 2);
+        assert_eq!(MemimpactError::Template("template", "unclosed field".to_string()).exit_code() as i32, 2);
+        assert_eq!(MemimpactError::Config("missing config".to_string()).exit_code() as i32, 2);
+        assert_eq!(MemimpactError::Diff("no samples".to_string()).exit_code() as i32, 1);
+        assert_eq!(ExitCode::Success as i32, 0);
+    }
+
+    #[test]
+    fn test_messages() {
+        let e = MemimpactError::Args(ParseArgError::ConflictingFlags("final", "no-final"));
+        assert_eq!(e.to_string(), "invalid arguments: --final cannot be combined with --no-final");
+        let e = MemimpactError::Args(ParseArgError::Unconfirmed("this tracks everything", "all"));
+        assert_eq!(e.to_string(), "invalid arguments: this tracks everything, pass --all to confirm");
+        let e = MemimpactError::Template("template", "unclosed field".to_string());
+        assert_eq!(e.to_string(), "invalid --template: unclosed field");
+        let e = MemimpactError::AboveBaseline(1200 * 1024, 1100 * 1024);
+        assert_eq!(e.to_string(), "the peak went above --baseline and its --tolerance (1.17MiB > 1.07MiB)");
+        let e = MemimpactError::AboveThreshold(600 * 1024 * 1024, 500 * 1000 * 1000);
+        assert_eq!(e.to_string(), "the peak went above --threshold (600.00MiB > 476.84MiB)");
+        let e = MemimpactError::WaitTimeout("java".to_string(), 60);
+        assert_eq!(e.to_string(), "no process named \"java\" started within 60s (--wait-timeout)");
+        let e = MemimpactError::OomKilled(42, "java".to_string(), 2048 * 1024);
+        assert_eq!(e.to_string(), "java (pid 42) was OOM-killed, the tree peaked at 2.00MiB");
+    }
+}
@@ -0,0 +1,401 @@
+use std::fmt::Write;
+
+use crate::template_engine::{render_field, Field, FieldKind, MemorySample};
+
+
+// Structured output formats, the alternative to user templates.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormatKind {
+    Text,     // the default template or the one given by the user
+    Influx,
+    Json,     // one flat object per line, which --replay and --diff read back
+    Csv,      // a header row, then one row per sample, also read back by --replay
+    Prometheus,   // the text exposition format, for the textfile collector of node_exporter
+}
+
+impl std::str::FromStr for FormatKind {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<FormatKind, Self::Err> {
+        match input {
+            "text" => Ok(FormatKind::Text),
+            "influx" => Ok(FormatKind::Influx),
+            "json" => Ok(FormatKind::Json),
+            "csv" => Ok(FormatKind::Csv),
+            "prometheus" => Ok(FormatKind::Prometheus),
+            _ => Err(format!("unknown format {:?}", input)),
+        }
+    }
+}
+
+
+// --fields: the columns of CSV and the keys of JSON, in the given order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Columns {
+    pub fields: Vec<Field>,
+    pub precision: usize,   // decimals of the human fields
+}
+
+
+fn column_name(field: Field, out: &mut String) {
+    // the snake_case of the field, like the default columns: CurrentBytes is current_bytes
+    for (i, c) in field.name().char_indices() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+}
+
+
+fn escape_influx_tag(value: &str, out: &mut String) {
+    // see https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/#special-characters
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ' | '\\') {
+            out.push('\\');
+        }
+        if c == '\n' {
+            out.push_str("\\n");
+            continue;
+        }
+        out.push(c);
+    }
+}
+
+
+fn escape_json_string(value: &str, out: &mut String) -> std::fmt::Result {
+    // see https://www.rfc-editor.org/rfc/rfc8259#section-7, a comm can hold quotes and control characters
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.push(c),
+        }
+    }
+    Ok(())
+}
+
+
+pub fn render_json(sample: &MemorySample, columns: Option<&Columns>, out: &mut String) -> std::fmt::Result {
+    if let Some(columns) = columns {
+        return render_json_columns(sample, columns, out);
+    }
+    // {"pid":1234,"process_name":"firefox","current_bytes":1024,"max_bytes":2048,"timestamp":1700000000,"final":false}
+    write!(out, "{{\"pid\":{},\"process_name\":\"", sample.pid)?;
+    escape_json_string(sample.process_name, out)?;
+    writeln!(
+        out,
+        "\",\"current_bytes\":{},\"max_bytes\":{},\"timestamp\":{},\"final\":{}}}",
+        sample.current_bytes, sample.max_bytes, sample.timestamp, sample.is_final,
+    )
+}
+
+
+fn render_json_columns(sample: &MemorySample, columns: &Columns, out: &mut String) -> std::fmt::Result {
+    // strings are quoted, numbers are not and a missing value is null: {"pid":1234,"swap":null}
+    let mut value = String::new();
+    for (i, field) in columns.fields.iter().enumerate() {
+        out.push_str(if i == 0 { "{\"" } else { ",\"" });
+        column_name(*field, out);
+        out.push_str("\":");
+        value.clear();
+        render_field(*field, sample, columns.precision, &mut value)?;
+        match field.kind() {
+            FieldKind::String => {
+                out.push('"');
+                escape_json_string(&value, out)?;
+                out.push('"');
+            }
+            _ if value == "n/a" => out.push_str("null"),
+            // a Delta is signed, a JSON number has no '+'
+            _ => out.push_str(value.strip_prefix('+').unwrap_or(&value)),
+        }
+    }
+    out.push_str("}\n");
+    Ok(())
+}
+
+
+pub const CSV_HEADER: &str = "timestamp,pid,process_name,current_bytes,max_bytes\n";
+
+
+fn quote_csv_field(value: &str, out: &mut String) {
+    // RFC 4180: always quoted, a comm can hold commas and spaces; a quote is doubled
+    out.push('"');
+    for c in value.chars() {
+        if c == '"' {
+            out.push('"');
+        }
+        out.push(c);
+    }
+    out.push('"');
+}
+
+
+pub fn csv_header(columns: Option<&Columns>) -> String {
+    let Some(columns) = columns else {
+        return CSV_HEADER.to_string();
+    };
+    let mut header = String::new();
+    for (i, field) in columns.fields.iter().enumerate() {
+        if i > 0 {
+            header.push(',');
+        }
+        column_name(*field, &mut header);
+    }
+    header.push('\n');
+    header
+}
+
+
+pub fn render_csv(sample: &MemorySample, columns: Option<&Columns>, out: &mut String) -> std::fmt::Result {
+    if let Some(columns) = columns {
+        let mut value = String::new();
+        for (i, field) in columns.fields.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            value.clear();
+            render_field(*field, sample, columns.precision, &mut value)?;
+            match field.kind() {
+                FieldKind::String => quote_csv_field(&value, out),
+                _ => out.push_str(&value),
+            }
+        }
+        out.push('\n');
+        return Ok(());
+    }
+    // the columns of CSV_HEADER
+    write!(out, "{},{},", sample.timestamp, sample.pid)?;
+    quote_csv_field(sample.process_name, out);
+    writeln!(out, ",{},{}", sample.current_bytes, sample.max_bytes)
+}
+
+
+fn escape_prometheus_label(value: &str, out: &mut String) {
+    // see https://prometheus.io/docs/instrumenting/exposition_formats/#comments-help-text-and-type-information
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+}
+
+
+pub fn render_prometheus(sample: &MemorySample, out: &mut String) -> std::fmt::Result {
+    // every record is a whole exposition, the current value and, on the final one, the peak:
+    // memimpact_rss_bytes{pid="4242",comm="firefox"} 10485760
+    // no timestamp, the textfile collector refuses them
+    let mut labels = format!("{{pid=\"{}\",comm=\"", sample.pid);
+    escape_prometheus_label(sample.process_name, &mut labels);
+    labels.push_str("\"}");
+    let mut gauge = |name: &str, help: &str, bytes: u64| {
+        write!(out, "# HELP {0} {1}\n# TYPE {0} gauge\n{0}{2} {3}\n", name, help, labels, bytes)
+    };
+    gauge("memimpact_rss_bytes", "Resident memory of the process tree.", sample.current_bytes)?;
+    if sample.is_final {
+        gauge("memimpact_max_rss_bytes", "Peak resident memory of the process tree during the run.", sample.max_bytes)?;
+    }
+    Ok(())
+}
+
+
+pub fn render_influx(sample: &MemorySample, out: &mut String) -> std::fmt::Result {
+    // memimpact,pid=1234,name=firefox rss=1048576i,max=2097152i,... 1700000000000000000
+    write!(out, "memimpact,pid={},name=", sample.pid)?;
+    escape_influx_tag(sample.process_name, out);
+    write!(
+        out,
+        " rss={}i,max={}i,private={}i,processes={}i",
+        sample.current_bytes, sample.max_bytes, sample.private_bytes, sample.process_count,
+    )?;
+    if let Some(some) = sample.mem_pressure_some {
+        write!(out, ",mem_pressure_some={}", some)?;
+    }
+    if let Some(full) = sample.mem_pressure_full {
+        write!(out, ",mem_pressure_full={}", full)?;
+    }
+    writeln!(out, ",final={} {}", sample.is_final, sample.timestamp as u128 * 1_000_000_000)
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorded::{parse_flat_json_object, split_csv_line};
+
+    #[test]
+    fn test_format_kind_from_str() {
+        assert_eq!("text".parse::<FormatKind>(), Ok(FormatKind::Text));
+        assert_eq!("influx".parse::<FormatKind>(), Ok(FormatKind::Influx));
+        assert_eq!("json".parse::<FormatKind>(), Ok(FormatKind::Json));
+        assert_eq!("csv".parse::<FormatKind>(), Ok(FormatKind::Csv));
+        assert_eq!("prometheus".parse::<FormatKind>(), Ok(FormatKind::Prometheus));
+        assert!("xml".parse::<FormatKind>().is_err());
+    }
+
+    #[test]
+    fn test_render_influx() {
+        let sample = MemorySample{
+            pid: 1234,
+            process_name: "firefox",
+            current_bytes: 1048576,
+            max_bytes: 2097152,
+            private_bytes: 1024,
+            process_count: 3,
+            timestamp: 1_700_000_000,
+            ..Default::default()
+        };
+        let mut out = String::new();
+        render_influx(&sample, &mut out).unwrap();
+        assert_eq!(out, "memimpact,pid=1234,name=firefox rss=1048576i,max=2097152i,private=1024i,processes=3i,final=false 1700000000000000000\n");
+    }
+
+    #[test]
+    fn test_render_influx_with_pressure() {
+        let sample = MemorySample{
+            pid: 1,
+            process_name: "init",
+            mem_pressure_some: Some(1.5),
+            mem_pressure_full: Some(0.25),
+            is_final: true,
+            ..Default::default()
+        };
+        let mut out = String::new();
+        render_influx(&sample, &mut out).unwrap();
+        assert_eq!(out, "memimpact,pid=1,name=init rss=0i,max=0i,private=0i,processes=0i,mem_pressure_some=1.5,mem_pressure_full=0.25,final=true 0\n");
+    }
+
+    #[test]
+    fn test_render_json() {
+        let sample = MemorySample{
+            pid: 1234,
+            process_name: "firefox",
+            current_bytes: 1048576,
+            max_bytes: 2097152,
+            timestamp: 1_700_000_000,
+            ..Default::default()
+        };
+        let mut out = String::new();
+        render_json(&sample, None, &mut out).unwrap();
+        assert_eq!(out, "{\"pid\":1234,\"process_name\":\"firefox\",\"current_bytes\":1048576,\"max_bytes\":2097152,\"timestamp\":1700000000,\"final\":false}\n");
+        let fields = parse_flat_json_object(&out).unwrap();
+        assert_eq!(fields["pid"], "1234");
+        assert_eq!(fields["current_bytes"], "1048576");
+        assert_eq!(fields["max_bytes"], "2097152");
+        assert_eq!(fields["timestamp"], "1700000000");
+    }
+
+    #[test]
+    fn test_render_json_escaping() {
+        let sample = MemorySample{process_name: "(my \"weird\" proc)\\\n\u{1}", is_final: true, ..Default::default()};
+        let mut out = String::new();
+        render_json(&sample, None, &mut out).unwrap();
+        assert!(out.contains(r#""process_name":"(my \"weird\" proc)\\\n\u0001""#));
+        assert_eq!(out.lines().count(), 1);
+        let fields = parse_flat_json_object(&out).unwrap();
+        assert_eq!(fields["process_name"], "(my \"weird\" proc)\\\n\u{1}");
+        assert_eq!(fields["final"], "true");
+    }
+
+    #[test]
+    fn test_render_csv() {
+        let sample = MemorySample{
+            pid: 1234,
+            process_name: "firefox",
+            current_bytes: 1048576,
+            max_bytes: 2097152,
+            timestamp: 1_700_000_000,
+            ..Default::default()
+        };
+        let mut out = String::new();
+        render_csv(&sample, None, &mut out).unwrap();
+        assert_eq!(out, "1700000000,1234,\"firefox\",1048576,2097152\n");
+    }
+
+    #[test]
+    fn test_render_csv_quoting() {
+        let sample = MemorySample{pid: 7, process_name: "my \"weird\", proc", ..Default::default()};
+        let mut out = String::new();
+        render_csv(&sample, None, &mut out).unwrap();
+        assert_eq!(out, "0,7,\"my \"\"weird\"\", proc\",0,0\n");
+        // the reader of --replay gets the name back
+        let fields = split_csv_line(out.trim_end());
+        assert_eq!(fields, ["0", "7", "my \"weird\", proc", "0", "0"]);
+        assert_eq!(split_csv_line(CSV_HEADER.trim_end()).len(), fields.len());
+    }
+
+    fn columns(names: &[&str]) -> Columns {
+        Columns{fields: names.iter().map(|name| name.parse().unwrap()).collect(), precision: 0}
+    }
+
+    #[test]
+    fn test_render_csv_columns() {
+        let columns = columns(&["Timestamp", "ProcessName", "CurrentBytes", "Swap"]);
+        assert_eq!(csv_header(Some(&columns)), "timestamp,process_name,current_bytes,swap\n");
+        assert_eq!(csv_header(None), CSV_HEADER);
+        let sample = MemorySample{process_name: "my \"weird\", proc", current_bytes: 2048, timestamp: 7, ..Default::default()};
+        let mut out = String::new();
+        render_csv(&sample, Some(&columns), &mut out).unwrap();
+        assert_eq!(out, "7,\"my \"\"weird\"\", proc\",2048,n/a\n");
+        assert_eq!(split_csv_line(out.trim_end()), ["7", "my \"weird\", proc", "2048", "n/a"]);
+    }
+
+    #[test]
+    fn test_render_json_columns() {
+        let columns = columns(&["Pid", "ProcessName", "CurrentHuman", "Delta", "Swap", "Final"]);
+        let sample = MemorySample{pid: 7, process_name: "a\"b", current_bytes: 2048, previous_bytes: Some(0), is_final: true, ..Default::default()};
+        let mut out = String::new();
+        render_json(&sample, Some(&columns), &mut out).unwrap();
+        assert_eq!(out, "{\"pid\":7,\"process_name\":\"a\\\"b\",\"current_human\":\"2KiB\",\"delta\":2048,\"swap\":null,\"final\":true}\n");
+        let fields = parse_flat_json_object(&out).unwrap();
+        assert_eq!(fields["process_name"], "a\"b");
+        assert_eq!(fields["delta"], "2048");
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let sample = MemorySample{pid: 4242, process_name: "firefox", current_bytes: 10485760, max_bytes: 20971520, ..Default::default()};
+        let mut out = String::new();
+        render_prometheus(&sample, &mut out).unwrap();
+        assert_eq!(
+            out,
+            "# HELP memimpact_rss_bytes Resident memory of the process tree.\n\
+             # TYPE memimpact_rss_bytes gauge\n\
+             memimpact_rss_bytes{pid=\"4242\",comm=\"firefox\"} 10485760\n",
+        );
+
+        let sample = MemorySample{is_final: true, ..sample};
+        let mut out = String::new();
+        render_prometheus(&sample, &mut out).unwrap();
+        assert!(out.starts_with("# HELP memimpact_rss_bytes "));
+        assert!(out.contains("\n# TYPE memimpact_max_rss_bytes gauge\nmemimpact_max_rss_bytes{pid=\"4242\",comm=\"firefox\"} 20971520\n"));
+        assert_eq!(out.lines().filter(|line| !line.starts_with('#')).count(), 2);
+    }
+
+    #[test]
+    fn test_prometheus_label_escaping() {
+        let sample = MemorySample{pid: 7, process_name: "my \"weird\" proc\\\n", ..Default::default()};
+        let mut out = String::new();
+        render_prometheus(&sample, &mut out).unwrap();
+        assert!(out.ends_with("\nmemimpact_rss_bytes{pid=\"7\",comm=\"my \\\"weird\\\" proc\\\\\\n\"} 0\n"));
+        assert_eq!(out.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_influx_tag_escaping() {
+        let mut out = String::new();
+        escape_influx_tag("(my proc,a=b)", &mut out);
+        assert_eq!(out, "(my\\ proc\\,a\\=b)");
+    }
+}
@@ -0,0 +1,333 @@
+use std::io::{self, Write};
+
+
+// Gzip encoder for --gzip, small enough to not pull a compression crate.
+// Deflate with the fixed Huffman codes and a greedy LZ77 over a 32KiB window:
+// far from gzip -9, but memimpact records are repetitive enough for a good ratio.
+// see https://www.rfc-editor.org/rfc/rfc1951 and https://www.rfc-editor.org/rfc/rfc1952
+
+const BLOCK_SIZE: usize = 64 * 1024;   // input compressed at once, a crash loses at most this much
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;           // candidates tried per position, bounds the time spent on a block
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &byte in data {
+        c = CRC_TABLE[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter{bytes: Vec::new(), accumulator: 0, count: 0}
+    }
+
+    fn write_bits(&mut self, value: u32, bits: u32) {
+        // deflate packs values starting from the least significant bit
+        self.accumulator |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.accumulator as u8);
+            self.accumulator >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn write_code(&mut self, code: u32, bits: u32) {
+        // Huffman codes are the exception, packed starting from the most significant bit
+        self.write_bits(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write_bits(0, 8 - self.count);
+        }
+    }
+}
+
+
+fn write_literal(bits: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => bits.write_code(0x30 + symbol, 8),
+        144..=255 => bits.write_code(0x190 + symbol - 144, 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap_or(0);
+    write_literal(bits, 257 + code as u16);
+    bits.write_bits((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+    let code = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap_or(0);
+    bits.write_code(code as u32, 5);
+    bits.write_bits((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+}
+
+fn hash(data: &[u8]) -> usize {
+    let key = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (key.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn compress_block(bits: &mut BitWriter, data: &[u8], start: usize, is_final: bool) {
+    // data[..start] is the window left by the previous blocks, only data[start..] is written
+    bits.write_bits(is_final as u32, 1);
+    bits.write_bits(1, 2);   // fixed Huffman codes
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; data.len()];
+    let insert = |position: usize, head: &mut [usize], previous: &mut [usize]| {
+        if position + MIN_MATCH <= data.len() {
+            let h = hash(&data[position..]);
+            previous[position] = head[h];
+            head[h] = position;
+        }
+    };
+    for position in 0..start {
+        insert(position, &mut head, &mut previous);
+    }
+    let mut position = start;
+    while position < data.len() {
+        let mut best = (0, 0);
+        if position + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - position);
+            let mut candidate = head[hash(&data[position..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && position - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..].iter().zip(&data[position..position + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, position - candidate);
+                    if length == max_length {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+        let (length, distance) = best;
+        if length >= MIN_MATCH {
+            write_match(bits, length, distance);
+            for p in position..position + length {
+                insert(p, &mut head, &mut previous);
+            }
+            position += length;
+        } else {
+            write_literal(bits, data[position] as u16);
+            insert(position, &mut head, &mut previous);
+            position += 1;
+        }
+    }
+    write_literal(bits, 256);   // end of block
+}
+
+
+pub struct GzipWriter<W: Write> {
+    inner: W,
+    window: Vec<u8>,    // the last WINDOW_SIZE bytes already compressed, then the pending input
+    pending: usize,     // length of the pending input at the end of window
+    bits: BitWriter,
+    crc: u32,
+    size: u32,          // input length modulo 2^32, as the trailer wants it
+    finished: bool,
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        // no name, no modification time, unix
+        inner.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3])?;
+        Ok(GzipWriter{
+            inner,
+            window: Vec::new(),
+            pending: 0,
+            bits: BitWriter::new(),
+            crc: 0,
+            size: 0,
+            finished: false,
+        })
+    }
+
+    fn compress_pending(&mut self, is_final: bool) -> io::Result<()> {
+        let start = self.window.len() - self.pending;
+        compress_block(&mut self.bits, &self.window, start, is_final);
+        self.pending = 0;
+        if self.window.len() > WINDOW_SIZE {
+            self.window.drain(..self.window.len() - WINDOW_SIZE);
+        }
+        self.inner.write_all(&self.bits.bytes)?;
+        self.bits.bytes.clear();
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        // without the final block and the trailer, gzip -d reports a truncated file
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.compress_pending(true)?;
+        self.bits.align();
+        self.bits.bytes.extend_from_slice(&self.crc.to_le_bytes());
+        self.bits.bytes.extend_from_slice(&self.size.to_le_bytes());
+        self.inner.write_all(&self.bits.bytes)?;
+        self.bits.bytes.clear();
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("write after the end of the gzip stream"));
+        }
+        let taken = buf.len().min(BLOCK_SIZE - self.pending);
+        let buf = &buf[..taken];
+        self.window.extend_from_slice(buf);
+        self.pending += taken;
+        self.crc = crc32_update(self.crc, buf);
+        self.size = self.size.wrapping_add(taken as u32);
+        if self.pending == BLOCK_SIZE {
+            self.compress_pending(false)?;
+        }
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // the pending input is kept, compressing every record on its own would waste the ratio
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for GzipWriter<W> {
+    fn drop(&mut self) {
+        // like BufWriter, errors on drop are ignored, call finish to see them
+        let _ = self.finish();
+    }
+}
+
+impl<W: Write + std::fmt::Debug> std::fmt::Debug for GzipWriter<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GzipWriter").field("inner", &self.inner).field("pending", &self.pending).finish()
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    fn gunzip(compressed: &[u8]) -> Vec<u8> {
+        let mut child = Command::new("gzip").arg("-dc")
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+            .spawn().unwrap();
+        child.stdin.take().unwrap().write_all(compressed).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        output.stdout
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let mut writer = GzipWriter::new(&mut compressed).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        compressed
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF43926);
+        assert_eq!(crc32_update(crc32_update(0, b"1234"), b"56789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_gzip_empty() {
+        assert_eq!(gunzip(&gzip(b"")), b"");
+    }
+
+    #[test]
+    fn test_gzip_records_round_trip() {
+        let mut data = String::new();
+        for i in 0..20_000u64 {
+            data.push_str(&format!("{},1234,\"(bash)\",{},{}\n", 1700000000 + i, 4096 + i % 7, 8192));
+        }
+        let compressed = gzip(data.as_bytes());
+        assert!(compressed.len() < data.len() / 4);
+        assert_eq!(gunzip(&compressed), data.as_bytes());
+    }
+
+    #[test]
+    fn test_gzip_binary_round_trip() {
+        // every byte value, long runs and matches across blocks
+        let mut data: Vec<u8> = (0..=255u8).cycle().take(BLOCK_SIZE + 1000).collect();
+        data.extend(std::iter::repeat_n(7u8, 3 * MAX_MATCH));
+        let mut seed = 12345u32;
+        data.extend((0..BLOCK_SIZE).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }));
+        assert_eq!(gunzip(&gzip(&data)), data);
+    }
+
+    #[test]
+    fn test_gzip_finished_on_drop() {
+        let mut compressed = Vec::new();
+        {
+            let mut writer = GzipWriter::new(&mut compressed).unwrap();
+            writer.write_all(b"memimpact\n").unwrap();
+        }
+        assert_eq!(gunzip(&compressed), b"memimpact\n");
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+use crate::template_engine::format_memory;
+
+
+// --histogram: every sample is counted in the bucket of its memory,
+// the distribution is printed after the final line.
+const BAR_WIDTH: u64 = 40;
+
+
+#[derive(Debug)]
+pub struct Histogram {
+    bucket_size: u64,
+    counts: BTreeMap<u64, u64>,   // bucket index to number of samples, empty buckets are absent
+}
+
+impl Histogram {
+    pub fn new(bucket_size: u64) -> Self {
+        Histogram{bucket_size: bucket_size.max(1), counts: BTreeMap::new()}
+    }
+
+    pub fn record(&mut self, memory: u64) {
+        *self.counts.entry(memory / self.bucket_size).or_insert(0) += 1;
+    }
+
+    pub fn format(&self, precision: usize) -> String {
+        // buckets are listed from the lowest to the highest recorded one, empty ones in between
+        // included so that a bimodal run shows its gap
+        let mut out = String::from("memory histogram:\n");
+        let (Some(&first), Some(&last)) = (self.counts.keys().next(), self.counts.keys().next_back()) else {
+            return out;
+        };
+        let biggest = self.counts.values().copied().max().unwrap_or(1);
+        let labels: Vec<String> = (first..=last)
+            .map(|bucket| format!(
+                "{} - {}",
+                format_memory(bucket.saturating_mul(self.bucket_size), precision),
+                format_memory(bucket.saturating_add(1).saturating_mul(self.bucket_size), precision),
+            ))
+            .collect();
+        let label_width = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+        for (bucket, label) in (first..=last).zip(labels) {
+            let count = self.counts.get(&bucket).copied().unwrap_or(0);
+            let bar = "#".repeat((count * BAR_WIDTH).div_ceil(biggest) as usize);
+            out.push_str(&format!("  {:<width$}  {} {}\n", label, bar, count, width = label_width));
+        }
+        out
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = Histogram::new(100);
+        for memory in [0, 99, 100, 250, 299] {
+            histogram.record(memory);
+        }
+        assert_eq!(histogram.counts, BTreeMap::from([(0, 2), (1, 1), (2, 2)]));
+    }
+
+    #[test]
+    fn test_histogram_format() {
+        let mut histogram = Histogram::new(1024 * 1024);
+        for memory in [1024, 1500, 1800, 1900, 3100].map(|kib| kib * 1024) {
+            histogram.record(memory);
+        }
+        assert_eq!(
+            histogram.format(0),
+            "memory histogram:\n\
+             \x20 1MiB - 2MiB  ######################################## 4\n\
+             \x20 2MiB - 3MiB   0\n\
+             \x20 3MiB - 4MiB  ########## 1\n"
+        );
+    }
+
+    #[test]
+    fn test_histogram_format_empty() {
+        assert_eq!(Histogram::new(1024).format(0), "memory histogram:\n");
+    }
+}
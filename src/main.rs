@@ -3,462 +3,1655 @@
 //#![warn(clippy::unwrap_used)]
 //#![warn(clippy::expect_used)]
 
-use std::collections::{HashMap, HashSet};
 use std::{env, fs, process};
-use std::io::{self, Write};
-use std::path::PathBuf;
-use std::time::{Duration, SystemTime};
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 use std::thread;
 
+mod oom;
+mod output;
+mod breakdown;
+mod children;
+mod color;
+mod config;
+mod error;
+mod formats;
+mod gzip;
+mod histogram;
+mod proc;
+mod pushgateway;
+mod profile;
+mod recorded;
+mod regex;
+mod sampling;
+mod sparkline;
+mod sqlite;
+mod stats;
+mod sys;
 mod template;
+mod threshold;
+mod watchdog;
 pub use crate::template::template_engine;
-
-
-fn list_processes() -> Vec<i32> {
-    let mut pids = Vec::new();
-
-    if let Ok(entries) = fs::read_dir("/proc") {
-        for entry in entries.flatten() {              // ignore invalid directory entries
-            if let Ok(metadata) = entry.metadata() && metadata.is_dir() {  // ignore metadata errors
-               if let Some(name) = entry.file_name().to_str()
-                    && let Ok(pid) = name.parse::<i32>() {
-                        pids.push(pid);
-                    }
-            }
-        }
-    }
-    pids
-}
-
-#[derive(Debug, PartialEq)]
-enum ProcessState{
-	R,      //Running
-    S,      //Sleeping in an interruptible wait
-    D,      //Waiting in uninterruptible disk sleep
-    Z,      //Zombie
-    T,      //Stopped (on a signal) or (before Linux2.6.33) trace stopped or Tracing stop (Linux 2.6.33 onward)
-    W,      //Paging (only before Linux 2.6.0) or Waking (Linux 2.6.33 to 3.13 only)
-    X,      //Dead (from Linux 2.6.0 onward)
-    K,      //Wakekill (Linux 2.6.33 to 3.13 only)
-    P,      //Parked (Linux 3.9 to 3.13 only)
-    I,      //Idle (Linux 4.14 onward)
-}
-
-
-impl TryFrom<&str> for ProcessState {
-    type Error = ProcStatError;
-
-    fn try_from(s: &str) -> Result<Self, Self::Error> {
-        match s.chars().next().ok_or('_') {
-            Ok('R') => Ok(ProcessState::R),
-            Ok('S') => Ok(ProcessState::S),
-            Ok('D') => Ok(ProcessState::D),
-            Ok('Z') => Ok(ProcessState::Z),
-            Ok('T') => Ok(ProcessState::T),
-            Ok('W') => Ok(ProcessState::W),
-            Ok('X') => Ok(ProcessState::X),
-            Ok('K') => Ok(ProcessState::K),
-            Ok('P') => Ok(ProcessState::P),
-            Ok('I') => Ok(ProcessState::I),
-            _ => Err(ProcStatError::UnsupportedKernelLayout),
-        }
-    }
+use crate::proc::{
+    build_children_index, descendants_of_init, find_descendants, get_map_pid_to_ppid, get_process_name, is_kernel_thread, list_processes,
+    exclude_subtree, is_pid_reused, is_stopped, read_cmdline, read_memory_pressure, read_hugetlb, read_mem_total, read_pss, read_rss_breakdown, read_start_time, read_statm, read_swap, read_uss, saturating_total, targets_alive, ProcReader,
+    parse_namespace_id, read_cgroup_procs, translate_namespace_pid, Metric, RssBreakdown, Statm,
+};
+use crate::breakdown::LargestProcess;
+use crate::children::ChildTracker;
+use crate::error::{ExitCode, MemimpactError, ParseArgError};
+use crate::color::{no_color_env, should_colorize, ColorChoice};
+use crate::formats::{Columns, FormatKind};
+use crate::oom::OomWatch;
+use crate::output::{finish_sinks, MAX_BUFFER_SIZE, setup_sinks, write_output, Emitter, Output, OutputSpec, RecordFormat, Sink, Verbosity};
+use crate::profile::SelfProfile;
+use crate::pushgateway::{is_valid_job, render_metrics, Pushgateway};
+use crate::regex::Regex;
+use crate::threshold::{baseline_limit, breached, parse_memory_arg, reap_hooks, spawn_hook, terminate, ThresholdWatch};
+use crate::sampling::{aligned_sleep, ChangeFilter, LagDetector, next_adaptive_interval, Coalescer, ReportClock};
+use crate::sparkline::Sparkline;
+use crate::sqlite::SqliteSink;
+use crate::stats::RunStats;
+use crate::watchdog::Watchdog;
+
+
+fn choose_waited_pid(processes: &[(i32, String)], name: &str) -> Option<(i32, usize)>{
+	// --wait-for: the lowest pid named name, likely the parent of the others, and how many are
+	let matching: Vec<i32> = processes.iter()
+		.filter(|(_, process_name)| process_name == name)
+		.map(|(pid, _)| *pid)
+		.collect();
+	matching.iter().min().map(|pid| (*pid, matching.len()))
 }
 
 
-
-#[derive(Debug, PartialEq)]
-#[allow(dead_code)]
-struct ProcStat<'a>{
-    pid: i32,
-    comm: &'a str,
-    state: ProcessState,
-    ppid: i32,
+fn wait_for_process(name: &str, timeout: Option<u64>, poll_ms: u64) -> Result<i32, MemimpactError>{
+	let started = Instant::now();
+	let self_pid = process::id() as i32;
+	eprintln!("memimpact: waiting for a process named {:?}", name);
+	loop {
+		let processes: Vec<(i32, String)> = list_processes().into_iter()
+			.filter(|pid| *pid != self_pid)
+			.filter_map(|pid| get_process_name(&pid).ok().map(|process_name| (pid, process_name)))
+			.collect();
+		if let Some((pid, count)) = choose_waited_pid(&processes, name){
+			if count > 1 {
+				eprintln!("memimpact: {} processes named {:?}, monitoring the lowest pid, {}", count, name, pid);
+			}
+			return Ok(pid);
+		}
+		if let Some(seconds) = timeout
+			&& started.elapsed() >= Duration::from_secs(seconds){
+				return Err(MemimpactError::WaitTimeout(name.to_string(), seconds));
+			}
+		thread::sleep(Duration::from_millis(poll_ms));
+	}
 }
 
 
-#[derive(Debug)]
-enum ProcStatError {
-    InvalidFormat,
-    UnsupportedKernelLayout,
+fn get_pids_from_name(name: String) -> Vec<i32>{
+	let mut result_pids: Vec<i32> = Vec::new();
+	let all_pids = list_processes();
+	for pid in all_pids{
+		if let Ok(x) = get_process_name(&pid)
+			&& x == name{
+				result_pids.push(pid);
+			}  
+	}
+	result_pids
 }
 
 
-fn parse_proc_stat(content: &str) -> Result<ProcStat<'_>, ProcStatError> {
-	// because the 2nd colum is the process name and can contain whitespaces
-	// see https://man7.org/linux/man-pages/man5/proc_pid_stat.5.html
-    let mut res = Vec::new();
-
-    let open = content.find('(').ok_or(ProcStatError::InvalidFormat)?;
-    let close = content[open + 1..]
-        .rfind(')')
-        .map(|i| open + 1 + i)
-        .ok_or(ProcStatError::InvalidFormat)?;
-
-    // pid
-    if open < 2 {
-        return Err(ProcStatError::InvalidFormat);
-    }
-    res.push(&content[..open - 1]);
-	let pid: i32 = match content[..open - 1].parse(){
-		Ok(i) => i,
-		Err(_) => return Err(ProcStatError::InvalidFormat)
-	};
-
-	// comm
-    let comm = &content[open..=close];
-
-	// state
-    let after_comm = close + 2;
-    let state = match ProcessState::try_from(&content[after_comm..after_comm + 1]){
-    	Ok(s) => s,
-    	Err(_) => return Err(ProcStatError::UnsupportedKernelLayout)
-    };
-
-    // ppid
-    let next_space = content[after_comm + 2..].find(' ').ok_or(ProcStatError::InvalidFormat)?;
-	let ppid: i32 = match content[after_comm + 2..after_comm + 2 + next_space].parse(){
-		Ok(i) => i,
-		Err(_) => return Err(ProcStatError::InvalidFormat)
-	};
-    
-    Ok(ProcStat{pid, comm, state, ppid})
-}
-
-fn get_process_name(pid: &i32) -> Result<String, String> {
-    let path = format!("/proc/{}/stat", pid);
-    let contents = fs::read_to_string(&path)
-   	        .map_err(|_| format!("Could not read {}", path))?;
-    let proc_stat = parse_proc_stat(&contents).map_err(|e| {
-        format!(
-            "Unsupported /proc/{}/stat format ({:?}). \
-             Either the process name is or your system is currently not supported. \
-             Please open an issue with the complete /proc/pid/stat content and your kernel version.",
-            pid, e
-        )
-    })?;
 
-    Ok(proc_stat.comm.to_string())
+fn get_pids_from_search(search: &NameSearch, self_pid: i32) -> Vec<i32>{
+	// --search: scanned again at every sample, the matches come and go during the run
+	let mut result_pids: Vec<i32> = list_processes().into_iter()
+		.filter(|pid| *pid != self_pid && get_process_name(pid).is_ok_and(|name| search.matches(&name)))
+		.collect();
+	result_pids.sort();
+	result_pids
 }
 
 
-fn get_map_pid_to_ppid() -> HashMap<i32, i32> {
-    // list directories insde /proc and foreach read its stat
-    // returns a map of i32 -> i32, each representing a pid to its ppid 
-    let mut map = HashMap::<i32, i32>::new();
-    for pid in list_processes(){
-    	let path = format!("/proc/{}/stat", pid);
-    	let contents = match fs::read_to_string(path){
-    		Ok(c) => {c},
-    		Err(_) => {continue} // probably the process exited	
-    	};
-    	let proc_stat = match parse_proc_stat(&contents) {
-	        Ok(p) => p,
-	        Err(_) => continue, // unsupported or malformed stat for this PID
-	    };
-   	    map.insert(proc_stat.pid, proc_stat.ppid);
-    }
-    map
+fn get_pids_from_cmdline(pattern: &Regex) -> Vec<i32>{
+	// memimpact's own command line contains the pattern, never select ourself
+	let self_pid = process::id() as i32;
+	let mut result_pids: Vec<i32> = Vec::new();
+	for pid in list_processes(){
+		if pid != self_pid
+			&& let Some(cmdline) = read_cmdline(&pid)
+			&& pattern.is_match(&cmdline){
+				result_pids.push(pid);
+			}
+	}
+	result_pids
 }
 
 
 #[derive(Debug)]
-enum ProcStatmError {
-    InvalidFormat,
+struct Args{
+	help_flag: bool,
+	version_flag: bool,
+	list_fields_flag: bool,
+	final_flag: bool,
+	no_final_flag: bool,
+	summary_only_flag: bool,
+	hz: u64,
+	grace: u32,
+	page_size_kib: Option<u64>,   // detected at startup unless given
+	output: OutputSpec,
+	target_pids: Vec<i32>,
+	template_string: Option<String>,
+	template_file: Option<PathBuf>,
+	psi_flag: bool,
+	no_kernel_threads_flag: bool,
+	search: Option<NameSearch>,
+	search_tree_flag: bool,
+	wait_for: Option<String>,   // a process name, resolved once it starts
+	wait_timeout: Option<u64>,  // in seconds
+	self_profile_flag: bool,
+	adaptive_flag: bool,
+	null_flag: bool,
+	max_threshold: Option<u64>,   // in bytes, like the sampled values
+	baseline: Option<u64>,        // in bytes, max_threshold is then computed from it
+	tolerance: Option<f64>,       // in percent of the baseline
+	threshold: Option<u64>,       // in bytes, the run fails above it
+	on_threshold: Option<String>,
+	on_threshold_repeat_flag: bool,
+	kill_on_threshold_flag: bool,
+	kill_tree_flag: bool,
+	kill_grace: u64,   // seconds between SIGTERM and SIGKILL
+	diff_files: Option<(PathBuf, PathBuf)>,
+	replay_file: Option<PathBuf>,
+	sqlite_path: Option<PathBuf>,
+	group_by_name_flag: bool,
+	format: FormatKind,
+	metric: Metric,
+	report_every: Option<u64>,
+	on_change_flag: bool,
+	change_threshold: u64,   // in bytes
+	histogram_bucket: Option<u64>,
+	per_child_flag: bool,
+	per_pid_stream_flag: bool,
+	all_flag: bool,   // confirms monitoring pid 1
+	precision: usize,
+	color: ColorChoice,
+	coalesce: Option<u64>,
+	time_weighted_avg_flag: bool,
+	template_stderr: Option<String>,
+	end_marker: Option<String>,   // a template, rendered with the last sample
+	fields: Option<Vec<template_engine::Field>>,
+	pid_namespace: Option<PidNamespace>,   // the pid is the one seen inside a container
+	no_follow_flag: bool,
+	skip_stopped_flag: bool,
+	gzip_flag: bool,
+	buffer_size: Option<usize>,
+	pushgateway_url: Option<String>,
+	job: Option<String>,
+	pushgateway: Option<Pushgateway>,   // built from the two above once every flag is read
+	push_every: Option<u64>,            // in seconds
+	include_self_flag: bool,
+	epoch_offset: Option<u64>,
+	relative_to_start_flag: bool,
+	fail_if_absent_flag: bool,
+	watchdog: Option<u64>,
+	keep_parens_flag: bool,
+	largest_process_flag: bool,
+	top: Option<usize>,
+	snapshot_file: Option<PathBuf>,
+	snapshot_breakdown_flag: bool,
+	log_warmup_flag: bool,
+	align_to_clock_flag: bool,
+	map_count_flag: bool,
+	hugepages_flag: bool,
+	swap_flag: bool,
+	folded_file: Option<PathBuf>,
+	summary_json_flag: bool,
+	summary_json_file: Option<PathBuf>,   // stdout when None
+	measure_flag: bool,
+	command: Vec<String>,   // after --, run and measured, --measure being implied
 }
 
 
-fn parse_statm(content: String) -> Result<u64, ProcStatmError> {
-	let first_space = match content.find(' ').ok_or(ProcStatmError::InvalidFormat){
-		Ok(i) => i,
-		Err(_) => return Err(ProcStatmError::InvalidFormat)
-	};
-	let next_space = match content[first_space + 1..].find(' ').ok_or(ProcStatmError::InvalidFormat){
-		Ok(i) => i,
-		Err(_) => return Err(ProcStatmError::InvalidFormat)
-	};
-    let rss_pages: u64 = match content[first_space + 1..first_space + 1 + next_space].parse::<u64>() {
-        Ok(n) => n,
-        Err(_) => return Err(ProcStatmError::InvalidFormat),
-    };
-
-    Ok(rss_pages)
+// --search and --search-regex, against the command name without its parentheses
+#[derive(Debug)]
+enum NameSearch {
+    Substring(String),   // in any case
+    Pattern(String, Regex),   // the pattern as given, compiled
 }
 
-
-fn read_rss_kb(pid: &i32, page_size_kib: &u64) -> u64{
-    // see https://man7.org/linux/man-pages/man5/proc_pid_statm.5.html
-    let path = format!("/proc/{}/statm", pid);
-    /*
-    TODO
-    Trick 2: Use std::fs::read instead of read_to_string
-    read_to_string incurs UTF-8 validation — wasteful since /proc is ASCII.
-    */
-    let contents = match fs::read_to_string(path) {
-        Ok(c) => c,
-        Err(_) => return 0,
-    };
-    parse_statm(contents).unwrap_or(0) * page_size_kib
-}	
-
-
-fn find_descendants(
-    parent_of: &HashMap<i32, i32>,
-    target_pids: &Vec<i32>,
-) -> HashSet<i32> {
-	// Given a mapping of pid -> ppid and a target pid,
-	// return all descendants of the targets (including the targets themself)
-    let mut descendants = HashSet::new();
-    for pid in target_pids{
-    	descendants.insert(*pid);
-    }
-    let mut found_new: bool;
-    loop {
-    	found_new = false;
-        for (&pid, &ppid) in parent_of.iter() {
-        	// if the parent process is among descendants and we don't already know the current pid
-            if descendants.contains(&ppid) && !descendants.contains(&pid) {
-                descendants.insert(pid);
-                found_new = true;
-            }
-        }
-        if !found_new {
-            break;
+impl NameSearch {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NameSearch::Substring(text) => name.to_lowercase().contains(&text.to_lowercase()),
+            NameSearch::Pattern(_, regex) => regex.is_match(name),
         }
     }
-    descendants
-}
-
-
 
-
-#[derive(Debug)]
-enum OutputSpec {
-    Stdout,
-    File(PathBuf),
-}
-
-#[derive(Debug)]
-enum Output {
-    File(fs::File),
-    Stdout(io::Stdout),
-}
-
-impl Write for Output {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    fn text(&self) -> &str {
         match self {
-            Output::File(f) => f.write(buf),
-            Output::Stdout(s) => s.write(buf),
+            NameSearch::Substring(text) | NameSearch::Pattern(text, _) => text,
         }
     }
 
-    fn flush(&mut self) -> io::Result<()> {
+    fn describe(&self) -> String {
         match self {
-            Output::File(f) => f.flush(),
-            Output::Stdout(s) => s.flush(),
+            NameSearch::Substring(text) => format!("contains {:?}", text),
+            NameSearch::Pattern(pattern, _) => format!("matches {:?}", pattern),
         }
     }
 }
 
 
-fn write_output<W: Write>(out: &mut W, text: &str){
-    match out.write_all(text.as_bytes()){
-		Ok(_) => (),
-		Err(e) => {eprintln!("Could not write output because {}", e);}
-    };
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PidNamespace {
+    Auto,
+    Id(u64),
 }
 
+impl std::str::FromStr for PidNamespace {
+    type Err = String;
 
-fn setup_output(spec: OutputSpec) -> io::Result<Output> {
-    match spec {
-        OutputSpec::Stdout => Ok(Output::Stdout(io::stdout())),
-        OutputSpec::File(path) => {
-            let file = fs::File::create(path)?;
-            Ok(Output::File(file))
+    fn from_str(input: &str) -> Result<PidNamespace, Self::Err> {
+        // auto, the inode of the namespace, or the target of /proc/[pid]/ns/pid
+        if input == "auto" {
+            return Ok(PidNamespace::Auto);
         }
+        input.parse().ok()
+            .or_else(|| parse_namespace_id(input))
+            .map(PidNamespace::Id)
+            .ok_or(format!("invalid pid namespace {:?}", input))
     }
 }
 
-
-fn get_pids_from_name(name: String) -> Vec<i32>{
-	let mut result_pids: Vec<i32> = Vec::new();
-	let all_pids = list_processes();
-	for pid in all_pids{
-		if let Ok(x) = get_process_name(&pid)
-			&& x == name{
-				result_pids.push(pid);
-			}  
-	}
-	result_pids
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            help_flag: false,
+            version_flag: false,
+            list_fields_flag: false,
+            final_flag: false,
+            no_final_flag: false,
+            summary_only_flag: false,
+            hz: 1,
+            grace: 1,
+            page_size_kib: None,
+            output: OutputSpec::Stdout,
+            target_pids: Vec::new(),
+            template_string: None,
+            template_file: None,
+            psi_flag: false,
+            no_kernel_threads_flag: false,
+            search: None,
+            search_tree_flag: false,
+            wait_for: None,
+            wait_timeout: None,
+            self_profile_flag: false,
+            adaptive_flag: false,
+            null_flag: false,
+            max_threshold: None,
+            baseline: None,
+            threshold: None,
+            tolerance: None,
+            on_threshold: None,
+            on_threshold_repeat_flag: false,
+            kill_on_threshold_flag: false,
+            kill_tree_flag: false,
+            kill_grace: 5,
+            diff_files: None,
+            replay_file: None,
+            sqlite_path: None,
+            group_by_name_flag: false,
+            format: FormatKind::Text,
+            metric: Metric::Rss,
+            report_every: None,
+            on_change_flag: false,
+            change_threshold: 0,
+            histogram_bucket: None,
+            per_child_flag: false,
+            per_pid_stream_flag: false,
+            all_flag: false,
+            precision: 0,
+            color: ColorChoice::Auto,
+            coalesce: None,
+            time_weighted_avg_flag: false,
+            template_stderr: None,
+            end_marker: None,
+            fields: None,
+            pid_namespace: None,
+            no_follow_flag: false,
+            skip_stopped_flag: false,
+            gzip_flag: false,
+            buffer_size: None,
+            pushgateway_url: None,
+            job: None,
+            pushgateway: None,
+            push_every: None,
+            include_self_flag: false,
+            epoch_offset: None,
+            relative_to_start_flag: false,
+            fail_if_absent_flag: false,
+            watchdog: None,
+            keep_parens_flag: false,
+            largest_process_flag: false,
+            top: None,
+            snapshot_file: None,
+            snapshot_breakdown_flag: false,
+            log_warmup_flag: false,
+            align_to_clock_flag: false,
+            map_count_flag: false,
+            hugepages_flag: false,
+            swap_flag: false,
+            folded_file: None,
+            summary_json_flag: false,
+            summary_json_file: None,
+            measure_flag: false,
+            command: Vec::new(),
+        }
+    }
 }
 
 
-
-#[derive(Debug)]
-#[allow(dead_code)]
-enum ParseArgError {
-    MissingValue(&'static str),
-    InvalidValue(&'static str),
-}
-
-#[derive(Debug)]
-struct Args{
-	help_flag: bool,
-	version_flag: bool,
-	final_flag: bool,
-	hz: u64,
-	page_size_kib: u64,
-	output: OutputSpec,
-	target_pids: Vec<i32>,
-	template_string: String,
+fn output_flag(spec: &OutputSpec) -> Option<&'static str> {
+    // the flag that selected a non default output, for conflict errors
+    match spec {
+        OutputSpec::Stdout => None,
+        OutputSpec::File(_) | OutputSpec::NoFollowFile(_) | OutputSpec::Buffered(..) => Some("output-file"),
+        OutputSpec::Socket(_) => Some("socket"),
+        OutputSpec::Tcp(_) => Some("tcp"),
+        OutputSpec::Gzip(inner) => output_flag(inner),
+    }
 }
 
 
 fn parse_args(args: &[String]) -> Result<Args, ParseArgError> {
-    let mut help_flag = false;
-	let mut version_flag = false;
-    let mut final_flag = false;
-    let mut hz = 1;
-    let mut page_size_kib = 4;  // 4096 bytes = 4 KB, True for most Linux, but the user probably knows its system better
-    let mut output = OutputSpec::Stdout;
-    let mut pid = None;
+    let mut parsed = Args::default();
+    let mut pids = Vec::new();
     let mut name = None;
-    let mut target_pids: Vec<i32> = Vec::new();
-    let mut template_string: String = "PID {Pid} {ProcessName}: current {CurrentHuman}, max {MaxHuman}\n".to_string();
+    let mut cmdline_pattern = None;
+    let mut emit_end_marker_flag = false;
+    let mut cgroup = None;
+    let mut search_flags = Vec::new();
 
     let mut iter = args.iter().skip(1).peekable(); // skip program name
 
     while let Some(arg) = iter.next() {
         match arg.as_str() {
             "--help" | "-h" => {
-            	help_flag = true;
-            	return Ok(Args {
-            	        help_flag,
-            	        version_flag,
-            	        final_flag,
-            	        hz,
-            	        page_size_kib,
-            	        output,
-            	        target_pids,
-            	        template_string,
-            	    });
+            	parsed.help_flag = true;
+            	return Ok(parsed);
+            }
+            "--list-fields" => {
+                parsed.list_fields_flag = true;
+                return Ok(parsed);
             }
             "--version" | "-v" => {
-             	version_flag = true;
-             	return Ok(Args {
-             	        help_flag,
-             	        version_flag,
-             	        final_flag,
-             	        hz,
-             	        page_size_kib,
-             	        output,
-             	        target_pids,
-             	        template_string,
-             	    });
+             	parsed.version_flag = true;
+             	return Ok(parsed);
              }
-            "--final" => final_flag = true,
+            "--diff" => {
+                let baseline = iter.next().ok_or(ParseArgError::MissingValue("diff"))?;
+                let candidate = iter.next().ok_or(ParseArgError::MissingValue("diff"))?;
+                parsed.diff_files = Some((PathBuf::from(baseline), PathBuf::from(candidate)));
+                return Ok(parsed);
+            }
+            "--replay" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("replay"))?;
+                parsed.replay_file = Some(PathBuf::from(value));
+            }
+            "--final" => parsed.final_flag = true,
+            "--no-final" => parsed.no_final_flag = true,
+            "--group-by-name" => parsed.group_by_name_flag = true,
+            "--per-child" => parsed.per_child_flag = true,
+            "--per-pid-stream" => parsed.per_pid_stream_flag = true,
+            "--all" => parsed.all_flag = true,
+            "--time-weighted-avg" => parsed.time_weighted_avg_flag = true,
+            "--skip-stopped" => parsed.skip_stopped_flag = true,
+            "--histogram" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("histogram"))?;
+                let bytes = parse_memory_arg(value).map_err(|_| ParseArgError::InvalidValue("histogram"))?;
+                if bytes < 1024 {
+                    return Err(ParseArgError::InvalidValue("histogram"));
+                }
+                parsed.histogram_bucket = Some(bytes);
+            }
+            "--summary-only" => parsed.summary_only_flag = true,
+            "--psi" => parsed.psi_flag = true,
+            "--no-kernel-threads" => parsed.no_kernel_threads_flag = true,
+            "--include-self" => parsed.include_self_flag = true,
+            "--self-profile" => parsed.self_profile_flag = true,
+            "--adaptive" => parsed.adaptive_flag = true,
+            "--align-to-clock" => parsed.align_to_clock_flag = true,
+            "--map-count" => parsed.map_count_flag = true,
+            "--hugepages" => parsed.hugepages_flag = true,
+            "--swap" => parsed.swap_flag = true,
+            "--null" | "-0" => parsed.null_flag = true,
             "--hertz" => {
                 let value = iter.next().ok_or(ParseArgError::MissingValue("hertz"))?;
-                hz = value.parse().map_err(|_| ParseArgError::InvalidValue("hertz"))?;
-                if hz == 0 {
+                parsed.hz = value.parse().map_err(|_| ParseArgError::InvalidValue("hertz"))?;
+                if parsed.hz == 0 {
                     return Err(ParseArgError::InvalidValue("hertz"));
                 }
             }
+            "--report-every" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("report-every"))?;
+                let ms: u64 = value.parse().map_err(|_| ParseArgError::InvalidValue("report-every"))?;
+                if ms == 0 {
+                    return Err(ParseArgError::InvalidValue("report-every"));
+                }
+                parsed.report_every = Some(ms);
+            }
+            "--on-change" => parsed.on_change_flag = true,
+            "--change-threshold" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("change-threshold"))?;
+                let bytes = parse_memory_arg(value).map_err(|_| ParseArgError::InvalidValue("change-threshold"))?;
+                parsed.change_threshold = bytes;
+                parsed.on_change_flag = true;
+            }
+            "--coalesce" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("coalesce"))?;
+                let ms: u64 = value.parse().map_err(|_| ParseArgError::InvalidValue("coalesce"))?;
+                if ms == 0 {
+                    return Err(ParseArgError::InvalidValue("coalesce"));
+                }
+                parsed.coalesce = Some(ms);
+            }
+            "--grace" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("grace"))?;
+                parsed.grace = value.parse().map_err(|_| ParseArgError::InvalidValue("grace"))?;
+                if parsed.grace == 0 {
+                    return Err(ParseArgError::InvalidValue("grace"));
+                }
+            }
+            "--epoch-offset" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("epoch-offset"))?;
+                parsed.epoch_offset = Some(value.parse().map_err(|_| ParseArgError::InvalidValue("epoch-offset"))?);
+            }
+            "--relative-to-start" => parsed.relative_to_start_flag = true,
+            "--fail-if-absent" => parsed.fail_if_absent_flag = true,
+            "--keep-parens" => parsed.keep_parens_flag = true,
+            "--largest-process" => parsed.largest_process_flag = true,
+            "--top" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("top"))?;
+                let count: usize = value.parse().map_err(|_| ParseArgError::InvalidValue("top"))?;
+                if count == 0 {
+                    return Err(ParseArgError::InvalidValue("top"));
+                }
+                parsed.top = Some(count);
+            }
+            "--snapshot-file" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("snapshot-file"))?;
+                parsed.snapshot_file = Some(PathBuf::from(value));
+            }
+            "--snapshot-breakdown" => parsed.snapshot_breakdown_flag = true,
+            "--log-warmup" => parsed.log_warmup_flag = true,
+            "--measure" => parsed.measure_flag = true,
+            "--" => {
+                // the rest is the command, its own options included
+                parsed.command = iter.by_ref().cloned().collect();
+            }
+            "--summary-json" => {
+                // the path is optional: a next argument which is not an option or a pid
+                parsed.summary_json_flag = true;
+                parsed.summary_json_file = iter.next_if(|value| !value.starts_with('-') && value.parse::<i32>().is_err())
+                    .map(PathBuf::from);
+            }
+            "--folded" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("folded"))?;
+                parsed.folded_file = Some(PathBuf::from(value));
+            }
+            "--watchdog" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("watchdog"))?;
+                let seconds: u64 = value.parse().map_err(|_| ParseArgError::InvalidValue("watchdog"))?;
+                if seconds == 0 {
+                    return Err(ParseArgError::InvalidValue("watchdog"));
+                }
+                parsed.watchdog = Some(seconds);
+            }
+            "--pushgateway" => {
+                parsed.pushgateway_url = Some(iter.next().ok_or(ParseArgError::MissingValue("pushgateway"))?.clone());
+            }
+            "--job" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("job"))?;
+                if !is_valid_job(value) {
+                    return Err(ParseArgError::InvalidValue("job"));
+                }
+                parsed.job = Some(value.clone());
+            }
+            "--push-every" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("push-every"))?;
+                let seconds: u64 = value.parse().map_err(|_| ParseArgError::InvalidValue("push-every"))?;
+                if seconds == 0 {
+                    return Err(ParseArgError::InvalidValue("push-every"));
+                }
+                parsed.push_every = Some(seconds);
+            }
             "--output-file" => {
                 let value = iter.next().ok_or(ParseArgError::MissingValue("output-file"))?;
-                output = OutputSpec::File(PathBuf::from(value));
+                if let Some(previous) = output_flag(&parsed.output) {
+                    return Err(ParseArgError::ConflictingFlags(previous, "output-file"));
+                }
+                parsed.output = OutputSpec::File(PathBuf::from(value));
+            }
+            "--no-follow" => parsed.no_follow_flag = true,
+            "--gzip" => parsed.gzip_flag = true,
+            "--buffer-size" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("buffer-size"))?;
+                let size = parse_memory_arg(value).map_err(|_| ParseArgError::InvalidValue("buffer-size"))?;
+                if size == 0 || size > MAX_BUFFER_SIZE as u64 {
+                    return Err(ParseArgError::InvalidValue("buffer-size"));
+                }
+                parsed.buffer_size = Some(size as usize);
+            }
+            "--socket" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("socket"))?;
+                if let Some(previous) = output_flag(&parsed.output) {
+                    return Err(ParseArgError::ConflictingFlags(previous, "socket"));
+                }
+                parsed.output = OutputSpec::Socket(PathBuf::from(value));
+            }
+            "--tcp" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("tcp"))?;
+                if let Some(previous) = output_flag(&parsed.output) {
+                    return Err(ParseArgError::ConflictingFlags(previous, "tcp"));
+                }
+                // the host is resolved when connecting, only the port can be checked here
+                match value.rsplit_once(':') {
+                    Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => (),
+                    _ => return Err(ParseArgError::InvalidValue("tcp")),
+                }
+                parsed.output = OutputSpec::Tcp(value.clone());
+            }
+            "--sqlite" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("sqlite"))?;
+                parsed.sqlite_path = Some(PathBuf::from(value));
             }
             "--name" => {
             	let value = iter.next().ok_or(ParseArgError::MissingValue("name"))?;
-            	name = Some("(".to_string() + value + ")");
+            	name = Some(value.to_string());
+            }
+            "--search" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("search"))?;
+                parsed.search = Some(NameSearch::Substring(value.to_string()));
+                search_flags.push("search");
+            }
+            "--search-regex" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("search-regex"))?;
+                let regex = Regex::new(value).map_err(|_| ParseArgError::InvalidValue("search-regex"))?;
+                parsed.search = Some(NameSearch::Pattern(value.to_string(), regex));
+                search_flags.push("search-regex");
+            }
+            "--search-tree" => parsed.search_tree_flag = true,
+            "--wait-for" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("wait-for"))?;
+                parsed.wait_for = Some(value.to_string());
+            }
+            "--wait-timeout" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("wait-timeout"))?;
+                parsed.wait_timeout = Some(value.parse().map_err(|_| ParseArgError::InvalidValue("wait-timeout"))?);
+            }
+            "--cgroup-procs" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("cgroup-procs"))?;
+                cgroup = Some(cgroup_dir(value));
+            }
+            "--match-cmdline" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("match-cmdline"))?;
+                cmdline_pattern = Some(Regex::new(value).map_err(|_| ParseArgError::InvalidValue("match-cmdline"))?);
+            }
+            "--max-threshold" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("max-threshold"))?;
+                let bytes = parse_memory_arg(value).map_err(|_| ParseArgError::InvalidValue("max-threshold"))?;
+                parsed.max_threshold = Some(bytes);
+            }
+            "--threshold" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("threshold"))?;
+                let bytes = parse_memory_arg(value).map_err(|_| ParseArgError::InvalidValue("threshold"))?;
+                parsed.threshold = Some(bytes);
+            }
+            "--baseline" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("baseline"))?;
+                let bytes = parse_memory_arg(value).map_err(|_| ParseArgError::InvalidValue("baseline"))?;
+                parsed.baseline = Some(bytes);
+            }
+            "--tolerance" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("tolerance"))?;
+                let percent: f64 = value.trim_end_matches('%').parse().map_err(|_| ParseArgError::InvalidValue("tolerance"))?;
+                if !percent.is_finite() || percent < 0.0 {
+                    return Err(ParseArgError::InvalidValue("tolerance"));
+                }
+                parsed.tolerance = Some(percent);
+            }
+            "--on-threshold" => {
+                parsed.on_threshold = Some(iter.next().ok_or(ParseArgError::MissingValue("on-threshold"))?.clone());
+            }
+            "--on-threshold-repeat" => parsed.on_threshold_repeat_flag = true,
+            "--kill-on-threshold" => parsed.kill_on_threshold_flag = true,
+            "--kill-tree" => parsed.kill_tree_flag = true,
+            "--kill-grace" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("kill-grace"))?;
+                parsed.kill_grace = value.parse().map_err(|_| ParseArgError::InvalidValue("kill-grace"))?;
+            }
+            "--vsz" => parsed.metric = Metric::Vsz,
+            "--pss" => parsed.metric = Metric::Pss,
+            "--metric" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("metric"))?;
+                parsed.metric = value.parse().map_err(|_| ParseArgError::InvalidValue("metric"))?;
+            }
+            "--format" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("format"))?;
+                parsed.format = value.parse().map_err(|_| ParseArgError::InvalidValue("format"))?;
             }
             "--template" => {
-            	template_string = iter.next().ok_or(ParseArgError::MissingValue("template"))?.clone();
+            	parsed.template_string = Some(iter.next().ok_or(ParseArgError::MissingValue("template"))?.clone());
+            }
+            "--pid-ns" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("pid-ns"))?;
+                parsed.pid_namespace = Some(value.parse().map_err(|_| ParseArgError::InvalidValue("pid-ns"))?);
+            }
+            "--fields" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("fields"))?;
+                let fields: Vec<template_engine::Field> = value.split(',')
+                    .map(|name| name.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| ParseArgError::InvalidValue("fields"))?;
+                parsed.fields = Some(fields);
+            }
+            "--emit-end-marker" => emit_end_marker_flag = true,
+            "--end-marker" => {
+                parsed.end_marker = Some(iter.next().ok_or(ParseArgError::MissingValue("end-marker"))?.clone());
+            }
+            "--template-stderr" => {
+                parsed.template_stderr = Some(iter.next().ok_or(ParseArgError::MissingValue("template-stderr"))?.clone());
+            }
+            "--template-file" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("template-file"))?;
+                parsed.template_file = Some(PathBuf::from(value));
+            }
+            "--color" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("color"))?;
+                parsed.color = value.parse().map_err(|_| ParseArgError::InvalidValue("color"))?;
+            }
+            "--no-color" => parsed.color = ColorChoice::Never,
+            "--precision" => {
+                let value = iter.next().ok_or(ParseArgError::MissingValue("precision"))?;
+                parsed.precision = value.parse().map_err(|_| ParseArgError::InvalidValue("precision"))?;
+                if parsed.precision > template_engine::MAX_PRECISION {
+                    return Err(ParseArgError::InvalidValue("precision"));
+                }
             }
             "--page-size-kib" => {
                 let value = iter.next().ok_or(ParseArgError::MissingValue("page-size-kib"))?;
-                page_size_kib = value.parse().map_err(|_| ParseArgError::InvalidValue("page-size-kib"))?;
+                let page_size: u64 = value.parse().map_err(|_| ParseArgError::InvalidValue("page-size-kib"))?;
+                if page_size == 0 {
+                    return Err(ParseArgError::InvalidValue("page-size-kib"));
+                }
+                parsed.page_size_kib = Some(page_size);
             }
             other => {
                 // assume PID if numeric
-                pid = Some(other.parse().map_err(|_| ParseArgError::InvalidValue("pid"))?);
+                pids.push(other.parse().map_err(|_| ParseArgError::InvalidValue("pid"))?);
             }
         }
     }
-    if let Some(name_val) = name {
-        target_pids.append(&mut get_pids_from_name(name_val));
-    } else {
-        let target_pid = pid.ok_or(ParseArgError::MissingValue("pid"))?; // accept only one pid from raw args
-        target_pids.push(target_pid);
-    }
-
-    Ok(Args {
-        help_flag,
-        version_flag,
-        final_flag,
-        hz,
-        page_size_kib,
-        output,
-        target_pids,
-        template_string,
-    })
+    if parsed.tolerance.is_some() && parsed.baseline.is_none() {
+        return Err(ParseArgError::MissingValue("baseline"));
+    }
+    if parsed.threshold.is_some() {
+        // two limits on the same peak, nothing would say which one fails the run
+        if parsed.baseline.is_some() {
+            return Err(ParseArgError::ConflictingFlags("threshold", "baseline"));
+        }
+        if parsed.max_threshold.is_some() {
+            return Err(ParseArgError::ConflictingFlags("threshold", "max-threshold"));
+        }
+    }
+    if let Some(baseline) = parsed.baseline {
+        if parsed.max_threshold.is_some() {
+            return Err(ParseArgError::ConflictingFlags("baseline", "max-threshold"));
+        }
+        // the hooks and --kill-on-threshold then work against the computed limit
+        parsed.max_threshold = Some(baseline_limit(baseline, parsed.tolerance.unwrap_or(0.0)));
+    }
+    if (parsed.on_threshold.is_some() || parsed.kill_on_threshold_flag) && parsed.max_threshold.is_none() {
+        return Err(ParseArgError::MissingValue("max-threshold"));
+    }
+    if parsed.format != FormatKind::Text && (parsed.template_string.is_some() || parsed.template_file.is_some()) {
+        return Err(ParseArgError::ConflictingFlags("format", "template"));
+    }
+    if parsed.template_string.is_some() && parsed.template_file.is_some() {
+        return Err(ParseArgError::ConflictingFlags("template", "template-file"));
+    }
+    if parsed.fields.is_some() {
+        // the columns of CSV and the keys of JSON, a template already chooses its fields
+        // and --format refuses one
+        match parsed.format {
+            FormatKind::Csv | FormatKind::Json => (),
+            FormatKind::Text => return Err(ParseArgError::MissingValue("format")),
+            _ => return Err(ParseArgError::ConflictingFlags("fields", "format")),
+        }
+    }
+    if emit_end_marker_flag && parsed.end_marker.is_none() {
+        // --format can come after --emit-end-marker
+        parsed.end_marker = Some(default_end_marker(parsed.format).to_string());
+    }
+    if parsed.group_by_name_flag && parsed.no_final_flag {
+        return Err(ParseArgError::ConflictingFlags("group-by-name", "no-final"));
+    }
+    if parsed.coalesce.is_some() && parsed.report_every.is_some() {
+        return Err(ParseArgError::ConflictingFlags("coalesce", "report-every"));
+    }
+    if parsed.per_pid_stream_flag {
+        // each of them already decides which lines stand for a sample
+        let other = [
+            (parsed.per_child_flag, "per-child"),
+            (parsed.coalesce.is_some(), "coalesce"),
+        ].into_iter().find_map(|(used, flag)| used.then_some(flag));
+        if let Some(other) = other {
+            return Err(ParseArgError::ConflictingFlags("per-pid-stream", other));
+        }
+    }
+    if parsed.coalesce.is_some() && parsed.per_child_flag {
+        return Err(ParseArgError::ConflictingFlags("coalesce", "per-child"));
+    }
+    if parsed.histogram_bucket.is_some() && parsed.no_final_flag {
+        return Err(ParseArgError::ConflictingFlags("histogram", "no-final"));
+    }
+    if parsed.largest_process_flag && parsed.no_final_flag {
+        return Err(ParseArgError::ConflictingFlags("largest-process", "no-final"));
+    }
+    if parsed.summary_only_flag && parsed.no_final_flag {
+        return Err(ParseArgError::ConflictingFlags("summary-only", "no-final"));
+    }
+    if parsed.final_flag && parsed.no_final_flag {
+        // --final prints only the trailing line, --no-final removes it: nothing would be printed
+        return Err(ParseArgError::ConflictingFlags("final", "no-final"));
+    }
+    if parsed.epoch_offset.is_some() && parsed.relative_to_start_flag {
+        return Err(ParseArgError::ConflictingFlags("epoch-offset", "relative-to-start"));
+    }
+    let selectors: Vec<&'static str> = [
+        (name.is_some(), "name"),
+        (cmdline_pattern.is_some(), "match-cmdline"),
+        (cgroup.is_some(), "cgroup-procs"),
+        (parsed.wait_for.is_some(), "wait-for"),
+    ].into_iter().filter_map(|(used, flag)| used.then_some(flag)).chain(search_flags).collect();
+    if let [first, second, ..] = selectors[..] {
+        return Err(ParseArgError::ConflictingFlags(first, second));
+    }
+    if !parsed.command.is_empty() {
+        // memimpact -- <command> wraps the command, like time(1)
+        parsed.measure_flag = true;
+    }
+    if parsed.measure_flag {
+        if parsed.command.is_empty() {
+            return Err(ParseArgError::MissingValue("command"));
+        }
+        let other = [
+            (parsed.top.is_some(), "top"),
+            (parsed.replay_file.is_some(), "replay"),
+            (parsed.pid_namespace.is_some(), "pid-ns"),
+        ].into_iter().chain(selectors.iter().map(|selector| (true, *selector)))
+            .find_map(|(used, flag)| used.then_some(flag));
+        if let Some(other) = other {
+            return Err(ParseArgError::ConflictingFlags("measure", other));
+        }
+        if !pids.is_empty() {
+            // the command is the target
+            return Err(ParseArgError::InvalidValue("pid"));
+        }
+    }
+    if parsed.no_follow_flag {
+        parsed.output = match parsed.output {
+            OutputSpec::File(path) => OutputSpec::NoFollowFile(path),
+            _ => return Err(ParseArgError::MissingValue("output-file")),
+        };
+    }
+    match &parsed.pushgateway_url {
+        Some(url) => {
+            let job = parsed.job.as_deref().unwrap_or("memimpact");
+            parsed.pushgateway = Some(Pushgateway::new(url, job).map_err(|_| ParseArgError::InvalidValue("pushgateway"))?);
+        }
+        None if parsed.job.is_some() || parsed.push_every.is_some() => {
+            return Err(ParseArgError::MissingValue("pushgateway"));
+        }
+        None => (),
+    }
+    if let Some(size) = parsed.buffer_size {
+        if output_flag(&parsed.output) != Some("output-file") {
+            return Err(ParseArgError::MissingValue("output-file"));
+        }
+        if parsed.gzip_flag {
+            // the compressed blocks are already written at once
+            return Err(ParseArgError::ConflictingFlags("buffer-size", "gzip"));
+        }
+        parsed.output = OutputSpec::Buffered(Box::new(parsed.output), size);
+    }
+    if parsed.gzip_flag {
+        if output_flag(&parsed.output) != Some("output-file") {
+            return Err(ParseArgError::MissingValue("output-file"));
+        }
+        parsed.output = OutputSpec::Gzip(Box::new(parsed.output));
+    }
+    if parsed.top.is_some()
+        && let Some(selector) = selectors.first() {
+            // --top looks at every process, there is no target to select
+            return Err(ParseArgError::ConflictingFlags("top", selector));
+        }
+    if parsed.pid_namespace.is_some()
+        && let Some(selector) = selectors.first() {
+            // names, command lines and cgroups are looked up on the host, only a pid can be translated
+            return Err(ParseArgError::ConflictingFlags("pid-ns", selector));
+        }
+    if parsed.search_tree_flag && parsed.search.is_none() {
+        return Err(ParseArgError::MissingValue("search"));
+    }
+    if parsed.wait_timeout.is_some() && parsed.wait_for.is_none() {
+        return Err(ParseArgError::MissingValue("wait-for"));
+    }
+    if let Some(dir) = cgroup {
+        parsed.target_pids = read_cgroup_procs(&dir).map_err(|_| ParseArgError::InvalidValue("cgroup-procs"))?;
+    } else if let Some(pattern) = cmdline_pattern {
+        parsed.target_pids.append(&mut get_pids_from_cmdline(&pattern));
+    } else if parsed.search.is_some() || parsed.wait_for.is_some() {
+        if !pids.is_empty() {
+            // the processes are found again at every sample, a fixed pid makes no sense
+            return Err(ParseArgError::InvalidValue("pid"));
+        }
+    } else if let Some(name_val) = name {
+        parsed.target_pids.append(&mut get_pids_from_name(name_val));
+        if parsed.no_kernel_threads_flag {
+            let parent_of = get_map_pid_to_ppid(&mut ProcReader::new());
+            parsed.target_pids.retain(|pid| !is_kernel_thread(*pid, &parent_of));
+        }
+    } else if parsed.replay_file.is_none() && parsed.top.is_none() && !parsed.measure_flag {
+        if pids.is_empty() {
+            return Err(ParseArgError::MissingValue("pid"));
+        }
+        // the trees of several targets are measured as one, a pid given twice counts once
+        for pid in pids {
+            if !parsed.target_pids.contains(&pid) {
+                parsed.target_pids.push(pid);
+            }
+        }
+    }
+    if parsed.pid_namespace.is_none() {
+        // with --pid-ns, pid 1 is a container's application: checked once translated
+        confirm_pid_1(&parsed.target_pids, parsed.all_flag)?;
+    }
+
+    Ok(parsed)
 }
 
 
-fn now() -> u64{
-	SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+fn confirm_pid_1(target_pids: &[i32], all_flag: bool) -> Result<(), ParseArgError> {
+    // the host pid 1: likely a mistake on a host, intended in a container where pid 1 is the application
+    if target_pids.contains(&1) && !all_flag {
+        return Err(ParseArgError::Unconfirmed("pid 1 is the ancestor of every process, it would monitor them all", "all"));
+    }
+    Ok(())
 }
 
-fn main() {
-	let raw_args: Vec<String> = env::args().collect();
-    let args: Args = match parse_args(&raw_args) {
-    	Ok(args_struct) => args_struct,
-    	Err(e) => {
-    		eprintln!("Memimpact failed to parsed arguments: {:?}", e);
-    		process::exit(1);
-    	}
-    };
-    if args.help_flag{
-    	let version = env!("CARGO_PKG_VERSION");
-		println!(
-"MemImpact — sample and report peak RSS memory usage of a Linux process tree
-
-MemImpact monitors memory from the outside via /proc. It estimates peak
-resident memory (RSS) usage over time for a process and all its children.
-It is designed for quick measurement, not deep profiling.
 
-USAGE:
-    memimpact <pid>                  Monitor a running process
-    memimpact --name <process_name>  Monitor processes matching a name
+fn named_processes(statms: &[(i32, Statm)], keep_parens: bool) -> Vec<(String, u64)> {
+    // processes which exited since statm was read are left out
+    statms.iter()
+        .filter_map(|(pid, statm)| get_process_name(pid).ok().map(|name| (display_name(name, keep_parens), statm.resident)))
+        .collect()
+}
 
-COMMON USE:
-    To measure a command like `time`, use a shell wrapper that launches the
-    program and passes its PID to memimpact (see README).
 
-OPTIONS:
-    --help -h            Print this message and leave.
+fn page_size(args: &Args) -> Result<u64, MemimpactError> {
+    // in bytes; 16KiB and 64KiB pages are common on arm64, a guess would scale every value wrong
+    args.page_size_kib.map(|kib| kib * 1024).or_else(sys::page_size).ok_or_else(|| {
+        MemimpactError::UnsupportedKernel("could not detect the page size, pass it with --page-size-kib".to_string())
+    })
+}
 
-    --hertz <n>          Sampling rate in measurements per second.
-                         Higher values increase accuracy but add overhead.
 
-    --page-size-kib <n>  Page size of your system in KiB.
-    					 4 by default, for most Linux.
+fn run_top(count: usize, args: &Args, interval_ms: u64, page_size: u64) -> ! {
+    // refreshed until interrupted, on a terminal each refresh replaces the previous one
+    let clear_screen = io::stdout().is_terminal();
+    let mut reader = ProcReader::new();
+    let mut out = io::stdout();
+    let mut separator = if clear_screen { "\x1b[H\x1b[2J" } else { "" };
+    loop {
+        let parent_of = get_map_pid_to_ppid(&mut reader);
+        let resident: HashMap<i32, u64> = parent_of.keys()
+            .filter_map(|pid| read_statm(&mut reader, pid, page_size).map(|statm| (*pid, statm.resident)))
+            .collect();
+        let trees = breakdown::rank_trees(&parent_of, &resident, count);
+        let report = breakdown::format_top(
+            &trees,
+            |pid| get_process_name(&pid).ok().map(|name| display_name(name, args.keep_parens_flag)),
+            args.precision,
+        );
+        write_output(&mut out, &format!("{}{}", separator, report));
+        if !clear_screen {
+            separator = "\n";
+        }
+        let _ = out.flush();
+        thread::sleep(Duration::from_millis(interval_ms));
+    }
+}
 
 
-    --final              Print only one line with the maximum observed memory
-                         instead of continuous sampling output.
+fn push_metrics(gateway: &Pushgateway, sample: &template_engine::MemorySample) {
+    // the measurement matters more than its publication, a failed push is only reported
+    if let Err(e) = gateway.push(&render_metrics(sample)) {
+        eprintln!("memimpact warning: could not push to the pushgateway: {}", e);
+    }
+}
 
-    --output-file <path> Write output to a file instead of stdout.
 
-    --template <string>  Custom output format. Fields use {{}} placeholders.
+fn write_snapshot(path: Option<&Path>, snapshot: &[u8]) -> io::Result<()> {
+    // appended, so that a file collects every snapshot of the run
+    match path {
+        Some(path) => fs::OpenOptions::new().create(true).append(true).open(path)?.write_all(snapshot),
+        None => io::stderr().write_all(snapshot),
+    }
+}
 
-    --version -v         Print the Memimpact version and leave.
 
-NAME MODE:
+fn display_name(name: String, keep_parens: bool) -> String {
+    // --keep-parens: the name as it appears in /proc/[pid]/stat, like memimpact used to print it
+    if keep_parens {
+        format!("({})", name)
+    } else {
+        name
+    }
+}
+
+
+fn cgroup_dir(value: &str) -> PathBuf {
+    // either a directory of the cgroup filesystem, or a cgroup path like /system.slice/nginx.service
+    let path = PathBuf::from(value);
+    if path.is_dir() {
+        return path;
+    }
+    Path::new("/sys/fs/cgroup").join(value.trim_start_matches('/'))
+}
+
+
+fn default_end_marker(format: FormatKind) -> &'static str {
+    // --emit-end-marker without --end-marker: a last record for JSON lines, which
+    // their consumers can parse, and a comment for CSV and the other formats
+    match format {
+        FormatKind::Json => "{{\"event\":\"end\",\"max_bytes\":{MaxBytes}}}\\n",
+        _ => "# END\\n",
+    }
+}
+
+
+// a tree with no resident memory after this many samples is measured anyway,
+// e.g. a kernel thread: without a bound the run would print nothing until it exits
+const MAX_WARMUP_SAMPLES: u32 = 50;
+
+
+fn still_warming_up(resident: u64, skipped_samples: u32) -> bool {
+    resident == 0 && skipped_samples < MAX_WARMUP_SAMPLES
+}
+
+
+fn default_template(args: &Args) -> String {
+    let mut template = match &args.target_pids[..] {
+        _ if args.search.is_some() => "Search {ProcessName} ({ProcessCount} processes): current {CurrentHuman}, max {MaxHuman}".to_string(),
+        [_, _, ..] => format!("PIDs {} (aggregate): current {{CurrentHuman}}, max {{MaxHuman}}", pid_list(&args.target_pids)),
+        _ => "PID {Pid} {ProcessName}: current {CurrentHuman}, max {MaxHuman}".to_string(),
+    };
+    if args.swap_flag {
+        template.push_str(", swap {SwapHuman}");
+    }
+    if args.psi_flag {
+        template.push_str(", memory pressure some {MemPressure}% full {MemPressureFull}%");
+    }
+    template.push('\n');
+    template
+}
+
+
+fn pid_list(pids: &[i32]) -> String {
+    // a --name matching hundreds of processes must not make the line unreadable
+    const SHOWN: usize = 5;
+    let shown: Vec<String> = pids.iter().take(SHOWN).map(|pid| pid.to_string()).collect();
+    match pids.len() {
+        count if count > SHOWN => format!("{} and {} more", shown.join(","), count - SHOWN),
+        _ => shown.join(","),
+    }
+}
+
+
+fn load_template(args: &Args) -> Result<template_engine::Template, String> {
+    let template_string = match &args.template_file {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| format!("Could not read template file {}: {}", path.display(), e))?,
+        None => args.template_string.clone().unwrap_or_else(|| default_template(args)),
+    };
+    parse_template(&template_string)
+}
+
+
+fn record_format(args: &Args, template: template_engine::Template) -> RecordFormat {
+    let columns = args.fields.clone().map(|fields| Columns{fields, precision: args.precision});
+    match args.format {
+        FormatKind::Text => RecordFormat::Template(template),
+        FormatKind::Influx => RecordFormat::Influx,
+        FormatKind::Json => RecordFormat::Json(columns),
+        FormatKind::Csv => RecordFormat::Csv(columns),
+        FormatKind::Prometheus => RecordFormat::Prometheus,
+    }
+}
+
+
+fn parse_template(template_string: &str) -> Result<template_engine::Template, String> {
+    let escaped = template_engine::unescape(template_string)?;
+    template_engine::Template::parse(escaped.as_str())
+}
+
+
+fn run_diff(baseline: &Path, candidate: &Path, precision: usize) -> Result<String, String> {
+    let summarize_file = |path: &Path| -> Result<recorded::RunSummary, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let samples = recorded::parse_recorded_run(&content)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        recorded::summarize(&samples).ok_or(format!("{}: no sample found", path.display()))
+    };
+    Ok(recorded::format_diff(&summarize_file(baseline)?, &summarize_file(candidate)?, precision))
+}
+
+
+fn run_replay<W: io::Write>(path: &Path, args: &Args, out: &mut W) -> Result<(), MemimpactError> {
+    // recorded samples go through the statistics of a live run, only the final line is printed
+    let content = fs::read_to_string(path)
+        .map_err(|e| MemimpactError::Replay(format!("Could not read {}: {}", path.display(), e)))?;
+    let recorded = recorded::parse_recorded_run(&content)
+        .map_err(|e| MemimpactError::Replay(format!("{}: {}", path.display(), e)))?;
+    let Some(first) = recorded.first() else {
+        return Err(MemimpactError::Replay(format!("{}: no sample found", path.display())));
+    };
+    let mut template = load_template(args).map_err(|msg| MemimpactError::Template("template", msg))?;
+    template.precision = args.precision;
+    let record_format = record_format(args, template);
+
+    let mut run_stats = RunStats::new(args.histogram_bucket, args.time_weighted_avg_flag, args.summary_json_flag);
+    let started = Instant::now();
+    let first_timestamp = first.timestamp.unwrap_or(0);
+    let process_name = first.process_name.clone().unwrap_or_default();
+    let mut sample = template_engine::MemorySample{
+        pid: first.pid.unwrap_or(0),
+        process_name: process_name.as_str(),
+        ..Default::default()
+    };
+    for (index, record) in recorded.iter().enumerate(){
+        sample.next_sample();
+        sample.current_bytes = record.current_bytes;
+        // a recording made with --report-every can hold a max above every written sample
+        sample.max_bytes = sample.max_bytes.max(record.max_bytes.unwrap_or(0));
+        sample.process_count = record.process_count.unwrap_or(0);
+        sample.timestamp = record.timestamp.unwrap_or(index as u64);
+        // without timestamps, samples are taken as one second apart
+        let elapsed = Duration::from_secs(sample.timestamp.saturating_sub(first_timestamp));
+        run_stats.record(&mut sample, started + elapsed, false);
+    }
+    sample.is_final = true;
+    Emitter::new(record_format, args.null_flag).emit(out, &sample, None, false);
+    if args.format == FormatKind::Text{
+        write_output(out, &run_stats.stats.format(args.precision));
+    }
+    if let Some(histogram) = &run_stats.histogram{
+        write_output(out, &histogram.format(args.precision));
+    }
+    write_summary_json(args.summary_json_file.as_deref(), &run_stats, sample.max_bytes);
+    Ok(())
+}
+
+
+fn spawn_measured(command: &[String]) -> Result<process::Child, MemimpactError> {
+    // stdin, stdout and stderr are inherited, the command runs as it would without memimpact
+    process::Command::new(&command[0])
+        .args(&command[1..])
+        .spawn()
+        .map_err(|e| MemimpactError::Spawn(command[0].clone(), e))
+}
+
+
+fn exit_code_of(status: process::ExitStatus) -> i32 {
+    // a command killed by a signal exits like in a shell, 128 + the signal
+    status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}
+
+
+fn measure_line(sample: &template_engine::MemorySample, duration: Duration, exit: i32, precision: usize) -> String {
+    // --measure: one line for a Makefile or a CI log, like time(1)
+    format!(
+        "peak={} avg={} duration={:.1}s exit={}",
+        template_engine::format_memory(sample.max_bytes, precision),
+        template_engine::format_memory(sample.time_weighted_avg.unwrap_or(0), precision),
+        duration.as_secs_f64(),
+        exit,
+    )
+}
+
+
+fn exit_like_command(command_exit: Option<i32>, outcome: &Result<(), MemimpactError>) {
+    // once measured, memimpact exits with the code of the command, unless it failed itself
+    if let (Some(code), Ok(())) = (command_exit, outcome) {
+        process::exit(code);
+    }
+}
+
+
+fn write_summary_json(path: Option<&Path>, run_stats: &RunStats, max: u64) {
+    let Some(summary) = &run_stats.summary else {
+        return;
+    };
+    let json = summary.to_json(max);
+    let written = match path {
+        Some(path) => fs::write(path, json),
+        None => io::stdout().write_all(json.as_bytes()),
+    };
+    if let Err(e) = written{
+        eprintln!("memimpact warning: could not write the summary json: {}", e);
+    }
+}
+
+
+fn now() -> u64{
+	SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn main() {
+	let raw_args: Vec<String> = env::args().collect();
+	let result = config::expand_config(raw_args).map_err(MemimpactError::Config)
+	    .and_then(|raw_args| parse_args(&raw_args).map_err(MemimpactError::from))
+	    .and_then(run);
+	let code = match result {
+	    Ok(()) => ExitCode::Success,
+	    Err(e) => {
+	        eprintln!("memimpact error: {}", e);
+	        e.exit_code()
+	    }
+	};
+	process::exit(code as i32);
+}
+
+
+fn run(mut args: Args) -> Result<(), MemimpactError> {
+    if args.help_flag{
+    	let version = env!("CARGO_PKG_VERSION");
+		println!(
+"MemImpact — sample and report peak RSS memory usage of a Linux process tree
+
+MemImpact monitors memory from the outside via /proc. It estimates peak
+resident memory (RSS) usage over time for a process and all its children.
+It is designed for quick measurement, not deep profiling.
+
+USAGE:
+    memimpact <pid>                  Monitor a running process
+    memimpact <pid> <pid>...         Monitor several processes as one, the
+                                     union of their trees, until the last exits
+    memimpact [--measure] -- <command> [args...]
+                                     Run a command and print its peak memory
+    memimpact --name <process_name>  Monitor processes matching a name
+    memimpact --cgroup-procs <path>  Monitor every process of a cgroup
+    memimpact --match-cmdline <regex>
+                                     Monitor processes whose full command line
+                                     matches a regular expression
+    memimpact --search <text>        Monitor processes whose name contains
+                                     <text>, found again at every sample
+    memimpact --search-regex <regex> Same, for names matching a regular
+                                     expression
+    memimpact --wait-for <name>      Wait for a process named <name> to start,
+                                     then monitor it and its children
+
+CONFIG FILE:
+    --config <path>      Read options from a file, one per line as key = value,
+                         the key being the option without its dashes:
+                             # nightly job
+                             hertz = 10
+                             output-file = /var/log/memimpact.csv
+                             template = \"{{Timestamp}},{{CurrentBytes}}\\n\"
+                             final = true
+                             pid = 1234
+                         true enables a flag, false leaves it out. Quotes keep
+                         the spaces around a value. An option also given on the
+                         command line takes the command line value.
+
+COMMON USE:
+    To measure a command like `time`, put it after --:
+        memimpact -- cargo build --release
+    The command keeps its input and outputs, then memimpact prints its peak
+    on stderr and exits with the exit code of the command (see MEASURE MODE).
+
+OPTIONS:
+    --help -h            Print this message and leave.
+
+    --list-fields        Print every template field with its type and leave.
+
+    --hertz <n>          Sampling rate in measurements per second.
+                         Higher values increase accuracy but add overhead.
+
+    --adaptive           Slow down sampling while memory is stable: the interval
+                         doubles each time two samples differ by 1% or less, up
+                         to 8 times the --hertz interval, and goes back to the
+                         --hertz interval as soon as memory changes more.
+
+    --align-to-clock     Take every sample after the first one on a multiple of
+                         the interval since the Unix epoch, e.g. on every round
+                         second at --hertz 1, to line them up with other
+                         per-second collectors. The time spent sampling is then
+                         taken out of the sleep.
+
+    --report-every <ms>  Print a sample at most once every <ms> milliseconds,
+                         while still sampling at the --hertz rate so that the
+                         max catches short spikes. The final line is always
+                         printed.
+
+    --on-change          Write a sample only when {{CurrentBytes}} differs from the
+                         last written one, the max and the averages still use
+                         every sample. The final line is always printed.
+
+    --change-threshold <size>
+                         With --on-change, ignore changes up to <size>, e.g.
+                         1MiB, measured from the last written sample so a slow
+                         drift still shows. Implies --on-change.
+
+    --coalesce <ms>      Write only the sample with the most memory of every <ms>
+                         window. Unlike --report-every, the written samples keep
+                         the peaks. The max still counts every sample.
+
+    --grace <n>          Number of consecutive samples the target must be missing
+                         before memimpact stops. 1 by default.
+
+    --wait-timeout <seconds>
+                         Give up --wait-for with exit code 3 when no process of
+                         that name started within <seconds>. Waits forever by
+                         default. When several match, the lowest pid is chosen.
+
+    --epoch-offset <seconds>
+                         Subtract <seconds> from every {{Timestamp}}, e.g. the Unix
+                         time a benchmark started, to line samples up with logs
+                         timestamped from that start.
+
+    --relative-to-start  Same as --epoch-offset with the time memimpact started:
+                         the first sample has a {{Timestamp}} of 0.
+
+    --color <when>       Print the final line in bold on the terminal: auto (default,
+                         only when stdout is a terminal and NO_COLOR is not set),
+                         always or never. Files and sockets are never colored.
+    --no-color           Same as --color never.
+
+    --precision <n>      Decimals of every human readable value, like CurrentHuman,
+                         from 0 (default) to 9. Values are rounded.
+
+    --page-size-kib <n>  Page size of your system in KiB. Detected by default,
+                         4 on most Linux systems, 16 or 64 on some arm64 ones.
+
+
+    --final              Print only the summary: the line with the maximum observed
+                         memory and the statistics of the samples (count, min,
+                         mean and max), instead of continuous sampling output.
+
+    --no-final           Do not print the trailing line once the process is gone,
+                         so every output line is a regular sample.
+
+    --per-child          Track every direct child of the target on its own, with
+                         the memory of its subtree: one line per child and per
+                         sample instead of the total. A child that exits gets
+                         its final line, and its max and average on stderr.
+                         The final line of the whole tree is still printed.
+
+    --all                Confirm the monitoring of pid 1, or of a --name or
+                         --match-cmdline matching it. Every process descends
+                         from pid 1, memimpact then measures the whole system,
+                         or the whole container when it runs inside one. With
+                         --pid-ns, only a pid translated to the host pid 1
+                         needs it.
+
+    --per-pid-stream     Write one line per process of the tree and per sample,
+                         with its own {{Pid}}, {{ProcessName}}, {{CurrentBytes}}
+                         and {{Private}}, instead of the total, for a tool doing
+                         its own aggregation. {{MaxBytes}} is the max of the tree.
+                         The final line of the whole tree is still printed.
+
+    --metric <name>      What {{CurrentBytes}} and {{MaxBytes}} measure:
+                         rss    resident memory, the default
+                         uss    unique set size, what would be freed if the
+                                tree died, from /proc/<pid>/smaps_rollup.
+                                Each read walks every mapping of the process,
+                                it costs far more than the RSS. Needs Linux
+                                4.14 and the right to ptrace the processes,
+                                the RSS is used instead with a warning.
+                         pss    proportional set size, each shared page split
+                                between the processes mapping it, so that
+                                the PSS of several trees adds up. Same cost
+                                and access as uss, read from /proc/<pid>/smaps
+                                before Linux 4.14; a process which cannot be
+                                read counts for nothing.
+                         vsz    virtual memory size, everything mapped whether
+                                resident or not: reserved heaps, memory-mapped
+                                files, thread stacks. Far above the RSS.
+                         {{Vsz}} is filled whatever the metric, to report both.
+    --vsz                Same as --metric vsz.
+    --pss                Same as --metric pss.
+
+    --map-count          Count the memory mappings of every process of the tree,
+                         available as the {{MapCount}} field. A mapping leak grows
+                         it while RSS stays flat, up to vm.max_map_count. Reading
+                         /proc/<pid>/maps costs more than the RSS: the file holds
+                         a line per mapping, thousands for a JVM or a browser.
+
+    --hugepages          Sum the HugetlbPages of /proc/<pid>/status over the tree,
+                         available as the {{HugePages}} field. Explicit hugepages
+                         (hugetlbfs, SHM_HUGETLB) are not part of the RSS: for a
+                         database or a JVM using them, RSS understates the memory.
+                         Transparent hugepages are in the RSS already.
+
+    --swap               Sum the VmSwap of /proc/<pid>/status over the tree,
+                         available as the {{Swap}} and {{SwapHuman}} fields and
+                         added to the default line. Memory moved to swap is not
+                         part of the RSS: under memory pressure the RSS drops
+                         while the process still needs the memory.
+
+    --time-weighted-avg  Compute the mean RSS weighting every sample by the time
+                         elapsed since the previous one, available as the
+                         {{TimeWeightedAvg}} field. Unlike a mean of the samples,
+                         it is not biased by --adaptive or interval jitter.
+
+    --skip-stopped       While every target is stopped (state T, by a signal or a
+                         debugger), keep sampling and printing, and keep the max
+                         up to date, but leave those samples out of --histogram
+                         and --time-weighted-avg: a frozen process would only
+                         repeat the same value for as long as it is stopped.
+
+    --histogram <size>   After the final line, print how many samples fell in each
+                         bucket of <size> memory (e.g. 100MB), to tell a stable
+                         run from a climbing or bimodal one.
+
+    --group-by-name      After the final line, print the memory of the tree at its
+                         peak grouped by process name, with the number of
+                         processes per name, biggest first.
+
+    --summary-json [path]
+                         At exit, write the statistics of the run as one JSON
+                         object to <path>, or to stdout after the final line:
+                         max_bytes, avg_bytes and p95_bytes (in bytes, over the
+                         samples), duration_seconds, samples and
+                         peak_process_count. Stopped samples (--skip-stopped)
+                         only count for the max. Also with --replay.
+
+    --folded <path>      At exit, write the tree at its RSS peak to <path> in the
+                         folded stack format, one line per process:
+                         \"bash-100;make-120;cc1-130 53596160\", the RSS in bytes.
+                         flamegraph.pl or inferno-flamegraph turn it into a
+                         flame graph where the width is the memory of a process
+                         and of its descendants.
+
+    --snapshot-file <path>
+                         Where to append the snapshot taken on SIGUSR1, stderr by
+                         default. On SIGUSR1 (kill -USR1 <memimpact pid>),
+                         memimpact writes the sample it is taking right away,
+                         with the --template or --format, then keeps going.
+
+    --snapshot-breakdown Add the memory of the tree grouped by process name to
+                         every SIGUSR1 snapshot, as --group-by-name does.
+
+    --largest-process    After the final line, print the biggest RSS any single
+                         process of the tree reached during the run, with its
+                         name and pid, e.g. to size a per-worker memory limit.
+
+    --fields <list>      The columns of --format csv, or the keys of --format
+                         json, in that order: template fields in snake_case,
+                         e.g. --fields Timestamp,CurrentBytes,Swap writes the
+                         header timestamp,current_bytes,swap. Strings are quoted,
+                         a missing value is n/a in CSV and null in JSON. An
+                         unknown field is an error.
+
+    --emit-end-marker    Write a last line once the run ends, so that a consumer
+                         can tell a complete output from one cut short, e.g.
+                         over --tcp. With --format json, a last record
+                         {{\"event\":\"end\",\"max_bytes\":<bytes>}}, otherwise
+                         \"# END\". Not written when memimpact is killed.
+
+    --end-marker <template>
+                         The end marker to write instead, a template rendered
+                         with the final values, e.g. 'END {{MaxBytes}}\\n'.
+                         Implies --emit-end-marker.
+
+    --output-file <path> Write output to a file instead of stdout.
+
+    --no-follow          Refuse to open the --output-file if it is a symlink,
+                         instead of truncating the file it points to.
+
+    --gzip               Compress the --output-file with gzip as it is written,
+                         e.g. --gzip --output-file run.csv.gz. The file is only
+                         complete once memimpact exits: on SIGINT or SIGTERM the
+                         sampling stops and the file is finished as on a normal
+                         exit. Up to 64KiB of output is held in memory between
+                         two compressed blocks.
+
+    --pushgateway <url>  At exit, push the peak, the last value and the
+                         time-weighted average of the tree to a Prometheus
+                         Pushgateway, e.g. http://gateway:9091, for jobs too
+                         short to be scraped. Implies --time-weighted-avg.
+                         Only http:// is supported.
+                         A failed push is a warning, it never fails the run.
+
+    --job <name>         Job the metrics are pushed under, /metrics/job/<name>.
+                         Letters, digits, '_', '-' and '.'. memimpact by default.
+
+    --push-every <n>     Also push every <n> seconds while monitoring. A push
+                         waits up to 2s for the pushgateway, delaying a sample.
+
+    --buffer-size <size> Write the --output-file by chunks of <size>, e.g. 64K, up
+                         to 16MiB, instead of once per record. Fewer syscalls at
+                         very short intervals, but the file lags behind and the
+                         last chunk is lost if memimpact is killed with SIGKILL.
+
+    --socket <path>      Write output to a Unix domain stream socket instead of
+                         stdout. The listener must be up when memimpact starts;
+                         if it goes away later, records are dropped until it
+                         accepts a new connection.
+
+    --tcp <host:port>    Stream output over TCP instead of stdout. If the collector
+                         goes away, memimpact reconnects with a backoff of up to
+                         30s and keeps the last 1000 records meanwhile.
+
+    --sqlite <path>      Also insert every sample in the samples table of a SQLite
+                         database, created if needed. Requires the sqlite3 command
+                         line shell at run time: memimpact does not link SQLite,
+                         it pipes INSERT statements to sqlite3, the text escaped,
+                         in transactions of 100 samples.
+
+    --summary-only       Print only the final line on the terminal. Combined with
+                         --output-file, --socket or --tcp, every sample is still written to the file.
+
+    --format <name>      Output format of every sample:
+                         text    the template, see --template (default)
+                         influx  InfluxDB line protocol, with the pid and name
+                                 tags and a nanosecond timestamp
+                         json    one object per line (JSON lines) with pid,
+                                 process_name, current_bytes, max_bytes (bytes),
+                                 timestamp and final, readable by --replay
+                         csv     a header row then one row per sample with
+                                 timestamp, pid, process_name (quoted),
+                                 current_bytes and max_bytes (bytes), readable by
+                                 --replay. --final prints the summary row alone
+                         prometheus
+                                 the text exposition format: for every sample
+                                 the memimpact_rss_bytes gauge with the pid and
+                                 comm labels, then memimpact_max_rss_bytes on
+                                 the final one. With --final and --output-file,
+                                 a file for the textfile collector of
+                                 node_exporter
+
+    --keep-parens        Print process names between parentheses, as they appear
+                         in /proc/<pid>/stat: (bash) instead of bash.
+
+    --template <string>  Custom output format. Fields use {{}} placeholders.
+
+    --pid-ns <ns>        The pid is the one seen inside a container. It is
+                         translated to the host pid through the NSpid lines of
+                         /proc/[pid]/status. <ns> is the pid namespace, as its
+                         inode or as pid:[inode] (see /proc/[pid]/ns/pid), or
+                         auto to search every nested namespace; auto fails when
+                         several containers have a process with that pid.
+
+    --template-stderr <string>
+                         Also write every sample to stderr with this template,
+                         e.g. a human readable line while stdout or the output
+                         file gets --format influx or a JSON template.
+
+    --null -0            End every record, including the final one, with a NUL
+                         byte instead of a newline, for xargs -0 and read -d ''.
+
+    --template-file <path>
+                         Read the template from a file. Escapes like \\n are
+                         supported. Cannot be combined with --template.
+
+    --psi                Also report the memory Pressure Stall Information (avg10)
+                         of the cgroup holding the target, or of the whole system
+                         when the cgroup one is not available.
+
+    --version -v         Print the Memimpact version and leave.
+
+    --no-kernel-threads  Ignore kernel threads (kthreadd and its children) when
+                         selecting and listing processes. They have no RSS so
+                         the total is unchanged.
+
+    --log-warmup         Print a line on stderr for every sample skipped while
+                         warming up. Until the tree has some resident memory,
+                         e.g. while the target is still exec'ing, samples are
+                         neither printed nor counted: a 0 there means the memory
+                         is not readable yet, not that it is empty. A target
+                         that is gone still ends the monitoring. Only the RSS
+                         is checked, whatever --metric, and after 50 samples
+                         the tree is measured anyway, with a warning.
+
+    --include-self       Add the memory of memimpact itself to the total. By
+                         default memimpact leaves itself out, along with the
+                         --on-threshold hooks it starts, even when it runs inside
+                         the monitored tree (e.g. monitoring the shell it was
+                         started from).
+
+    --self-profile       Print on stderr, at exit, how much time memimpact spent
+                         sampling compared to sleeping.
+
+    --max-threshold <size>
+                         Memory limit of the whole tree, e.g. 500MB or 2GiB.
+                         KB, MB, GB are powers of 1000, K, M, G, KiB, MiB, GiB
+                         are powers of 1024, no unit means bytes.
+
+    --on-threshold <cmd> Run <cmd> with sh -c, in the background, the first time
+                         the tree goes above --max-threshold. It receives the
+                         MEMIMPACT_PID, MEMIMPACT_CURRENT_BYTES, MEMIMPACT_MAX_BYTES
+                         and MEMIMPACT_THRESHOLD_BYTES environment variables.
+
+    --on-threshold-repeat
+                         Run the --on-threshold command again each time memory
+                         goes back above the threshold after dropping below it.
+
+    --baseline <size>    Peak of a known good run, e.g. 1.2GiB. The monitoring
+                         goes on to the end, then memimpact exits with code 4
+                         if the peak went above the baseline plus --tolerance.
+                         Acts as --max-threshold for the options using it.
+
+    --tolerance <percent>
+                         Regression allowed above --baseline, e.g. 10 for a
+                         limit of baseline * 1.10. 0 by default.
+
+    --threshold <size>   Fail a CI job using too much memory: the monitoring goes
+                         on to the end, then memimpact exits with code 4 if the
+                         tree ever went above <size>, e.g. 500MB or 2GiB. Cannot
+                         be combined with --max-threshold or --baseline.
+
+    --watchdog <seconds> Exit with code 6 if no sample completes for <seconds>,
+                         e.g. when a read of /proc blocks, instead of hanging
+                         the job memimpact runs in. Must be longer than the
+                         sampling interval, 8 times longer with --adaptive.
+
+    --fail-if-absent     Exit with code 3 when the monitoring stops because the
+                         target is gone, after the final line is printed, so a
+                         CI job can tell a crashed process apart. Stopping on
+                         SIGINT or SIGTERM still exits with 0.
+
+    --kill-on-threshold  When the tree goes above --max-threshold, send SIGTERM to
+                         the target, then SIGKILL if it is still running after
+                         --kill-grace seconds. memimpact then exits with code 5.
+
+    --kill-tree          With --kill-on-threshold, signal every process of the
+                         tree instead of only the target.
+
+    --kill-grace <n>     Seconds between SIGTERM and SIGKILL. 5 by default.
+
+TOP MODE:
+    memimpact --top <n> [--hertz <n>]
+    Print the <n> process trees of the system using the most resident memory,
+    refreshed at the sampling interval until interrupted. A tree is a child of
+    init with all its descendants, e.g. a service or a login session, so that
+    a program spread over many small processes still shows up. No target pid.
+
+MEASURE MODE:
+    memimpact [--measure] [options] -- <command> [args...]
+    Run the command, monitor its tree until it exits, then print one line on
+    stderr: \"peak=1.8GiB avg=900MiB duration=42.0s exit=0\", avg being the
+    time-weighted average. The input and outputs of the command are its own,
+    the samples are only written with --output-file. memimpact exits with the
+    exit code of the command, 128 + the signal if it was killed, unless it
+    failed itself. --measure is implied by the command after --.
+
+REPLAY MODE:
+    memimpact --replay <recording> [--histogram <size>] [--time-weighted-avg]
+    Read a recorded run, in the formats --diff accepts, and print the final line
+    a live run would have printed, with the --template or --format, --histogram
+    and --time-weighted-avg of the command line. Only the fields of the
+    recording are known: pid, process_name, current_bytes, max_bytes,
+    process_count and timestamp, which times --time-weighted-avg.
+
+DIFF MODE:
+    memimpact --diff <baseline> <candidate>
+    Compare two recorded runs and print the change of max and average memory,
+    and of the peak process count. A recording is either JSON lines with the
+    current_bytes key, or CSV with a current_bytes column in its header row.
+    timestamp, max_bytes, process_count and final keys/columns are optional,
+    lines whose final is true are ignored. For instance, record with:
+    --template '{{{{\"current_bytes\":{{CurrentBytes}},\"process_count\":{{ProcessCount}},\"final\":{{Final}}}}}}\\n'
+
+NAME MODE:
     --name monitors all processes whose command name matches the provided
     string. Use with care: unrelated processes with the same name will be
     aggregated.
 
+CGROUP MODE:
+    --cgroup-procs <path> monitors every process of a cgroup v2 and of its child
+    cgroups, read from their cgroup.procs files at startup, with all their
+    descendants: a whole systemd service or slice without knowing its main pid.
+    <path> is a directory under /sys/fs/cgroup, or a cgroup path such as
+    /system.slice/nginx.service (see /proc/<pid>/cgroup).
+
+CMDLINE MODE:
+    --match-cmdline reads /proc/<pid>/cmdline, arguments joined by spaces, and
+    selects every process it matches. The pattern is unanchored and supports
+    . ^ $ ( ) | * + ? {{n,m}} [classes] \\d \\w \\s. Unlike --name, it can tell
+    apart processes sharing the same command name, e.g. 'java -jar worker\\.jar'.
+    Only the first 64KiB of a command line are matched.
+
+SEARCH MODE:
+    --search <text> sums the memory of every process whose command name
+    contains <text>, ignoring case, e.g. --search chrom for all the browser
+    processes. The processes are listed again at every sample, so the ones
+    started during the run are counted too, and the run does not end when none
+    matches: a warning is printed and memimpact waits for the next one. Only the
+    matching processes are measured; add --search-tree to include all their
+    descendants, whatever their names. No pid can be given with --search.
+    --search-regex <regex> selects the names matching a regular expression
+    instead, with the syntax of --match-cmdline and unanchored, case included:
+    --search-regex '^python3(\\.\\d+)?$' for python3 and python3.12 but not
+    python3-config.
+
 TEMPLATE FIELDS:
     {{Pid}}            Process ID
     {{ProcessName}}    Command name
@@ -466,390 +1659,2156 @@ TEMPLATE FIELDS:
     {{MaxBytes}}       Maximum RSS observed in bytes
     {{CurrentHuman}}   Current RSS in human-readable IEC format
     {{MaxHuman}}       Maximum RSS in human-readable IEC format
-    {{Timestamp}}      Unix timestamp (seconds since epoch)
+    {{Delta}}          Signed change of CurrentBytes since the previous measurement,
+                       like +4096 or -512, +0 on the first sample. Per process
+                       with --per-pid-stream, which keeps no history: +0
+    {{DeltaHuman}}     Same as Delta in human-readable IEC format, like -512KiB
+    {{CurrentPercent}} CurrentBytes as a percentage of the RAM (MemTotal of
+                       /proc/meminfo) with one decimal, like 3.2%, at most 100.0%
+    {{MaxPercent}}     Same for MaxBytes
+    {{PercentOfMax}}   CurrentBytes as a percentage of MaxBytes, 100.00 at a new
+                       peak (n/a while nothing was measured)
+    {{Timestamp}}      Unix timestamp (seconds since epoch), minus --epoch-offset
+    {{Iso8601}}        Timestamp as a UTC date and time, 2023-11-14T22:13:20Z.
+                       Counted from 1970 with --epoch-offset or --relative-to-start
+    {{Private}}        Resident memory not shared with other processes, summed
+                       per process. An approximation of the USS.
+    {{PrivateHuman}}   Same as Private in human-readable IEC format
+    {{ProcessCount}}   Number of processes in the tree
+    {{SampleNumber}}   Number of the sample, 1 for the first one
+    {{RssAnon}}        Anonymous resident memory (heap, stacks) in bytes, summed
+                       from /proc/[pid]/status. n/a on kernels before 4.5.
+    {{RssFile}}        File-backed resident memory (mmap'd files, libraries) in bytes
+    {{RssShmem}}       Resident shared memory (shm, tmpfs) in bytes
+    {{TimeWeightedAvg}} Mean RSS weighted by time (with --time-weighted-avg,
+                       n/a otherwise)
+    {{MapCount}}       Memory mappings (lines of /proc/[pid]/maps) summed over the
+                       tree (with --map-count, n/a otherwise)
+    {{HugePages}}      Explicit hugepages in bytes summed over the tree, not part of
+                       the RSS (with --hugepages, n/a otherwise)
+    {{Swap}}           Swapped out memory in bytes summed over the tree, not part of
+                       the RSS (with --swap, n/a otherwise)
+    {{SwapHuman}}      Same as Swap in human-readable IEC format
+    {{Uss}}            Unique set size (Private_Clean + Private_Dirty of smaps_rollup)
+                       in bytes, summed over the tree, read for --metric uss or
+                       when the template uses it
+    {{UssHuman}}       Same as Uss in human-readable IEC format
+    {{Pss}}            Proportional set size (Pss of smaps_rollup, or of smaps) in
+                       bytes, summed over the processes which could be read, read
+                       for --metric pss or when the template uses it
+    {{PssHuman}}       Same as Pss in human-readable IEC format
+    {{Vsz}}            Virtual memory size in bytes summed over the tree, from the
+                       first field of /proc/[pid]/statm
+    {{VszHuman}}       Same as Vsz in human-readable IEC format
+    {{MaxPrivate}} {{MaxRssAnon}} {{MaxRssFile}} {{MaxRssShmem}} {{MaxMapCount}}
+    {{MaxUss}} {{MaxVsz}} {{MaxPss}} {{MaxSwap}} {{MaxHugePages}}
+                       Maximum of each metric over the run. They peak at their
+                       own time, not necessarily with the RSS. With one of them,
+                       --map-count, --swap, --hugepages or --metric uss|vsz|pss,
+                       the summary ends with a \"peaks:\" line.
+    {{Final}}          true on the trailing summary line, false otherwise
+    {{MemPressure}}    PSI \"some\" avg10 percentage (with --psi, n/a otherwise)
+    {{MemPressureFull}} PSI \"full\" avg10 percentage (with --psi, n/a otherwise)
 
 EXAMPLE TEMPLATE (JSON line):
     '{{{{\"pid\":{{Pid}},\"name\":\"{{ProcessName}}\",\"ts\":{{Timestamp}},\"rss\":{{CurrentBytes}} }}}}\\n'
 
+EXIT CODES:
+    0    success, including a stop on SIGINT or SIGTERM (Ctrl-C), which still
+         prints the final line
+    1    any other failure, e.g. the output could not be opened
+    2    invalid arguments or template
+    3    no target process, or the target exited with --fail-if-absent
+    4    the peak went above --threshold, or --baseline plus --tolerance; not
+         3, which already tells a missing target
+    5    the target was killed by --kill-on-threshold
+    6    no sample completed within --watchdog
+    7    a process of the tree was killed by the kernel OOM killer, as told by
+         /dev/kmsg (root or kernel.dmesg_restrict=0); without access to it, a
+         warning only says that the system killed a process during the run
+    130, 143
+         a second SIGINT or SIGTERM while stopping: memimpact exits at once,
+         without the final line
+
 NOTES:
     • Memory is sampled, not continuously traced — short spikes may be missed.
+    • On a terminal, the final line is followed by a sparkline of the memory
+      over the run, as wide as the terminal.
+    • When the host is too loaded to keep up with --hertz, a warning tells the
+      actual interval, and the effective one is printed on stderr at exit.
     • RSS reflects resident memory only.
     • Linux only.
 
-Version: {}",
-			version
-		);
-    	process::exit(0);
+Version: {}",
+			version
+		);
+    	return Ok(());
+    }
+    if args.list_fields_flag{
+        print!("{}", template_engine::list_fields());
+        return Ok(());
+    }
+    if args.version_flag{
+    	let version = env!("CARGO_PKG_VERSION");
+    	println!("{}", 	version);
+    	return Ok(());
+    }
+    if let Some((baseline, candidate)) = &args.diff_files{
+        print!("{}", run_diff(baseline, candidate, args.precision).map_err(MemimpactError::Diff)?);
+        return Ok(());
+    }
+    if let Some(path) = &args.replay_file{
+        let mut out = io::stdout();
+        return run_replay(path, &args, &mut out);
+    }
+    
+	let sleep_duration: u64 = 1000 / args.hz;
+    let page_size = page_size(&args)?;
+    if let Some(count) = args.top{
+        run_top(count, &args, sleep_duration, page_size);
+    }
+    let measured = if args.measure_flag{
+        let started = Instant::now();
+        let mut child = spawn_measured(&args.command)?;
+        args.target_pids = vec![child.id() as i32];
+        // a child exited but not reaped stays in /proc as a zombie, the loop would never end;
+        // the duration is the one of the command, not until the next sample notices its end
+        Some(thread::spawn(move || child.wait().map(|status| (status, started.elapsed()))))
+    } else {
+        None
+    };
+
+    if let Some(namespace) = args.pid_namespace{
+        let namespace = match namespace {
+            PidNamespace::Auto => None,
+            PidNamespace::Id(id) => Some(id),
+        };
+        for pid in args.target_pids.iter_mut(){
+            *pid = translate_namespace_pid(*pid, namespace).map_err(MemimpactError::PidNamespace)?;
+        }
+        confirm_pid_1(&args.target_pids, args.all_flag).map_err(MemimpactError::Args)?;
+    }
+
+    if let Some(name) = &args.wait_for{
+        args.target_pids = vec![wait_for_process(name, args.wait_timeout, sleep_duration)?];
+        // the lowest pid with the name can be init
+        confirm_pid_1(&args.target_pids, args.all_flag).map_err(MemimpactError::Args)?;
+    }
+    let process_name = if let Some(search) = &args.search{
+        // the matches change from one sample to the next, the lines are about the search
+        args.target_pids = get_pids_from_search(search, process::id() as i32);
+        search.text().to_string()
+    } else {
+        let Some(first_pid) = args.target_pids.first() else {
+            return Err(MemimpactError::NoMatchingProcess);
+        };
+        match get_process_name(first_pid) {
+            Ok(name) => display_name(name, args.keep_parens_flag),
+            // a command can be done before its name is read
+            Err(_) if args.measure_flag => args.command[0].rsplit('/').next().unwrap_or_default().to_string(),
+            Err(e) => return Err(e),
+        }
+    };
+
+	let mut template = load_template(&args)
+	    .map_err(|msg| MemimpactError::Template(if args.template_file.is_some() { "template-file" } else { "template" }, msg))?;
+	template.precision = args.precision;
+
+	let stderr_template = args.template_stderr.as_deref().map(parse_template).transpose()
+	    .map_err(|msg| MemimpactError::Template("template-stderr", msg))?
+	    .map(|mut t| {
+	        t.precision = args.precision;
+	        t
+	    });
+	let end_marker = args.end_marker.as_deref().map(parse_template).transpose()
+	    .map_err(|msg| MemimpactError::Template("end-marker", msg))?
+	    .map(|mut t| {
+	        t.precision = args.precision;
+	        t
+	    });
+	let rss_fields = [
+	    template_engine::Field::RssAnon,
+	    template_engine::Field::RssFile,
+	    template_engine::Field::RssShmem,
+	    template_engine::Field::MaxRssAnon,
+	    template_engine::Field::MaxRssFile,
+	    template_engine::Field::MaxRssShmem,
+	];
+	// a template or a --fields column showing them
+	let shown = |fields: &[template_engine::Field]| template.uses_any(fields)
+	    || stderr_template.as_ref().is_some_and(|t| t.uses_any(fields))
+	    || args.fields.as_ref().is_some_and(|columns| columns.iter().any(|field| fields.contains(field)));
+	let rss_breakdown_flag = shown(&rss_fields);
+	let uss_fields = [template_engine::Field::Uss, template_engine::Field::UssHuman, template_engine::Field::MaxUss];
+	let uss_flag = args.metric == Metric::Uss || shown(&uss_fields);
+	// the VSZ is always read, shown in the peaks line when it is looked at
+	let vsz_fields = [template_engine::Field::Vsz, template_engine::Field::VszHuman, template_engine::Field::MaxVsz];
+	let vsz_flag = args.metric == Metric::Vsz || shown(&vsz_fields);
+	let pss_fields = [template_engine::Field::Pss, template_engine::Field::PssHuman, template_engine::Field::MaxPss];
+	let pss_flag = args.metric == Metric::Pss || shown(&pss_fields);
+
+	let record_format = record_format(&args, template);
+	let mut sinks = if args.measure_flag && matches!(args.output, OutputSpec::Stdout){
+	    // the stdout of the command is left to the command, only the one-liner is printed
+	    Vec::new()
+	} else {
+	    setup_sinks(args.output, args.final_flag, args.summary_only_flag).map_err(MemimpactError::Output)?
+	};
+    let no_color = no_color_env(env::var("NO_COLOR").ok().as_deref());
+    let colorize = should_colorize(args.color, no_color, io::stdout().is_terminal());
+    for sink in sinks.iter_mut().filter(|sink| matches!(sink.output, Output::Stdout(_))){
+        sink.colorize = colorize;
+    }
+    if let Some(template) = stderr_template{
+        // a human readable view next to the machine readable output
+        sinks.push(Sink{
+            output: Output::Stderr(io::stderr()),
+            verbosity: if args.final_flag { Verbosity::SummaryOnly } else { Verbosity::EverySample },
+            colorize: should_colorize(args.color, no_color, io::stderr().is_terminal()),
+            template: Some(template),
+        });
+    }
+
+	let mut emitter = Emitter::new(record_format, args.null_flag);
+	if let Some(header) = emitter.header(){
+	    // not with --final, a spreadsheet importing the summary row gets it alone
+	    for sink in sinks.iter_mut().filter(|sink| sink.verbosity == Verbosity::EverySample && sink.template.is_none()){
+	        write_output(&mut sink.output, &header);
+	    }
+	}
+
+    let mut sqlite = match &args.sqlite_path {
+        Some(path) => Some(SqliteSink::open(path).map_err(|e| MemimpactError::Sqlite(path.clone(), e))?),
+        None => None,
+    };
+
+	let epoch_offset = if args.relative_to_start_flag { now() } else { args.epoch_offset.unwrap_or(0) };
+	let mut sample = template_engine::MemorySample{
+		pid: args.target_pids.first().copied().unwrap_or(0),
+		process_name: process_name.as_str(),
+		timestamp: now().saturating_sub(epoch_offset),
+		mem_total: read_mem_total(),
+		..Default::default()
+	};
+
+    let mut missing_samples: u32 = 0;
+    let mut warned_saturation = false;
+    let mut warned_uss_fallback = false;
+    let mut warned_no_match = false;
+    let mut self_profile = SelfProfile::new();
+    let mut lag = LagDetector::default();
+    // pid 1, after --pid-ns translation: the tree is every process, no need to walk it
+    let whole_system = args.search.is_none() && args.target_pids.contains(&1);
+    // only drawn on a terminal, in place of a plot
+    let mut sparkline = (!args.no_final_flag && io::stdout().is_terminal()).then(Sparkline::new);
+    let mut interval_ms = sleep_duration;
+    let mut next_push = args.push_every.map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    let mut threshold_watch = args.max_threshold.map(|limit| ThresholdWatch::new(limit, args.on_threshold_repeat_flag));
+    let mut hooks = Vec::new();
+    let mut killed_on_threshold = false;
+    let mut target_vanished = false;
+    let mut warming_up = true;
+    let mut warmup_samples = 0;
+    let mut peak_groups = Vec::new();
+    let mut peak_folded = String::new();
+    let mut largest_process = args.largest_process_flag.then(LargestProcess::default);
+    // --pushgateway always pushes an average
+    let time_weighted_avg = args.time_weighted_avg_flag || args.measure_flag || args.pushgateway.is_some();
+    let mut run_stats = RunStats::new(args.histogram_bucket, time_weighted_avg, args.summary_json_flag);
+    let mut child_tracker = args.per_child_flag.then(ChildTracker::new);
+    let mut coalescer = args.coalesce.map(|ms| Coalescer::new(Duration::from_millis(ms)));
+    let mut report_clock = args.report_every.map(|ms| ReportClock::new(Duration::from_millis(ms)));
+    let mut change_filter = args.on_change_flag.then(|| ChangeFilter::new(args.change_threshold));
+    let mut oom_watch = OomWatch::start();
+    let self_pid = process::id() as i32;
+    let mut reader = ProcReader::new();
+    // the identity of the targets, their pids alone could be reused by other processes;
+    // --search finds its targets again at every sample
+    let mut start_times: Vec<(i32, Option<u64>)> = args.target_pids.iter()
+        .filter(|_| args.search.is_none())
+        .map(|pid| (*pid, read_start_time(&mut reader, *pid)))
+        .collect();
+    sys::catch_snapshot_signal();
+    let watchdog = args.watchdog.map(|seconds| Watchdog::spawn(Duration::from_secs(seconds)));
+    // Ctrl-C ends the run with its summary instead of losing the measurement,
+    // and a --gzip file gets its trailer
+    sys::catch_shutdown_signals();
+    loop {
+        if sys::shutdown_requested(){
+            break;
+        }
+        if let Some(watchdog) = &watchdog{
+            // the previous iteration, its sample and its sleep, completed
+            watchdog.feed();
+        }
+        let work_started = Instant::now();
+        if let Some(actual) = lag.record(work_started, Duration::from_millis(interval_ms)){
+            eprintln!(
+                "memimpact warning: sampling falling behind: requested {}ms, actual ~{}ms, short peaks can be missed",
+                interval_ms,
+                actual.as_millis(),
+            );
+        }
+        let mapping = get_map_pid_to_ppid(&mut reader);
+        if let Some(search) = &args.search{
+            args.target_pids = get_pids_from_search(search, self_pid);
+            if args.no_kernel_threads_flag{
+                args.target_pids.retain(|pid| !is_kernel_thread(*pid, &mapping));
+            }
+            let Some(first_pid) = args.target_pids.first() else {
+                // nothing to measure yet, or anymore: the run goes on until one shows up
+                if !warned_no_match{
+                    eprintln!("memimpact warning: no process name {}, waiting for one", search.describe());
+                    warned_no_match = true;
+                }
+                sys::sleep_unless_shutdown(Duration::from_millis(sleep_duration));
+                continue;
+            };
+            warned_no_match = false;
+            sample.pid = *first_pid;
+        }
+        if !targets_alive(&mapping, &args.target_pids){
+            // a single scan can miss a process during fork/exec, wait for the grace period
+            missing_samples += 1;
+            if missing_samples >= args.grace{
+                target_vanished = true;
+                break;
+            }
+            sys::sleep_unless_shutdown(Duration::from_millis(sleep_duration));
+            continue;
+        }
+        missing_samples = 0;
+        let reused: Vec<i32> = start_times.iter()
+            .filter(|(pid, start_time)| is_pid_reused(*start_time, read_start_time(&mut reader, *pid)))
+            .map(|(pid, _)| *pid)
+            .collect();
+        if !reused.is_empty(){
+            // the target exited and another process got its pid between two samples
+            args.target_pids.retain(|pid| !reused.contains(pid));
+            start_times.retain(|(pid, _)| !reused.contains(pid));
+            if args.target_pids.is_empty(){
+                target_vanished = true;
+                break;
+            }
+        }
+        let children_of = build_children_index(&mapping);
+        let mut target_descendants = if whole_system{
+            descendants_of_init(&mapping, &children_of)
+        } else if args.search.is_some() && !args.search_tree_flag{
+            // the children of a match are counted when their own name matches
+            args.target_pids.iter().copied().collect()
+        } else {
+            find_descendants(&children_of, &args.target_pids)
+        };
+        if args.no_kernel_threads_flag{
+            target_descendants.retain(|pid| !is_kernel_thread(*pid, &mapping));
+        }
+        exclude_subtree(&mut target_descendants, &children_of, self_pid);
+        if args.include_self_flag{
+            target_descendants.insert(self_pid);
+        }
+        oom_watch.track(&target_descendants);
+        let statms: Vec<(i32, Statm)> = target_descendants.iter()
+            .filter_map(|pid| read_statm(&mut reader, pid, page_size).map(|statm| (*pid, statm)))
+            .collect();
+        let previous_bytes = sample.current_bytes;
+        let (current, current_saturated) = saturating_total(
+            statms.iter().map(|(_, statm)| statm.resident)
+        );
+        let (private, private_saturated) = saturating_total(
+            statms.iter().map(|(_, statm)| statm.private())
+        );
+        // any total which is measured and capped is worth the warning
+        let mut saturated = current_saturated || private_saturated;
+        let uss = uss_flag.then(|| {
+            let mut fell_back = false;
+            let (uss, uss_saturated) = saturating_total(statms.iter().map(|(pid, statm)| {
+                read_uss(&mut reader, pid).unwrap_or_else(|| {
+                    fell_back = true;
+                    statm.resident
+                })
+            }));
+            if fell_back && !warned_uss_fallback{
+                eprintln!("memimpact warning: smaps_rollup could not be read (Linux < 4.14 or no ptrace access), the RSS is used for the USS");
+                warned_uss_fallback = true;
+            }
+            saturated |= uss_saturated;
+            uss
+        });
+        let pss = pss_flag.then(|| {
+            // unlike the USS no fallback: the RSS would count the shared pages again
+            let (pss, pss_saturated) = saturating_total(statms.iter().filter_map(|(pid, _)| read_pss(&mut reader, pid)));
+            saturated |= pss_saturated;
+            pss
+        });
+        let (vsz, vsz_saturated) = saturating_total(statms.iter().map(|(_, statm)| statm.size));
+        // always read, but only a VSZ which is looked at is worth the warning
+        saturated |= vsz_saturated && vsz_flag;
+        let resident = current;
+        let current = match (args.metric, uss, pss) {
+            (Metric::Uss, Some(uss), _) => uss,
+            (Metric::Pss, _, Some(pss)) => pss,
+            (Metric::Vsz, _, _) => vsz,
+            _ => current,
+        };
+        if warming_up{
+            // right after a fork, or while exec'ing, the tree can have no resident memory yet:
+            // a 0 there is not a measurement, wait for the first one.
+            // Only the RSS says so: a PSS or USS stays 0 without ptrace access
+            if still_warming_up(resident, warmup_samples){
+                if args.log_warmup_flag{
+                    eprintln!("memimpact: warming up, the tree has no resident memory yet");
+                }
+                warmup_samples += 1;
+                sys::sleep_unless_shutdown(Duration::from_millis(sleep_duration));
+                continue;
+            }
+            if resident == 0{
+                // a kernel thread, or a tree that never maps anything
+                eprintln!("memimpact warning: still no resident memory after {} samples, measuring anyway", MAX_WARMUP_SAMPLES);
+            }
+            warming_up = false;
+        }
+        sample.next_sample();
+        sample.current_bytes = current;
+        sample.private_bytes = private;
+        sample.vsz = vsz;
+        sample.uss = uss;
+        sample.pss = pss;
+        sample.process_count = target_descendants.len() as u64;
+        if args.map_count_flag{
+            // a process which exited since statm was read counts for nothing
+            let counts = statms.iter().filter_map(|(pid, _)| reader.count_lines(*pid, "maps"));
+            sample.map_count = Some(saturating_total(counts).0);
+        }
+        if args.hugepages_flag{
+            // explicit hugepages are reserved apart and never counted in the RSS,
+            // a database tuned with them looks far smaller than it is
+            let huge_pages = statms.iter().filter_map(|(pid, _)| read_hugetlb(&mut reader, pid));
+            sample.huge_pages = Some(saturating_total(huge_pages).0);
+        }
+        if args.swap_flag{
+            // swapped out memory left the RSS, a tree close to an OOM kill can look like it shrinks
+            let swap = statms.iter().filter_map(|(pid, _)| read_swap(&mut reader, pid));
+            let (swap, swap_saturated) = saturating_total(swap);
+            saturated |= swap_saturated;
+            sample.swap = Some(swap);
+        }
+        if saturated && !warned_saturation{
+            eprintln!("memimpact warning: memory total exceeds u64, values are capped at {}", u64::MAX);
+            warned_saturation = true;
+        }
+        if rss_breakdown_flag{
+            // processes which exited since statm was read, or kernels without these lines, are skipped
+            let breakdowns: Vec<RssBreakdown> = statms.iter().filter_map(|(pid, _)| read_rss_breakdown(&mut reader, pid)).collect();
+            let available = !breakdowns.is_empty();
+            sample.rss_anon = available.then(|| saturating_total(breakdowns.iter().map(|b| b.anon)).0);
+            sample.rss_file = available.then(|| saturating_total(breakdowns.iter().map(|b| b.file)).0);
+            sample.rss_shmem = available.then(|| saturating_total(breakdowns.iter().map(|b| b.shmem)).0);
+        }
+        if args.group_by_name_flag && sample.current_bytes > sample.max_bytes{
+            // names are only read at a new peak, it is the breakdown printed at exit
+            peak_groups = breakdown::group_by_name(&named_processes(&statms, args.keep_parens_flag));
+        }
+        if args.folded_file.is_some() && sample.current_bytes > sample.max_bytes{
+            let processes: Vec<(i32, u64)> = statms.iter().map(|(pid, statm)| (*pid, statm.resident)).collect();
+            peak_folded = breakdown::fold_tree(&processes, &mapping, |pid| {
+                get_process_name(&pid).map(|name| display_name(name, args.keep_parens_flag)).unwrap_or_else(|_| "?".to_string())
+            });
+        }
+        if let Some(largest) = largest_process.as_mut(){
+            let processes: Vec<(i32, u64)> = statms.iter().map(|(pid, statm)| (*pid, statm.resident)).collect();
+            largest.offer(&processes, |pid| get_process_name(&pid).ok().map(|name| display_name(name, args.keep_parens_flag)));
+        }
+        sample.update_peaks();
+        let stopped = args.skip_stopped_flag && args.target_pids.iter().all(is_stopped);
+        run_stats.record(&mut sample, Instant::now(), stopped);
+        if let Some(sparkline) = sparkline.as_mut(){
+            sparkline.record(sample.current_bytes);
+        }
+        sample.timestamp = now().saturating_sub(epoch_offset);
+        if args.psi_flag{
+            let pressure = read_memory_pressure(&args.target_pids[0]);
+            sample.mem_pressure_some = pressure.as_ref().map(|p| p.some_avg10);
+            sample.mem_pressure_full = pressure.as_ref().map(|p| p.full_avg10);
+        }
+		if let Some(tracker) = child_tracker.as_mut(){
+		    let by_pid: HashMap<i32, &Statm> = statms.iter().map(|(pid, statm)| (*pid, statm)).collect();
+		    let ended = tracker.update(&children_of, &args.target_pids, &by_pid, |pid| {
+		        display_name(get_process_name(&pid).unwrap_or_else(|_| "?".to_string()), args.keep_parens_flag)
+		    });
+		    for stream in ended{
+		        for sink in sinks.iter_mut().filter(|s| s.verbosity == Verbosity::EverySample){
+		            emitter.emit_to(sink, &stream.sample(&sample, true));
+		        }
+		        eprintln!("{}", stream.ended_report(args.precision));
+		    }
+		}
+		if sys::take_snapshot_request(){
+		    let mut snapshot = Vec::new();
+		    emitter.emit(&mut snapshot, &sample, None, false);
+		    if args.snapshot_breakdown_flag{
+		        let groups = breakdown::group_by_name(&named_processes(&statms, args.keep_parens_flag));
+		        snapshot.extend_from_slice(breakdown::format_groups("snapshot", &groups, args.precision).as_bytes());
+		    }
+		    if let Err(e) = write_snapshot(args.snapshot_file.as_deref(), &snapshot){
+		        eprintln!("memimpact warning: could not write the snapshot: {}", e);
+		    }
+		}
+		let report = report_clock.as_mut().is_none_or(|clock| clock.due(Instant::now()))
+		    && change_filter.as_mut().is_none_or(|filter| filter.changed(sample.current_bytes));
+		// with --per-child, the children replace the total
+		let per_pid: Vec<(i32, String, &Statm)> = if args.per_pid_stream_flag && report {
+		    let mut per_pid: Vec<(i32, String, &Statm)> = statms.iter().map(|(pid, statm)| {
+		        (*pid, display_name(get_process_name(pid).unwrap_or_else(|_| "?".to_string()), args.keep_parens_flag), statm)
+		    }).collect();
+		    per_pid.sort_by_key(|(pid, _, _)| *pid);
+		    per_pid
+		} else {
+		    Vec::new()
+		};
+		let records: Vec<template_engine::MemorySample> = match (&mut coalescer, &child_tracker) {
+		    (Some(coalescer), _) => coalescer.offer(sample.clone(), sample.current_bytes, Instant::now()).into_iter().collect(),
+		    _ if !report => Vec::new(),
+		    _ if args.per_pid_stream_flag => per_pid.iter().map(|(pid, name, statm)| template_engine::MemorySample{
+		        // the max stays the one of the tree, a pid has no history of its own here
+		        pid: *pid,
+		        process_name: name,
+		        current_bytes: statm.resident,
+		        max_bytes: sample.max_bytes,
+		        private_bytes: statm.private(),
+		        process_count: 1,
+		        timestamp: sample.timestamp,
+		        sample_number: sample.sample_number,
+		        ..Default::default()
+		    }).collect(),
+		    (None, Some(tracker)) => tracker.streams().map(|stream| stream.sample(&sample, false)).collect(),
+		    (None, None) => vec![sample.clone()],
+		};
+		for record in &records{
+			for sink in sinks.iter_mut().filter(|s| s.verbosity == Verbosity::EverySample){
+				emitter.emit_to(sink, record);
+			}
+			if let Some(db) = sqlite.as_mut()
+			    && let Err(e) = db.insert(record){
+			        eprintln!("memimpact warning: sqlite output disabled: {}", e);
+			        sqlite = None;
+			    }
+		}
+		if let (Some(gateway), Some(at)) = (&args.pushgateway, next_push.as_mut())
+		    && Instant::now() >= *at{
+		        push_metrics(gateway, &sample);
+		        *at = Instant::now() + Duration::from_secs(args.push_every.unwrap_or(0));
+		    }
+		let crossed = match threshold_watch.as_mut() {
+		    Some(watch) => watch.crossed(sample.current_bytes),
+		    None => false,
+		};
+		if crossed
+		    && let Some(command) = &args.on_threshold
+		    && let Some(child) = spawn_hook(
+		        command,
+		        sample.pid,
+		        sample.current_bytes,
+		        sample.max_bytes,
+		        args.max_threshold.unwrap_or(0),
+		    ){
+		        hooks.push(child);
+		    }
+		reap_hooks(&mut hooks);
+		if crossed && args.kill_on_threshold_flag{
+		    let mut victims: Vec<i32> = if args.kill_tree_flag {
+		        target_descendants.iter().copied().collect()
+		    } else {
+		        args.target_pids.clone()
+		    };
+		    victims.sort();
+		    let killed = terminate(&victims, Duration::from_secs(args.kill_grace));
+		    eprintln!(
+		        "memimpact: memory went above the threshold ({} > {}), sent SIGTERM to {:?}{}",
+		        template_engine::format_memory(sample.current_bytes, args.precision),
+		        template_engine::format_memory(args.max_threshold.unwrap_or(0), args.precision),
+		        victims,
+		        if killed.is_empty() { String::new() } else { format!(" and SIGKILL to {:?}", killed) },
+		    );
+		    killed_on_threshold = true;
+		    break;
+		}
+		self_profile.record(work_started.elapsed());
+		if args.adaptive_flag{
+		    interval_ms = next_adaptive_interval(sleep_duration, interval_ms, previous_bytes, sample.current_bytes);
+		}
+		
+        if args.align_to_clock_flag{
+            let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+            sys::sleep_unless_shutdown(aligned_sleep(interval_ms, since_epoch));
+        } else {
+            sys::sleep_unless_shutdown(Duration::from_millis(interval_ms));
+        }
+    }
+    sample.max_bytes = sample.max_bytes.max(sample.current_bytes);
+    if let Some(peak) = coalescer.as_mut().and_then(|c| c.flush()){
+        // the last window is cut short by the exit, its peak is still written
+        for sink in sinks.iter_mut().filter(|s| s.verbosity == Verbosity::EverySample){
+            emitter.emit_to(sink, &peak);
+        }
+        if let Some(db) = sqlite.as_mut()
+            && let Err(e) = db.insert(&peak){
+                eprintln!("memimpact warning: sqlite output disabled: {}", e);
+                sqlite = None;
+            }
+    }
+    if let Some(effective) = lag.effective_interval(){
+        eprintln!("memimpact: effective sampling interval ~{}ms, requested {}ms", effective.as_millis(), sleep_duration);
+    }
+    if args.self_profile_flag{
+        eprint!("{}", self_profile.report());
+    }
+    // the measurement is complete either way, the exit code tells how it ended
+    let oom_kill = oom_watch.killed();
+    if oom_kill.is_none() && target_vanished && oom_watch.system_killed_something(){
+        eprintln!("memimpact warning: the system OOM-killed a process while the tree was monitored, the target may have been one of them");
+    }
+    let outcome = if killed_on_threshold {
+        Err(MemimpactError::KilledOnThreshold)
+    } else if let Some(kill) = oom_kill {
+        Err(MemimpactError::OomKilled(kill.pid, kill.name, sample.max_bytes))
+    } else if args.baseline.is_some() && breached(sample.max_bytes, args.max_threshold) {
+        Err(MemimpactError::AboveBaseline(sample.max_bytes, args.max_threshold.unwrap_or(0)))
+    } else if let Some(limit) = args.threshold
+        && breached(sample.max_bytes, Some(limit)) {
+        Err(MemimpactError::AboveThreshold(sample.max_bytes, limit))
+    } else if target_vanished && args.fail_if_absent_flag {
+        Err(MemimpactError::TargetExited)
+    } else {
+        Ok(())
+    };
+    if let Some(gateway) = &args.pushgateway{
+        push_metrics(gateway, &sample);
+    }
+    let command_exit = measured.map(|reaper| {
+        let (exit, duration) = match reaper.join() {
+            Ok(Ok((status, duration))) => (exit_code_of(status), duration),
+            _ => (ExitCode::Failure as i32, Duration::ZERO),
+        };
+        eprintln!("{}", measure_line(&sample, duration, exit, args.precision));
+        exit
+    });
+    let end_marker = end_marker.map(|t| {
+        let mut rendered = String::new();
+        let _ = t.render(&template_engine::MemorySample{is_final: true, ..sample.clone()}, &mut rendered);
+        rendered
+    });
+    if let Some(path) = &args.folded_file
+        && let Err(e) = fs::write(path, &peak_folded){
+            eprintln!("memimpact warning: could not write the folded tree to {}: {}", path.display(), e);
+        }
+    if args.no_final_flag{
+        if let Some(mut db) = sqlite
+            && let Err(e) = db.finish(){
+                eprintln!("memimpact warning: could not complete the sqlite output: {}", e);
+            }
+        write_summary_json(args.summary_json_file.as_deref(), &run_stats, sample.max_bytes);
+        finish_sinks(&mut sinks, end_marker.as_deref());
+        exit_like_command(command_exit, &outcome);
+        return outcome;
+    }
+    sample.is_final = true;
+	if let Some(tracker) = &child_tracker{
+	    for stream in tracker.streams(){
+	        for sink in sinks.iter_mut(){
+	            emitter.emit_to(sink, &stream.sample(&sample, true));
+	        }
+	    }
+	}
+	for sink in sinks.iter_mut(){
+		emitter.emit_to(sink, &sample);
+		if args.format == FormatKind::Text{
+		    // a line of text would break the records of a structured format
+		    write_output(&mut sink.output, &run_stats.stats.format(args.precision));
+		}
+		if args.group_by_name_flag{
+		    write_output(&mut sink.output, &breakdown::format_groups("peak", &peak_groups, args.precision));
+		}
+		if let Some(largest) = &largest_process{
+		    write_output(&mut sink.output, &largest.format(args.precision));
+		}
+		if args.map_count_flag || rss_breakdown_flag || uss_flag || vsz_flag || pss_flag || args.swap_flag || args.hugepages_flag{
+		    // only once a metric besides the RSS is measured, the default summary is unchanged
+		    write_output(&mut sink.output, &breakdown::format_peaks(&sample.peaks, args.precision));
+		}
+		if let Some(histogram) = &run_stats.histogram{
+		    write_output(&mut sink.output, &histogram.format(args.precision));
+		}
+		if let Some(sparkline) = &sparkline
+		    && matches!(sink.output, Output::Stdout(_)){
+		        let width = sys::terminal_width().unwrap_or(80);
+		        write_output(&mut sink.output, &format!("{}\n", sparkline.render(width)));
+		    }
+	}
+	if let Some(mut db) = sqlite
+	    && let Err(e) = db.insert(&sample).and_then(|_| db.finish()){
+	        eprintln!("memimpact warning: could not complete the sqlite output: {}", e);
+	    }
+	write_summary_json(args.summary_json_file.as_deref(), &run_stats, sample.max_bytes);
+	finish_sinks(&mut sinks, end_marker.as_deref());
+	exit_like_command(command_exit, &outcome);
+	outcome
+}
+
+
+// Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template_engine::format_memory;
+
+    #[test]
+    fn test_format_memory_kb() {
+        assert_eq!(format_memory(512 * 1024, 0), "512KiB");
+    }
+
+    #[test]
+    fn test_format_memory_mb() {
+        assert_eq!(format_memory(2 * 1024 * 1024, 0), "2MiB");
+    }
+
+    #[test]
+    fn test_format_memory_gb() {
+        assert_eq!(format_memory(2 * 1024 * 1024 * 1024, 0), "2GiB");
+    }
+
+    #[test]
+    fn test_format_memory_rounding_behavior() {
+        assert_eq!(format_memory(1536 * 1024, 0), "2MiB");
+        assert_eq!(format_memory(1535 * 1024, 0), "1MiB");
+    }
+
+    #[test]
+    fn test_format_memory_max() {
+        assert_eq!(format_memory(u64::MAX, 0), "16EiB");
+    }
+
+    fn args(input: &[&str]) -> Vec<String> { // to avoid to add .to_string in following argument tests
+        input.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn minimal_valid_args() {
+        let argv = args(&["memimpact", "1234"]);
+        let parsed = parse_args(&argv).unwrap();
+
+        assert!(!parsed.help_flag);
+        assert!(!parsed.final_flag);
+        assert!(!parsed.no_final_flag);
+        assert_eq!(parsed.hz, 1);
+        matches!(parsed.output, OutputSpec::Stdout);
+        assert_eq!(parsed.target_pids, vec![1234]);
+    }
+
+    #[test]
+    fn full_valid_args() {
+        let argv = args(&[
+            "memimpact",
+            "--hertz", "10",
+            "--output-file", "out.txt",
+            "--final",
+            "4321",
+        ]);
+
+        let parsed = parse_args(&argv).unwrap();
+
+        assert!(parsed.final_flag);
+        assert!(!parsed.help_flag);
+        assert_eq!(parsed.hz, 10);
+        assert_eq!(parsed.target_pids, vec![4321]);
+
+        match parsed.output {
+            OutputSpec::File(path) => assert_eq!(path, PathBuf::from("out.txt")),
+            _ => panic!("expected file output"),
+        }
+    }
+
+    #[test]
+    fn help_flag_only() {
+        let argv = args(&["memimpact", "--help"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.help_flag);
+    }
+
+     #[test]
+    fn version_flag_only() {
+        let argv = args(&["memimpact", "--version"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.version_flag);
+    }
+
+    #[test]
+    fn help_flag_only_short() {
+        let argv = args(&["memimpact", "-h"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.help_flag);
+    }
+
+     #[test]
+    fn version_flag_only_short() {
+        let argv = args(&["memimpact", "-v"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.version_flag);
+    }
+
+    #[test]
+    fn hertz_value_missing_pid() {
+        let argv = args(&["memimpact", "--hertz", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::MissingValue("pid") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn missing_hertz_value() {
+        let argv = args(&["memimpact", "1234", "--hertz"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::MissingValue("hertz") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+
+    #[test]
+    fn invalid_hertz_value() {
+        let argv = args(&["memimpact", "--hertz", "abc", "123"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("hertz") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn zero_hertz_is_invalid() {
+        let argv = args(&["memimpact", "--hertz", "0", "123"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("hertz") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn missing_output_file_value() {
+        let argv = args(&["memimpact", "1234", "--output-file"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::MissingValue("output-file") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn invalid_pid() {
+        let argv = args(&["memimpact", "not_a_pid"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("pid") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn missing_pid() {
+        let argv = args(&["memimpact", "--final"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::MissingValue("pid") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn realistic_mixed_order() {
+        let argv = args(&[
+            "memimpact",
+            "--final",
+            "5678",
+            "--hertz", "5",
+        ]);
+
+        let parsed = parse_args(&argv).unwrap();
+
+        assert!(parsed.final_flag);
+        assert_eq!(parsed.hz, 5);
+        assert_eq!(parsed.target_pids, vec![5678]);
+    }
+
+    #[test]
+    fn realistic_order() {
+        let argv = args(&[
+            "memimpact",
+            "--final",
+            "--hertz", "5",
+            "5678",
+        ]);
+
+        let parsed = parse_args(&argv).unwrap();
+
+        assert!(parsed.final_flag);
+        assert_eq!(parsed.hz, 5);
+        assert_eq!(parsed.target_pids, vec![5678]);
+    }
+
+    #[test]
+    fn no_final_flag() {
+        let argv = args(&["memimpact", "--no-final", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.no_final_flag);
+        assert!(!parsed.final_flag);
+    }
+
+    #[test]
+    fn final_and_no_final_conflict() {
+        let argv = args(&["memimpact", "--final", "--no-final", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("final", "no-final") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn grace_value() {
+        let argv = args(&["memimpact", "--grace", "3", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.grace, 3);
+    }
+
+    #[test]
+    fn zero_grace_is_invalid() {
+        let argv = args(&["memimpact", "--grace", "0", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("grace") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn summary_only_flag() {
+        let argv = args(&["memimpact", "--summary-only", "--output-file", "out.txt", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.summary_only_flag);
+        assert!(!parsed.final_flag);
+    }
+
+    #[test]
+    fn summary_only_and_no_final_conflict() {
+        let argv = args(&["memimpact", "--summary-only", "--no-final", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("summary-only", "no-final") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn psi_flag_extends_default_template() {
+        let argv = args(&["memimpact", "--psi", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.psi_flag);
+        assert!(default_template(&parsed).contains("{MemPressure}"));
+    }
+
+    #[test]
+    fn psi_flag_keeps_user_template() {
+        let argv = args(&["memimpact", "--psi", "--template", "{Pid}", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.template_string.as_deref(), Some("{Pid}"));
+    }
+
+    #[test]
+    fn template_and_template_file_conflict() {
+        let argv = args(&["memimpact", "--template", "{Pid}", "--template-file", "t.txt", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("template", "template-file") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn load_template_from_file() {
+        let path = env::temp_dir().join(format!("memimpact-template-{}.txt", process::id()));
+        fs::write(&path, "{Pid}\\t{MaxBytes}\\n").unwrap();
+        let argv = args(&["memimpact", "--template-file", path.to_str().unwrap(), "1234"]);
+        let parsed = parse_args(&argv).unwrap();
+
+        let template = load_template(&parsed);
+        fs::remove_file(&path).unwrap();
+
+        let sample = template_engine::MemorySample{
+            pid: 1234,
+            process_name: "(bash)",
+            max_bytes: 2048,
+            ..Default::default()
+        };
+        let mut out = String::new();
+        template.unwrap().render(&sample, &mut out).unwrap();
+        assert_eq!(out, "1234\t2048\n");
+    }
+
+    #[test]
+    fn template_option_end_to_end() {
+        // the escapes of the command line are applied, the sizes are in bytes
+        let argv = args(&["memimpact", "--template", "{ProcessName}\\t{CurrentBytes}\\t{MaxHuman}\\n", "1234"]);
+        let parsed = parse_args(&argv).unwrap();
+        let template = load_template(&parsed).unwrap();
+
+        let sample = template_engine::MemorySample{
+            pid: 1234,
+            process_name: "bash",
+            current_bytes: 512,
+            max_bytes: 3 * 1024,
+            ..Default::default()
+        };
+        let mut out = String::new();
+        template.render(&sample, &mut out).unwrap();
+        assert_eq!(out, "bash\t512\t3KiB\n");
+    }
+
+    #[test]
+    fn load_template_missing_file() {
+        let argv = args(&["memimpact", "--template-file", "/nonexistent/memimpact.tpl", "1234"]);
+        let parsed = parse_args(&argv).unwrap();
+
+        let err = load_template(&parsed).unwrap_err();
+        assert!(err.contains("/nonexistent/memimpact.tpl"));
+    }
+
+    #[test]
+    fn adaptive_flag() {
+        let argv = args(&["memimpact", "--adaptive", "--hertz", "10", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.adaptive_flag);
+        assert_eq!(parsed.hz, 10);
+    }
+
+    #[test]
+    fn invalid_match_cmdline_pattern() {
+        let argv = args(&["memimpact", "--match-cmdline", "(unclosed"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("match-cmdline") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn name_and_match_cmdline_conflict() {
+        let argv = args(&["memimpact", "--name", "java", "--match-cmdline", "foo"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("name", "match-cmdline") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn search_args() {
+        let parsed = parse_args(&args(&["memimpact", "--search", "Chrom", "--search-tree"])).unwrap();
+        assert_eq!(parsed.search.as_ref().map(NameSearch::text), Some("Chrom"));
+        assert!(parsed.search_tree_flag);
+        assert!(parsed.target_pids.is_empty());
+
+        let expect_err = |argv: &[&str]| parse_args(&args(argv)).map(|_| ()).unwrap_err().to_string();
+        assert_eq!(expect_err(&["memimpact", "--search", "chrom", "1234"]), "invalid pid");
+        assert_eq!(expect_err(&["memimpact", "--search-tree", "1234"]), "missing search");
+        assert_eq!(expect_err(&["memimpact", "--name", "java", "--search", "java"]), "--name cannot be combined with --search");
+        assert_eq!(expect_err(&["memimpact", "--search"]), "missing search");
+        assert_eq!(expect_err(&["memimpact", "--search-regex", "(python"]), "invalid search-regex");
+        assert_eq!(expect_err(&["memimpact", "--search", "py", "--search-regex", "py"]), "--search cannot be combined with --search-regex");
+        assert_eq!(expect_err(&["memimpact", "--search-regex", "py", "--name", "py"]), "--name cannot be combined with --search-regex");
+    }
+
+    #[test]
+    fn search_regex_matches_names() {
+        let names = ["python3", "python3.12", "python3-config", "ipython3", "Python3", "(sd-pam)", "kworker/0:1"];
+        let matching = |pattern: &str| -> Vec<&str> {
+            let search = NameSearch::Pattern(pattern.to_string(), Regex::new(pattern).unwrap());
+            names.iter().copied().filter(|name| search.matches(name)).collect()
+        };
+        assert_eq!(matching(r"^python3(\.\d+)?$"), vec!["python3", "python3.12"]);
+        assert_eq!(matching("python3"), vec!["python3", "python3.12", "python3-config", "ipython3"]);
+        assert_eq!(matching("^[Pp]ython3$"), vec!["python3", "Python3"]);
+        assert_eq!(matching(r"^kworker/\d+:\d+$"), vec!["kworker/0:1"]);
+        // only the parentheses around the name are removed, not the ones it contains
+        assert_eq!(matching(r"^\(sd-pam\)$"), vec!["(sd-pam)"]);
+        assert!(matching("^sd-pam$").is_empty());
+    }
+
+    #[test]
+    fn search_matches_part_of_the_name() {
+        let search = |text: &str| NameSearch::Substring(text.to_string());
+        assert!(search("web c").matches("Web Content"));
+        assert!(search("CHROM").matches("chromium"));
+        assert!(!search("chrom").matches("firefox"));
+
+        let mut child = process::Command::new("sleep").arg("10").spawn().unwrap();
+        let pids = get_pids_from_search(&search("SLEE"), process::id() as i32);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(pids.contains(&(child.id() as i32)));
+        assert!(!get_pids_from_search(&search(""), process::id() as i32).contains(&(process::id() as i32)));
+    }
+
+    #[test]
+    fn wait_for_args() {
+        let parsed = parse_args(&args(&["memimpact", "--wait-for", "java", "--wait-timeout", "60"])).unwrap();
+        assert_eq!(parsed.wait_for.as_deref(), Some("java"));
+        assert_eq!(parsed.wait_timeout, Some(60));
+        assert!(parsed.target_pids.is_empty());
+
+        let expect_err = |argv: &[&str]| parse_args(&args(argv)).map(|_| ()).unwrap_err().to_string();
+        assert_eq!(expect_err(&["memimpact", "--wait-timeout", "60", "1234"]), "missing wait-for");
+        assert_eq!(expect_err(&["memimpact", "--wait-for", "java", "--wait-timeout", "soon"]), "invalid wait-timeout");
+        assert_eq!(expect_err(&["memimpact", "--wait-for", "java", "1234"]), "invalid pid");
+        assert_eq!(expect_err(&["memimpact", "--name", "java", "--wait-for", "java"]), "--name cannot be combined with --wait-for");
+    }
+
+    #[test]
+    fn choose_waited_pid_takes_the_lowest() {
+        let processes: Vec<(i32, String)> = [(900, "java"), (42, "bash"), (310, "java"), (77, "javac"), (1200, "java")]
+            .into_iter()
+            .map(|(pid, name)| (pid, name.to_string()))
+            .collect();
+        assert_eq!(choose_waited_pid(&processes, "java"), Some((310, 3)));
+        assert_eq!(choose_waited_pid(&processes, "javac"), Some((77, 1)));
+        assert_eq!(choose_waited_pid(&processes, "jav"), None);
+        assert_eq!(choose_waited_pid(&[], "java"), None);
+    }
+
+    #[test]
+    fn wait_for_process_times_out() {
+        let err = wait_for_process("no-such-process-name", Some(0), 10).unwrap_err();
+        assert_eq!(err.to_string(), "no process named \"no-such-process-name\" started within 0s (--wait-timeout)");
+    }
+
+    #[test]
+    fn match_cmdline_never_selects_self() {
+        let pattern = Regex::new(".").unwrap();
+        let pids = get_pids_from_cmdline(&pattern);
+        assert!(!pids.contains(&(process::id() as i32)));
+    }
+
+    #[test]
+    fn null_flag() {
+        let argv = args(&["memimpact", "-0", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.null_flag);
+    }
+
+    #[test]
+    fn max_threshold_value() {
+        let argv = args(&["memimpact", "--max-threshold", "2MiB", "--on-threshold", "echo hi", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.max_threshold, Some(2 * 1024 * 1024));
+        assert_eq!(parsed.on_threshold.as_deref(), Some("echo hi"));
+    }
+
+    #[test]
+    fn invalid_max_threshold() {
+        let argv = args(&["memimpact", "--max-threshold", "lots", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("max-threshold") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn on_threshold_requires_max_threshold() {
+        let argv = args(&["memimpact", "--on-threshold", "echo hi", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::MissingValue("max-threshold") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn kill_on_threshold_args() {
+        let argv = args(&["memimpact", "--max-threshold", "1G", "--kill-on-threshold", "--kill-tree", "--kill-grace", "2", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.kill_on_threshold_flag);
+        assert!(parsed.kill_tree_flag);
+        assert_eq!(parsed.kill_grace, 2);
+    }
+
+    #[test]
+    fn kill_on_threshold_requires_max_threshold() {
+        let argv = args(&["memimpact", "--kill-on-threshold", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::MissingValue("max-threshold") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn diff_mode_does_not_need_pid() {
+        let argv = args(&["memimpact", "--diff", "before.csv", "after.csv"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.diff_files, Some((PathBuf::from("before.csv"), PathBuf::from("after.csv"))));
+    }
+
+    #[test]
+    fn diff_mode_needs_two_files() {
+        let argv = args(&["memimpact", "--diff", "before.csv"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::MissingValue("diff") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn run_diff_end_to_end() {
+        let dir = env::temp_dir();
+        let baseline = dir.join(format!("memimpact-diff-base-{}.jsonl", process::id()));
+        let candidate = dir.join(format!("memimpact-diff-cand-{}.jsonl", process::id()));
+        fs::write(&baseline, "{\"current_bytes\":2048}\n{\"current_bytes\":1024}\n").unwrap();
+        fs::write(&candidate, "current_bytes\n1024\n1024\n").unwrap();
+
+        let report = run_diff(&baseline, &candidate, 0);
+        fs::remove_file(&baseline).unwrap();
+        fs::remove_file(&candidate).unwrap();
+
+        assert_eq!(report.unwrap(), "max RSS: 2KiB → 1KiB (-50.0%)\navg RSS: 2KiB → 1KiB (-33.3%)\nsamples: 2 → 2\n");
+    }
+
+    #[test]
+    fn group_by_name_and_no_final_conflict() {
+        let argv = args(&["memimpact", "--group-by-name", "--no-final", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("group-by-name", "no-final") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn format_influx() {
+        let argv = args(&["memimpact", "--format", "influx", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.format, FormatKind::Influx);
+    }
+
+    #[test]
+    fn format_json() {
+        let argv = args(&["memimpact", "--format", "json", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.format, FormatKind::Json);
+    }
+
+    #[test]
+    fn format_csv() {
+        let argv = args(&["memimpact", "--format", "csv", "--final", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.format, FormatKind::Csv);
+        assert!(parsed.final_flag);
+    }
+
+    #[test]
+    fn format_prometheus() {
+        let argv = args(&["memimpact", "--format", "prometheus", "--final", "--output-file", "memimpact.prom", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.format, FormatKind::Prometheus);
+        assert!(matches!(parsed.output, OutputSpec::File(_)));
+    }
+
+    #[test]
+    fn unknown_format() {
+        let argv = args(&["memimpact", "--format", "xml", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("format") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn format_and_template_conflict() {
+        let argv = args(&["memimpact", "--format", "influx", "--template", "{Pid}", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("format", "template") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn report_every() {
+        let argv = args(&["memimpact", "--hertz", "20", "--report-every", "5000", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.hz, 20);
+        assert_eq!(parsed.report_every, Some(5000));
+    }
+
+    #[test]
+    fn zero_report_every_is_invalid() {
+        let argv = args(&["memimpact", "--report-every", "0", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("report-every") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn socket_output() {
+        let argv = args(&["memimpact", "--socket", "/run/collector.sock", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(matches!(parsed.output, OutputSpec::Socket(ref path) if path == Path::new("/run/collector.sock")));
+    }
+
+    #[test]
+    fn socket_and_output_file_conflict() {
+        let argv = args(&["memimpact", "--output-file", "out.txt", "--socket", "/run/collector.sock", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("output-file", "socket") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn tcp_output() {
+        let argv = args(&["memimpact", "--tcp", "collector.local:9000", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(matches!(parsed.output, OutputSpec::Tcp(ref address) if address == "collector.local:9000"));
+    }
+
+    #[test]
+    fn tcp_output_without_port() {
+        for address in ["collector.local", "collector.local:http", ":9000"] {
+            let argv = args(&["memimpact", "--tcp", address, "1234"]);
+
+            let err = parse_args(&argv).unwrap_err();
+
+            match err {
+                ParseArgError::InvalidValue("tcp") => (),
+                _ => panic!("unexpected error: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn tcp_and_socket_conflict() {
+        let argv = args(&["memimpact", "--tcp", "localhost:9000", "--socket", "/run/collector.sock", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("tcp", "socket") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn histogram_bucket_size() {
+        let argv = args(&["memimpact", "--histogram", "100MiB", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.histogram_bucket, Some(100 * 1024 * 1024));
+    }
+
+    #[test]
+    fn histogram_bucket_too_small() {
+        let argv = args(&["memimpact", "--histogram", "512", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("histogram") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn per_child_flag() {
+        let argv = args(&["memimpact", "--per-child", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.per_child_flag);
+    }
+
+    #[test]
+    fn precision() {
+        let argv = args(&["memimpact", "--precision", "2", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.precision, 2);
+    }
+
+    #[test]
+    fn precision_too_high() {
+        let argv = args(&["memimpact", "--precision", "12", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("precision") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn color_choice() {
+        let parsed = parse_args(&args(&["memimpact", "--color", "always", "1234"])).unwrap();
+        assert_eq!(parsed.color, ColorChoice::Always);
+
+        let parsed = parse_args(&args(&["memimpact", "--no-color", "1234"])).unwrap();
+        assert_eq!(parsed.color, ColorChoice::Never);
+
+        let parsed = parse_args(&args(&["memimpact", "1234"])).unwrap();
+        assert_eq!(parsed.color, ColorChoice::Auto);
+    }
+
+    #[test]
+    fn invalid_color_choice() {
+        let argv = args(&["memimpact", "--color", "sometimes", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("color") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn coalesce() {
+        let argv = args(&["memimpact", "--hertz", "20", "--coalesce", "1000", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.coalesce, Some(1000));
+    }
+
+    #[test]
+    fn coalesce_and_report_every_conflict() {
+        let argv = args(&["memimpact", "--coalesce", "1000", "--report-every", "1000", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("coalesce", "report-every") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn list_fields_flag() {
+        let argv = args(&["memimpact", "--list-fields"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.list_fields_flag);
+    }
+
+    #[test]
+    fn time_weighted_avg_flag() {
+        let argv = args(&["memimpact", "--time-weighted-avg", "--adaptive", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.time_weighted_avg_flag);
+    }
+
+    #[test]
+    fn template_stderr_with_format() {
+        let argv = args(&["memimpact", "--format", "influx", "--template-stderr", "{MaxHuman}\\n", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.format, FormatKind::Influx);
+        assert_eq!(parsed.template_stderr.as_deref(), Some("{MaxHuman}\\n"));
+    }
+
+    #[test]
+    fn pid_namespace() {
+        let parsed = parse_args(&args(&["memimpact", "--pid-ns", "auto", "7"])).unwrap();
+        assert_eq!(parsed.pid_namespace, Some(PidNamespace::Auto));
+        assert_eq!(parsed.target_pids, vec![7]);
+
+        let parsed = parse_args(&args(&["memimpact", "--pid-ns", "pid:[4026532512]", "7"])).unwrap();
+        assert_eq!(parsed.pid_namespace, Some(PidNamespace::Id(4026532512)));
+
+        let parsed = parse_args(&args(&["memimpact", "--pid-ns", "4026532512", "7"])).unwrap();
+        assert_eq!(parsed.pid_namespace, Some(PidNamespace::Id(4026532512)));
+    }
+
+    #[test]
+    fn pid_namespace_with_name_conflict() {
+        let argv = args(&["memimpact", "--pid-ns", "auto", "--name", "nginx"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("pid-ns", "name") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
     }
-    if args.version_flag{
-    	let version = env!("CARGO_PKG_VERSION");
-    	println!("{}", 	version);
-    	process::exit(0);
+
+    #[test]
+    fn no_follow_output_file() {
+        let argv = args(&["memimpact", "--no-follow", "--output-file", "out.txt", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(matches!(parsed.output, OutputSpec::NoFollowFile(ref path) if path == Path::new("out.txt")));
     }
-    
-	let sleep_duration: u64 = 1000 / args.hz;
 
-    let process_name = match get_process_name(args.target_pids.first().unwrap()) {
-	    Ok(name) => name,
-	    Err(msg) => {
-	        eprintln!("memimpact error: {}", msg);
-	        process::exit(1);
-	    }
-	};
+    #[test]
+    fn no_follow_without_output_file() {
+        let argv = args(&["memimpact", "--no-follow", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
 
-	let mut output = match setup_output(args.output) {
-        Ok(o) => o,
-        Err(e) => {
-            eprintln!("Memimapct ailed to open output: {}", e);
-            process::exit(1);
+        match err {
+            ParseArgError::MissingValue("output-file") => (),
+            _ => panic!("unexpected error: {:?}", err),
         }
-    };
+    }
 
-	let mut output_buffer = String::new();
-	
-	let escaped = template_engine::unescape(args.template_string.as_str()).unwrap();
-	let template = template_engine::Template::parse(escaped.as_str()).unwrap();
+    #[test]
+    fn skip_stopped_flag() {
+        let argv = args(&["memimpact", "--skip-stopped", "--time-weighted-avg", "1234"]);
 
-	let mut sample = template_engine::MemorySample{
-		pid: *args.target_pids.first().unwrap(),
-		process_name: process_name.as_str(),
-		current_bytes: 0,
-		max_bytes: 0,
-		timestamp: now(),
-	};
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.skip_stopped_flag);
+    }
 
-    loop {
-    	let mut stop_loop = false;
-        let mapping = get_map_pid_to_ppid();
-        for pid in &args.target_pids{
-        	 if !mapping.contains_key(pid){
-        	 	stop_loop = true;
-	        	break;
-    	    } 
-        }
-        if stop_loop{
-        	break;
-        }
-        let target_descendants = find_descendants(&mapping, &args.target_pids);
-        sample.current_bytes = target_descendants.iter().map(|pid| read_rss_kb(pid, &args.page_size_kib)).sum();
-        sample.max_bytes = sample.max_bytes.max(sample.current_bytes);
-        sample.timestamp = now();
-		if !args.final_flag{
-			match template.render(&sample, &mut output_buffer){
-				Ok(()) => write_output(&mut output, &output_buffer),
-				Err(e) => eprintln!("error while writing ouput: {:?}", e) 
-			};
-			output_buffer.clear();
-		}
-		
-        thread::sleep(Duration::from_millis(sleep_duration));
+    #[test]
+    fn gzip_output_file() {
+        let argv = args(&["memimpact", "--gzip", "--no-follow", "--output-file", "run.csv.gz", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        match parsed.output {
+            OutputSpec::Gzip(inner) => assert!(matches!(*inner, OutputSpec::NoFollowFile(ref path) if path == Path::new("run.csv.gz"))),
+            other => panic!("unexpected output: {:?}", other),
+        }
     }
-    sample.max_bytes = sample.max_bytes.max(sample.current_bytes);
-	match template.render(&sample, &mut output_buffer){
-		Ok(()) => write_output(&mut output, &output_buffer),
-		Err(e) => eprintln!("error while writing ouput: {:?}", e) 
-	};
-}
 
+    #[test]
+    fn gzip_without_output_file() {
+        let argv = args(&["memimpact", "--gzip", "--socket", "/tmp/memimpact.sock", "1234"]);
 
-/// Tests
+        let err = parse_args(&argv).unwrap_err();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::template_engine::format_memory_from_kib;
+        match err {
+            ParseArgError::MissingValue("output-file") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
 
     #[test]
-    fn test_parse_proc_stat_basic() {
-        let input = "1234 (bash) R 1 2 3 4";
-        let actual = parse_proc_stat(input).unwrap();
+    fn include_self_flag() {
+        let argv = args(&["memimpact", "--include-self", "1234"]);
 
-        let expected = ProcStat{pid: 1234, comm: "(bash)", state: ProcessState::R, ppid: 1};
-        assert_eq!(actual, expected);
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.include_self_flag);
     }
 
     #[test]
-    fn test_parse_proc_stat_with_spaces_in_name() {
-        let input = "5678 (my fancy process) S 10 20 30";
-        let actual = parse_proc_stat(input).unwrap();
+    fn run_missing_pid() {
+        // above the largest pid_max the kernel accepts
+        let parsed = parse_args(&args(&["memimpact", "--final", "2000000000"])).unwrap();
 
-        let expected = ProcStat{pid: 5678, comm: "(my fancy process)", state: ProcessState::S, ppid: 10};
-        assert_eq!(actual, expected);
+        let err = run(parsed).unwrap_err();
+        assert!(matches!(err, MemimpactError::PidNotFound(2000000000)));
+        assert_eq!(err.exit_code(), ExitCode::TargetNotFound);
     }
 
     #[test]
-    fn test_parse_proc_stat_with_paranthesis_in_name() {
-    	// real world test case
-        let input = "3674 ((sd-pam)) S 3672 3672 3672 0 -1 4194624 49 0 0 0 0 0 0 0 20 0 1 0 4058 17170432 450 18446744073709551615 1 1 0 0 0 0 0 4096 0 0 0 0 17 8 0 0 0 0 0 0 0 0 0 0 0 0 0";
-        let actual = parse_proc_stat(input).unwrap();
+    fn run_invalid_template() {
+        let self_pid = process::id().to_string();
+        let parsed = parse_args(&args(&["memimpact", "--template", "{Nope}", &self_pid])).unwrap();
 
-        let expected = ProcStat{pid: 3674, comm: "((sd-pam))", state: ProcessState::S, ppid: 3672};
-        assert_eq!(actual, expected);
+        let err = run(parsed).unwrap_err();
+        assert!(matches!(err, MemimpactError::Template("template", _)));
     }
 
-
     #[test]
-    fn test_parse_proc_stat_invalid_missing_parens() {
-        let input = "9999 bash R 1 2 3";
-        let parts = parse_proc_stat(input);
+    fn run_wait_for_init_needs_all() {
+        let init_name = get_process_name(&1).unwrap();
+        let parsed = parse_args(&args(&["memimpact", "--wait-for", &init_name, "--wait-timeout", "0"])).unwrap();
 
-        assert!(parts.is_err());
+        let err = run(parsed).unwrap_err();
+        assert!(matches!(err, MemimpactError::Args(ParseArgError::Unconfirmed(_, "all"))));
     }
 
     #[test]
-    fn test_find_descendants_simple_tree() {
-        let mut map = HashMap::new();
-        map.insert(2, 1);
-        map.insert(3, 1);
-        map.insert(4, 2);
-        map.insert(5, 4);
+    fn run_diff_missing_file() {
+        let parsed = parse_args(&args(&["memimpact", "--diff", "/nonexistent/a.csv", "/nonexistent/b.csv"])).unwrap();
+
+        let err = run(parsed).unwrap_err();
+        assert!(matches!(err, MemimpactError::Diff(ref msg) if msg.contains("/nonexistent/a.csv")));
+    }
 
-        let descendants = find_descendants(&map, &vec![1]);
+    #[test]
+    fn epoch_offset() {
+        let argv = args(&["memimpact", "--epoch-offset", "1700000000", "1234"]);
 
-        let expected: HashSet<i32> = [1, 2, 3, 4, 5].into_iter().collect();
-        assert_eq!(descendants, expected);
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.epoch_offset, Some(1700000000));
     }
 
     #[test]
-    fn test_find_descendants_leaf() {
-        let mut map = HashMap::new();
-        map.insert(2, 1);
-        map.insert(3, 1);
+    fn epoch_offset_conflicts_with_relative_to_start() {
+        let argv = args(&["memimpact", "--relative-to-start", "--epoch-offset", "10", "1234"]);
 
-        let descendants = find_descendants(&map, &vec![2]);
+        let err = parse_args(&argv).unwrap_err();
 
-        let expected: HashSet<i32> = [2].into_iter().collect();
-        assert_eq!(descendants, expected);
+        match err {
+            ParseArgError::ConflictingFlags("epoch-offset", "relative-to-start") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
     }
 
     #[test]
-    fn test_format_memory_kb() {
-        assert_eq!(format_memory_from_kib(512), "512KiB");
+    fn run_fail_if_absent() {
+        let mut child = process::Command::new("sleep").arg("0.2").spawn().unwrap();
+        let pid = child.id().to_string();
+        // reaped meanwhile, a zombie would still be listed in /proc
+        let reaper = thread::spawn(move || child.wait().unwrap());
+        let parsed = parse_args(&args(&["memimpact", "--fail-if-absent", "--no-final", "--hertz", "100", &pid])).unwrap();
+        let started = run(parsed);
+        reaper.join().unwrap();
+
+        // the child may be gone before run reads its name
+        match started {
+            Err(MemimpactError::TargetExited) | Err(MemimpactError::PidNotFound(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
     }
 
     #[test]
-    fn test_format_memory_mb() {
-        assert_eq!(format_memory_from_kib(2 * 1024), "2MiB");
+    fn watchdog_seconds() {
+        let argv = args(&["memimpact", "--watchdog", "30", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.watchdog, Some(30));
     }
 
     #[test]
-    fn test_format_memory_gb() {
-        assert_eq!(format_memory_from_kib(2 * 1024 * 1024), "2GiB");
+    fn zero_watchdog_is_invalid() {
+        let argv = args(&["memimpact", "--watchdog", "0", "1234"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::InvalidValue("watchdog") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
     }
 
     #[test]
-    fn test_format_memory_rounding_behavior() {
-        assert_eq!(format_memory_from_kib(1536), "1MiB");
+    fn cgroup_procs_of_a_directory() {
+        let dir = env::temp_dir().join(format!("memimpact-cgroup-args-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("cgroup.procs"), "7\n8\n").unwrap();
+        let argv = args(&["memimpact", "--cgroup-procs", dir.to_str().unwrap()]);
+
+        let parsed = parse_args(&argv);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(parsed.unwrap().target_pids, vec![7, 8]);
     }
 
     #[test]
-    fn test_format_memory_max() {
-        assert_eq!(format_memory_from_kib(u64::MAX), "15ZiB");
+    fn cgroup_procs_path_under_the_cgroup_filesystem() {
+        assert_eq!(cgroup_dir("/memimpact-missing.slice/a.service"), PathBuf::from("/sys/fs/cgroup/memimpact-missing.slice/a.service"));
     }
 
     #[test]
-    fn test_parse_statm_valid() {
-        let input = "100 50 0 0 0 0 0";
-        assert_eq!(parse_statm(input.to_string()).ok(), Some(50));
+    fn cgroup_procs_conflicts_with_name() {
+        let argv = args(&["memimpact", "--name", "nginx", "--cgroup-procs", "/system.slice"]);
+
+        let err = parse_args(&argv).unwrap_err();
+
+        match err {
+            ParseArgError::ConflictingFlags("name", "cgroup-procs") => (),
+            _ => panic!("unexpected error: {:?}", err),
+        }
     }
 
     #[test]
-    fn test_parse_statm_invalid() {
-        assert!(parse_statm("invalid".to_string()).is_err());
+    fn display_name_keeps_parens_on_request() {
+        assert_eq!(display_name("bash".to_string(), false), "bash");
+        assert_eq!(display_name("bash".to_string(), true), "(bash)");
     }
 
     #[test]
-    fn test_write_output_to_buffer() {
-        let mut buffer: Vec<u8> = Vec::new();
-        write_output(&mut buffer, "hello");
-        assert_eq!(buffer, b"hello");
+    fn largest_process_flag() {
+        let argv = args(&["memimpact", "--largest-process", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.largest_process_flag);
     }
 
-    fn args(input: &[&str]) -> Vec<String> { // to avoid to add .to_string in following argument tests
-        input.iter().map(|s| s.to_string()).collect()
+    #[test]
+    fn replay_does_not_need_a_pid() {
+        let argv = args(&["memimpact", "--replay", "run.csv", "--histogram", "1MiB"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.replay_file, Some(PathBuf::from("run.csv")));
+        assert!(parsed.target_pids.is_empty());
     }
 
     #[test]
-    fn minimal_valid_args() {
-        let argv = args(&["memimpact", "1234"]);
+    fn replay_recomputes_the_summary() {
+        let path = env::temp_dir().join(format!("memimpact-replay-{}.csv", process::id()));
+        fs::write(&path, "timestamp,pid,process_name,current_bytes,max_bytes\n\
+                          100,42,worker,1024,1024\n\
+                          101,42,worker,3072,4096\n\
+                          103,42,worker,1024,4096\n").unwrap();
+        let argv = args(&["memimpact", "--replay", path.to_str().unwrap(), "--time-weighted-avg",
+                          "--template", "{Pid} {ProcessName} {MaxBytes} {TimeWeightedAvg} {SampleNumber} {Final}\\n"]);
         let parsed = parse_args(&argv).unwrap();
 
-        assert_eq!(parsed.help_flag, false);
-        assert_eq!(parsed.final_flag, false);
-        assert_eq!(parsed.hz, 1);
-        matches!(parsed.output, OutputSpec::Stdout);
-        assert_eq!(parsed.target_pids, vec![1234]);
+        let mut out = Vec::new();
+        let result = run_replay(&parsed.replay_file.clone().unwrap(), &parsed, &mut out);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        // 3072 for one second then 1024 for two, the recorded max wins over the samples
+        // but not in the stats, which only see the samples
+        assert_eq!(String::from_utf8(out).unwrap(), "42 worker 4096 1706 3 true\nstats: samples 3, min 1KiB, mean 2KiB, max 3KiB\n");
     }
 
     #[test]
-    fn full_valid_args() {
-        let argv = args(&[
-            "memimpact",
-            "--hertz", "10",
-            "--output-file", "out.txt",
-            "--final",
-            "4321",
-        ]);
+    fn snapshot_options() {
+        let argv = args(&["memimpact", "--snapshot-file", "snapshots.log", "--snapshot-breakdown", "1234"]);
 
         let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.snapshot_file, Some(PathBuf::from("snapshots.log")));
+        assert!(parsed.snapshot_breakdown_flag);
+    }
 
-        assert!(parsed.final_flag);
-        assert!(!parsed.help_flag);
-        assert_eq!(parsed.hz, 10);
-        assert_eq!(parsed.target_pids, vec![4321]);
+    #[test]
+    fn write_snapshot_appends() {
+        let path = env::temp_dir().join(format!("memimpact-snapshot-{}.log", process::id()));
+        write_snapshot(Some(&path), b"first\n").unwrap();
+        write_snapshot(Some(&path), b"second\n").unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(content, "first\nsecond\n");
+    }
 
-        match parsed.output {
-            OutputSpec::File(path) => assert_eq!(path, PathBuf::from("out.txt")),
-            _ => panic!("expected file output"),
-        }
+    #[test]
+    fn warm_up_is_bounded() {
+        assert!(still_warming_up(0, 0));
+        assert!(still_warming_up(0, MAX_WARMUP_SAMPLES - 1));
+        assert!(!still_warming_up(0, MAX_WARMUP_SAMPLES));
+        assert!(!still_warming_up(4096, 0));
     }
 
     #[test]
-    fn help_flag_only() {
-        let argv = args(&["memimpact", "--help"]);
+    fn log_warmup_flag() {
+        let argv = args(&["memimpact", "--log-warmup", "1234"]);
 
         let parsed = parse_args(&argv).unwrap();
-        assert!(parsed.help_flag);
+        assert!(parsed.log_warmup_flag);
     }
 
-     #[test]
-    fn version_flag_only() {
-        let argv = args(&["memimpact", "--version"]);
+    #[test]
+    fn align_to_clock_flag() {
+        let argv = args(&["memimpact", "--align-to-clock", "1234"]);
 
         let parsed = parse_args(&argv).unwrap();
-        assert!(parsed.version_flag);
+        assert!(parsed.align_to_clock_flag);
     }
 
     #[test]
-    fn help_flag_only_short() {
-        let argv = args(&["memimpact", "-h"]);
+    fn map_count_flag() {
+        let argv = args(&["memimpact", "--map-count", "--template", "{MapCount}\\n", "1234"]);
 
         let parsed = parse_args(&argv).unwrap();
-        assert!(parsed.help_flag);
+        assert!(parsed.map_count_flag);
     }
 
-     #[test]
-    fn version_flag_only_short() {
-        let argv = args(&["memimpact", "-v"]);
+    #[test]
+    fn baseline_sets_the_threshold() {
+        let argv = args(&["memimpact", "--baseline", "1000K", "--tolerance", "10%", "--kill-on-threshold", "1234"]);
 
         let parsed = parse_args(&argv).unwrap();
-        assert!(parsed.version_flag);
+        assert_eq!(parsed.baseline, Some(1000 * 1024));
+        assert_eq!(parsed.max_threshold, Some(1100 * 1024));
     }
 
     #[test]
-    fn hertz_value_missing_pid() {
-        let argv = args(&["memimpact", "--hertz", "1234"]);
+    fn baseline_args_errors() {
+        let expect_err = |argv: &[&str]| parse_args(&args(argv)).map(|_| ()).unwrap_err().to_string();
+        assert_eq!(expect_err(&["memimpact", "--tolerance", "10", "1234"]), "missing baseline");
+        assert_eq!(expect_err(&["memimpact", "--baseline", "1G", "--tolerance", "-5", "1234"]), "invalid tolerance");
+        assert_eq!(
+            expect_err(&["memimpact", "--baseline", "1G", "--max-threshold", "2G", "1234"]),
+            "--baseline cannot be combined with --max-threshold",
+        );
+    }
 
-        let err = parse_args(&argv).unwrap_err();
+    #[test]
+    fn threshold_value() {
+        let parsed = parse_args(&args(&["memimpact", "--threshold", "500MB", "1234"])).unwrap();
+        assert_eq!(parsed.threshold, Some(500_000_000));
+        assert_eq!(parsed.max_threshold, None);
+
+        let expect_err = |argv: &[&str]| parse_args(&args(argv)).map(|_| ()).unwrap_err().to_string();
+        assert_eq!(expect_err(&["memimpact", "--threshold", "lots", "1234"]), "invalid threshold");
+        assert_eq!(expect_err(&["memimpact", "1234", "--threshold"]), "missing threshold");
+        assert_eq!(
+            expect_err(&["memimpact", "--threshold", "1G", "--baseline", "1G", "1234"]),
+            "--threshold cannot be combined with --baseline",
+        );
+        assert_eq!(
+            expect_err(&["memimpact", "--max-threshold", "2G", "--threshold", "1G", "1234"]),
+            "--threshold cannot be combined with --max-threshold",
+        );
+    }
 
-        match err {
-            ParseArgError::MissingValue("pid") => (),
-            _ => panic!("unexpected error: {:?}", err),
+    #[test]
+    fn buffer_size_wraps_output_file() {
+        let argv = args(&["memimpact", "--buffer-size", "64K", "--output-file", "out.txt", "1234"]);
+
+        let parsed = parse_args(&argv).unwrap();
+        match parsed.output {
+            OutputSpec::Buffered(inner, size) => {
+                assert_eq!(size, 65536);
+                assert!(matches!(*inner, OutputSpec::File(ref path) if path == Path::new("out.txt")));
+            }
+            other => panic!("unexpected output {:?}", other),
         }
     }
 
     #[test]
-    fn missing_hertz_value() {
-        let argv = args(&["memimpact", "1234", "--hertz"]);
+    fn buffer_size_errors() {
+        let expect_err = |argv: &[&str]| parse_args(&args(argv)).map(|_| ()).unwrap_err().to_string();
+        assert_eq!(expect_err(&["memimpact", "--buffer-size", "64K", "1234"]), "missing output-file");
+        assert_eq!(expect_err(&["memimpact", "--buffer-size", "0", "--output-file", "o", "1234"]), "invalid buffer-size");
+        assert_eq!(expect_err(&["memimpact", "--buffer-size", "1G", "--output-file", "o", "1234"]), "invalid buffer-size");
+        assert_eq!(
+            expect_err(&["memimpact", "--buffer-size", "64K", "--gzip", "--output-file", "o", "1234"]),
+            "--buffer-size cannot be combined with --gzip",
+        );
+    }
 
-        let err = parse_args(&argv).unwrap_err();
+    #[test]
+    fn pushgateway_args() {
+        let argv = args(&["memimpact", "--job", "nightly", "--pushgateway", "http://gateway:9091", "--push-every", "30", "1234"]);
 
-        match err {
-            ParseArgError::MissingValue("hertz") => (),
-            _ => panic!("unexpected error: {:?}", err),
-        }
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.pushgateway.is_some());
+        assert_eq!(parsed.push_every, Some(30));
     }
 
+    #[test]
+    fn pushgateway_args_errors() {
+        let expect_err = |argv: &[&str]| parse_args(&args(argv)).map(|_| ()).unwrap_err().to_string();
+        assert_eq!(expect_err(&["memimpact", "--job", "nightly", "1234"]), "missing pushgateway");
+        assert_eq!(expect_err(&["memimpact", "--pushgateway", "https://gateway", "1234"]), "invalid pushgateway");
+        assert_eq!(expect_err(&["memimpact", "--pushgateway", "http://gateway", "--job", "a/b", "1234"]), "invalid job");
+    }
 
     #[test]
-    fn invalid_hertz_value() {
-        let argv = args(&["memimpact", "--hertz", "abc", "123"]);
+    fn top_does_not_need_a_pid() {
+        let argv = args(&["memimpact", "--top", "5"]);
 
-        let err = parse_args(&argv).unwrap_err();
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.top, Some(5));
+        assert!(parsed.target_pids.is_empty());
+    }
 
-        match err {
-            ParseArgError::InvalidValue("hertz") => (),
-            _ => panic!("unexpected error: {:?}", err),
-        }
+    #[test]
+    fn top_args_errors() {
+        let expect_err = |argv: &[&str]| parse_args(&args(argv)).map(|_| ()).unwrap_err().to_string();
+        assert_eq!(expect_err(&["memimpact", "--top", "0"]), "invalid top");
+        assert_eq!(expect_err(&["memimpact", "--top", "5", "--name", "bash"]), "--top cannot be combined with --name");
     }
 
     #[test]
-    fn zero_hertz_is_invalid() {
-        let argv = args(&["memimpact", "--hertz", "0", "123"]);
+    fn metric_value() {
+        let parsed = parse_args(&args(&["memimpact", "--metric", "uss", "1234"])).unwrap();
+        assert_eq!(parsed.metric, Metric::Uss);
+        let parsed = parse_args(&args(&["memimpact", "1234"])).unwrap();
+        assert_eq!(parsed.metric, Metric::Rss);
+        let parsed = parse_args(&args(&["memimpact", "--vsz", "1234"])).unwrap();
+        assert_eq!(parsed.metric, Metric::Vsz);
+        let parsed = parse_args(&args(&["memimpact", "--pss", "1234"])).unwrap();
+        assert_eq!(parsed.metric, Metric::Pss);
+        let err = parse_args(&args(&["memimpact", "--metric", "heap", "1234"])).unwrap_err();
+        assert_eq!(err.to_string(), "invalid metric");
+    }
 
-        let err = parse_args(&argv).unwrap_err();
+    #[test]
+    fn end_marker_args() {
+        let parsed = parse_args(&args(&["memimpact", "--emit-end-marker", "1234"])).unwrap();
+        assert_eq!(parsed.end_marker.as_deref(), Some("# END\\n"));
+        let parsed = parse_args(&args(&["memimpact", "--emit-end-marker", "--format", "csv", "1234"])).unwrap();
+        assert_eq!(parsed.end_marker.as_deref(), Some("# END\\n"));
+        let argv = args(&["memimpact", "--end-marker", "end {MaxBytes}\\n", "--emit-end-marker", "1234"]);
+        let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.end_marker.as_deref(), Some("end {MaxBytes}\\n"));
+    }
 
-        match err {
-            ParseArgError::InvalidValue("hertz") => (),
-            _ => panic!("unexpected error: {:?}", err),
-        }
+    #[test]
+    fn json_end_marker_is_a_json_record() {
+        // the format comes after the flag, the marker still follows it
+        let parsed = parse_args(&args(&["memimpact", "--emit-end-marker", "--format", "json", "1234"])).unwrap();
+        let marker = parse_template(parsed.end_marker.as_deref().unwrap()).unwrap();
+        let sample = template_engine::MemorySample{max_bytes: 2048, is_final: true, ..Default::default()};
+        let mut out = String::new();
+        marker.render(&sample, &mut out).unwrap();
+        assert_eq!(out, "{\"event\":\"end\",\"max_bytes\":2048}\n");
+        let fields = recorded::parse_flat_json_object(&out).unwrap();
+        assert_eq!(fields["event"], "end");
+        assert_eq!(fields["max_bytes"], "2048");
     }
 
     #[test]
-    fn missing_output_file_value() {
-        let argv = args(&["memimpact", "1234", "--output-file"]);
+    fn fields_choose_the_csv_columns() {
+        let argv = args(&["memimpact", "--format", "csv", "--fields", "Timestamp, CurrentBytes,Pid", "1234"]);
+        let parsed = parse_args(&argv).unwrap();
+        let template = load_template(&parsed).unwrap();
+        let mut emitter = Emitter::new(record_format(&parsed, template), false);
+        assert_eq!(emitter.header().as_deref(), Some("timestamp,current_bytes,pid\n"));
+        let sample = template_engine::MemorySample{pid: 1234, current_bytes: 2048, timestamp: 7, ..Default::default()};
+        let mut out = Vec::new();
+        emitter.emit(&mut out, &sample, None, false);
+        assert_eq!(out, b"7,2048,1234\n");
+    }
 
-        let err = parse_args(&argv).unwrap_err();
+    #[test]
+    fn fields_choose_the_json_keys() {
+        let parsed = parse_args(&args(&["memimpact", "--format", "json", "--fields", "ProcessName,CurrentBytes", "1234"])).unwrap();
+        let template = load_template(&parsed).unwrap();
+        let mut emitter = Emitter::new(record_format(&parsed, template), false);
+        assert_eq!(emitter.header(), None);
+        let sample = template_engine::MemorySample{process_name: "a,b", current_bytes: 2048, ..Default::default()};
+        let mut out = Vec::new();
+        emitter.emit(&mut out, &sample, None, false);
+        assert_eq!(out, b"{\"process_name\":\"a,b\",\"current_bytes\":2048}\n");
+    }
 
-        match err {
-            ParseArgError::MissingValue("output-file") => (),
-            _ => panic!("unexpected error: {:?}", err),
-        }
+    #[test]
+    fn fields_errors() {
+        let expect_err = |argv: &[&str]| parse_args(&args(argv)).map(|_| ()).unwrap_err().to_string();
+        assert_eq!(expect_err(&["memimpact", "--fields", "Pid,Heap", "1234"]), "invalid fields");
+        assert_eq!(expect_err(&["memimpact", "--fields", "Pid", "1234"]), "missing format");
+        assert_eq!(expect_err(&["memimpact", "--fields", "Pid", "--template", "{Pid}", "1234"]), "missing format");
+        assert_eq!(
+            expect_err(&["memimpact", "--fields", "Pid", "--format", "influx", "1234"]),
+            "--fields cannot be combined with --format",
+        );
     }
 
     #[test]
-    fn invalid_pid() {
-        let argv = args(&["memimpact", "not_a_pid"]);
+    fn per_pid_stream_args() {
+        let parsed = parse_args(&args(&["memimpact", "--per-pid-stream", "1234"])).unwrap();
+        assert!(parsed.per_pid_stream_flag);
+        let err = parse_args(&args(&["memimpact", "--per-pid-stream", "--per-child", "1234"])).unwrap_err();
+        assert_eq!(err.to_string(), "--per-pid-stream cannot be combined with --per-child");
+    }
 
-        let err = parse_args(&argv).unwrap_err();
+    #[test]
+    fn pid_1_requires_all() {
+        let err = parse_args(&args(&["memimpact", "1"])).unwrap_err();
+        assert!(matches!(err, ParseArgError::Unconfirmed(_, "all")));
+        let parsed = parse_args(&args(&["memimpact", "--all", "1"])).unwrap();
+        assert_eq!(parsed.target_pids, vec![1]);
+    }
 
-        match err {
-            ParseArgError::InvalidValue("pid") => (),
-            _ => panic!("unexpected error: {:?}", err),
-        }
+    #[test]
+    fn pid_1_of_a_namespace_is_checked_once_translated() {
+        // the application of a container, whose host pid is not 1
+        let parsed = parse_args(&args(&["memimpact", "--pid-ns", "auto", "1"])).unwrap();
+        assert_eq!(parsed.target_pids, vec![1]);
+        assert!(confirm_pid_1(&[4242], false).is_ok());
+        // translated to the host's init, it still needs --all
+        assert!(matches!(confirm_pid_1(&[1], false), Err(ParseArgError::Unconfirmed(_, "all"))));
+        assert!(confirm_pid_1(&[1], true).is_ok());
     }
 
     #[test]
-    fn missing_pid() {
-        let argv = args(&["memimpact", "--final"]);
+    fn change_threshold_implies_on_change() {
+        let parsed = parse_args(&args(&["memimpact", "--change-threshold", "1MiB", "1234"])).unwrap();
+        assert!(parsed.on_change_flag);
+        assert_eq!(parsed.change_threshold, 1024 * 1024);
+        let parsed = parse_args(&args(&["memimpact", "--on-change", "1234"])).unwrap();
+        assert_eq!(parsed.change_threshold, 0);
+    }
 
-        let err = parse_args(&argv).unwrap_err();
+    #[test]
+    fn hugepages_flag() {
+        let argv = args(&["memimpact", "--hugepages", "--template", "{HugePages}\\n", "1234"]);
 
-        match err {
-            ParseArgError::MissingValue("pid") => (),
-            _ => panic!("unexpected error: {:?}", err),
-        }
+        let parsed = parse_args(&argv).unwrap();
+        assert!(parsed.hugepages_flag);
     }
 
     #[test]
-    fn realistic_mixed_order() {
-        let argv = args(&[
-            "memimpact",
-            "--final",
-            "5678",
-            "--hertz", "5",
-        ]);
+    fn swap_flag() {
+        let parsed = parse_args(&args(&["memimpact", "--swap", "1234"])).unwrap();
+        assert!(parsed.swap_flag);
+        let template = default_template(&parsed);
+        assert_eq!(template, "PID {Pid} {ProcessName}: current {CurrentHuman}, max {MaxHuman}, swap {SwapHuman}\n");
+        assert!(parse_template(&template).is_ok());
+    }
+
+    #[test]
+    fn folded_file() {
+        let argv = args(&["memimpact", "--folded", "peak.folded", "1234"]);
 
         let parsed = parse_args(&argv).unwrap();
+        assert_eq!(parsed.folded_file, Some(PathBuf::from("peak.folded")));
+        assert!(parse_args(&args(&["memimpact", "1234", "--folded"])).is_err());
+    }
 
-        assert!(parsed.final_flag);
-        assert_eq!(parsed.hz, 5);
-        assert_eq!(parsed.target_pids, vec![5678]);
+    #[test]
+    fn summary_json_path_is_optional() {
+        let parsed = parse_args(&args(&["memimpact", "--summary-json", "summary.json", "1234"])).unwrap();
+        assert!(parsed.summary_json_flag);
+        assert_eq!(parsed.summary_json_file, Some(PathBuf::from("summary.json")));
+        // a pid or an option after it is not a path
+        let parsed = parse_args(&args(&["memimpact", "--summary-json", "1234"])).unwrap();
+        assert!(parsed.summary_json_flag);
+        assert_eq!(parsed.summary_json_file, None);
+        assert_eq!(parsed.target_pids, vec![1234]);
+        let parsed = parse_args(&args(&["memimpact", "--summary-json", "--final", "1234"])).unwrap();
+        assert_eq!(parsed.summary_json_file, None);
     }
 
     #[test]
-    fn realistic_order() {
-        let argv = args(&[
-            "memimpact",
-            "--final",
-            "--hertz", "5",
-            "5678",
-        ]);
+    fn measure_takes_the_command() {
+        let parsed = parse_args(&args(&["memimpact", "--measure", "--hertz", "10", "--", "make", "-j4", "--keep-going"])).unwrap();
+        assert!(parsed.measure_flag);
+        assert_eq!(parsed.command, args(&["make", "-j4", "--keep-going"]));
+        assert!(parsed.target_pids.is_empty());
+        assert!(matches!(parse_args(&args(&["memimpact", "--measure"])), Err(ParseArgError::MissingValue("command"))));
+        // the command alone is enough, memimpact wraps it
+        let parsed = parse_args(&args(&["memimpact", "--", "make", "-j4"])).unwrap();
+        assert!(parsed.measure_flag);
+        assert_eq!(parsed.command, args(&["make", "-j4"]));
+        // a number after -- is an argument of the command, not a target
+        assert_eq!(parse_args(&args(&["memimpact", "--", "kill", "1234"])).unwrap().command, args(&["kill", "1234"]));
+        assert!(matches!(parse_args(&args(&["memimpact", "1234", "--", "make"])), Err(ParseArgError::InvalidValue("pid"))));
+        assert!(matches!(
+            parse_args(&args(&["memimpact", "--measure", "--name", "make", "--", "make"])),
+            Err(ParseArgError::ConflictingFlags("measure", "name")),
+        ));
+    }
 
-        let parsed = parse_args(&argv).unwrap();
+    #[test]
+    fn measure_line_is_one_line() {
+        let sample = template_engine::MemorySample{max_bytes: 2 * 1024 * 1024, time_weighted_avg: Some(900 * 1024), ..Default::default()};
+        assert_eq!(
+            measure_line(&sample, Duration::from_millis(42_040), 0, 0),
+            "peak=2MiB avg=900KiB duration=42.0s exit=0",
+        );
+    }
 
-        assert!(parsed.final_flag);
-        assert_eq!(parsed.hz, 5);
-        assert_eq!(parsed.target_pids, vec![5678]);
+    #[test]
+    fn wrapped_command_exit_code() {
+        for (command, code) in [("true", 0), ("false", 1)] {
+            let mut child = spawn_measured(&args(&[command])).unwrap();
+            assert_eq!(exit_code_of(child.wait().unwrap()), code);
+        }
+        let err = spawn_measured(&args(&["/nonexistent/memimpact-command"])).unwrap_err();
+        assert!(err.to_string().starts_with("could not run /nonexistent/memimpact-command: "));
+    }
+
+    #[test]
+    fn exit_code_of_a_killed_command() {
+        let status = process::Command::new("sh").arg("-c").arg("kill -9 $$").status().unwrap();
+        assert_eq!(exit_code_of(status), 137);
+        let status = process::Command::new("sh").arg("-c").arg("exit 3").status().unwrap();
+        assert_eq!(exit_code_of(status), 3);
+    }
+
+    #[test]
+    fn several_pids_are_one_target_set() {
+        let parsed = parse_args(&args(&["memimpact", "100", "205", "3030", "205"])).unwrap();
+        assert_eq!(parsed.target_pids, vec![100, 205, 3030]);
+        let template = default_template(&parsed);
+        assert!(template.starts_with("PIDs 100,205,3030 (aggregate): current"));
+        assert!(parse_template(&template).is_ok());
+    }
+
+    #[test]
+    fn pid_list_is_shortened() {
+        assert_eq!(pid_list(&[1, 2, 3]), "1,2,3");
+        assert_eq!(pid_list(&[1, 2, 3, 4, 5, 6, 7]), "1,2,3,4,5 and 2 more");
     }
 }
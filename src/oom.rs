@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::sys;
+
+
+// A process of the tree killed by the kernel for memory only disappears from /proc,
+// like one which exited. The kernel log tells the difference: "Out of memory: Killed
+// process 1234 (java) ...", or "Memory cgroup out of memory: ..." for a cgroup limit.
+// /dev/kmsg needs CAP_SYSLOG or kernel.dmesg_restrict=0; without it, the oom_kill
+// counter of /proc/vmstat (Linux 4.13) can only tell that the system killed something.
+
+#[derive(Debug, PartialEq)]
+pub struct OomKill {
+    pub pid: i32,
+    pub name: String,
+}
+
+
+#[derive(Debug)]
+pub struct OomWatch {
+    kmsg: Option<fs::File>,   // positioned after the records logged before the run
+    oom_kills: Option<u64>,   // the counter of /proc/vmstat at the start
+    seen: HashSet<i32>,       // every pid of the tree, a killed descendant counts too
+}
+
+impl OomWatch {
+    pub fn start() -> Self {
+        let kmsg = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(sys::O_NONBLOCK)
+            .open("/dev/kmsg")
+            .and_then(|mut kmsg| kmsg.seek(SeekFrom::End(0)).map(|_| kmsg))
+            .ok();
+        OomWatch{kmsg, oom_kills: read_oom_kill_count(), seen: HashSet::new()}
+    }
+
+    pub fn track(&mut self, pids: &HashSet<i32>) {
+        self.seen.extend(pids);
+    }
+
+    pub fn killed(&mut self) -> Option<OomKill> {
+        // the records logged since the start, up to the first which names a process of the tree
+        let kmsg = self.kmsg.as_mut()?;
+        let mut record = [0u8; 8192];
+        loop {
+            // every read returns a single record
+            match kmsg.read(&mut record) {
+                Ok(0) => return None,
+                Ok(n) => {
+                    if let Some(kill) = parse_kmsg_oom_kill(&String::from_utf8_lossy(&record[..n]))
+                        && self.seen.contains(&kill.pid) {
+                            return Some(kill);
+                        }
+                }
+                // EPIPE: records were overwritten before being read, the next ones are still there
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe || e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    pub fn system_killed_something(&self) -> bool {
+        // best effort without the kernel log: the system OOM-killed a process during the run
+        matches!((self.oom_kills, read_oom_kill_count()), (Some(before), Some(now)) if now > before)
+    }
+}
+
+
+pub fn parse_kmsg_oom_kill(record: &str) -> Option<OomKill> {
+    // "6,1234,5678901,-;Out of memory: Killed process 1234 (java) total-vm:..."
+    let message = record.split_once(';').map_or(record, |(_, message)| message);
+    let rest = &message[message.find("Killed process ")? + "Killed process ".len()..];
+    let (pid, rest) = rest.split_once(' ')?;
+    let name = rest.strip_prefix('(')?.split_once(')')?.0;
+    Some(OomKill{pid: pid.parse().ok()?, name: name.to_string()})
+}
+
+
+pub fn parse_oom_kill_count(vmstat: &str) -> Option<u64> {
+    vmstat.lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+}
+
+
+fn read_oom_kill_count() -> Option<u64> {
+    parse_oom_kill_count(&fs::read_to_string("/proc/vmstat").ok()?)
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kmsg_oom_kill() {
+        let record = "3,2891,123456789,-;Out of memory: Killed process 4242 (java) total-vm:8388608kB, anon-rss:4194304kB, file-rss:0kB\n";
+        assert_eq!(parse_kmsg_oom_kill(record), Some(OomKill{pid: 4242, name: "java".to_string()}));
+        let record = "3,2892,123456790,-;Memory cgroup out of memory: Killed process 77 (python3) total-vm:1024kB\n";
+        assert_eq!(parse_kmsg_oom_kill(record), Some(OomKill{pid: 77, name: "python3".to_string()}));
+        assert_eq!(parse_kmsg_oom_kill("6,2893,123456791,-;eth0: link up\n"), None);
+    }
+
+    #[test]
+    fn test_parse_oom_kill_count() {
+        let vmstat = "pgfault 123456\noom_kill 3\nnuma_hit 42\n";
+        assert_eq!(parse_oom_kill_count(vmstat), Some(3));
+        assert_eq!(parse_oom_kill_count("pgfault 123456\n"), None);
+    }
+
+    #[test]
+    fn test_oom_watch_without_kills() {
+        // whether or not /dev/kmsg is readable here, nothing of this tree was killed
+        let mut watch = OomWatch::start();
+        watch.track(&HashSet::from([std::process::id() as i32]));
+        assert_eq!(watch.killed(), None);
+    }
+}
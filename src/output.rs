@@ -0,0 +1,691 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::color::bold;
+use crate::formats::{csv_header, render_csv, render_influx, render_json, render_prometheus, Columns};
+use crate::gzip::GzipWriter;
+use crate::sys;
+use crate::template_engine::{MemorySample, Template};
+
+
+#[derive(Debug)]
+pub enum OutputSpec {
+    Stdout,
+    File(PathBuf),
+    NoFollowFile(PathBuf),   // --no-follow: refuse to write through a symlink
+    Socket(PathBuf),
+    Tcp(String),
+    Gzip(Box<OutputSpec>),   // --gzip, around one of the file outputs
+    Buffered(Box<OutputSpec>, usize),   // --buffer-size, around one of the file outputs
+}
+
+#[derive(Debug)]
+pub enum Output {
+    File(fs::File),
+    Gzip(GzipWriter<fs::File>),
+    Buffered(io::BufWriter<fs::File>),
+    Stdout(io::Stdout),
+    Stderr(io::Stderr),
+    Socket(SocketOutput),
+    Tcp(TcpOutput),
+}
+
+
+#[derive(Debug)]
+pub struct SocketOutput {
+    // a unix socket peer may restart: records are dropped while it is away
+    // and the connection is tried again on the next record
+    path: PathBuf,
+    stream: Option<UnixStream>,
+}
+
+impl SocketOutput {
+    pub fn connect(path: PathBuf) -> io::Result<Self> {
+        let stream = UnixStream::connect(&path)?;
+        Ok(SocketOutput{path, stream: Some(stream)})
+    }
+
+    fn reconnect(&mut self) -> Option<&mut UnixStream> {
+        match UnixStream::connect(&self.path) {
+            Ok(stream) => {
+                eprintln!("memimpact: reconnected to {}", self.path.display());
+                self.stream = Some(stream);
+            }
+            Err(_) => self.stream = None,
+        }
+        self.stream.as_mut()
+    }
+}
+
+impl Write for SocketOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => match self.reconnect() {
+                Some(stream) => stream,
+                None => return Ok(buf.len()),
+            },
+        };
+        match stream.write(buf) {
+            Ok(written) => Ok(written),
+            Err(e) => {
+                eprintln!("memimpact warning: lost {} ({}), dropping records until it is back", self.path.display(), e);
+                match self.reconnect() {
+                    Some(stream) => stream.write(buf),
+                    None => Ok(buf.len()),
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::File(f) => f.write(buf),
+            Output::Gzip(g) => g.write(buf),
+            Output::Buffered(b) => b.write(buf),
+            Output::Stdout(s) => s.write(buf),
+            Output::Stderr(s) => s.write(buf),
+            Output::Socket(s) => s.write(buf),
+            Output::Tcp(t) => t.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::File(f) => f.flush(),
+            Output::Gzip(g) => g.flush(),
+            Output::Buffered(b) => b.flush(),
+            Output::Stdout(s) => s.flush(),
+            Output::Stderr(s) => s.flush(),
+            Output::Socket(s) => s.flush(),
+            Output::Tcp(t) => t.flush(),
+        }
+    }
+}
+
+
+// --buffer-size: records reach the file by chunks of this size instead of one write each.
+// Above it the memory saved on syscalls is not worth the records lost on a crash
+pub const MAX_BUFFER_SIZE: usize = 16 << 20;
+
+
+// --tcp: while the collector is away, up to TCP_PENDING_RECORDS records are kept,
+// the oldest dropped first, and the connection is retried with an exponential backoff
+const TCP_PENDING_RECORDS: usize = 1000;
+const TCP_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const TCP_BACKOFF_MAX: Duration = Duration::from_secs(30);
+const TCP_TIMEOUT: Duration = Duration::from_secs(1);   // a stalled collector must not stall the sampling
+
+
+#[derive(Debug)]
+pub struct TcpOutput {
+    address: String,
+    stream: Option<TcpStream>,
+    pending: VecDeque<Vec<u8>>,
+    backoff: Duration,
+    next_attempt: Instant,
+}
+
+fn connect_tcp(address: &str) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", address));
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TCP_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+impl TcpOutput {
+    pub fn connect(address: String) -> io::Result<Self> {
+        let stream = connect_tcp(&address)?;
+        Ok(TcpOutput{
+            address,
+            stream: Some(stream),
+            pending: VecDeque::new(),
+            backoff: TCP_BACKOFF_MIN,
+            next_attempt: Instant::now(),
+        })
+    }
+
+    fn queue(&mut self, record: &[u8]) {
+        if self.pending.len() == TCP_PENDING_RECORDS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(record.to_vec());
+    }
+
+    fn try_reconnect(&mut self) {
+        let now = Instant::now();
+        if now < self.next_attempt {
+            return;
+        }
+        match connect_tcp(&self.address) {
+            Ok(stream) => {
+                eprintln!("memimpact: reconnected to {}, sending {} pending records", self.address, self.pending.len());
+                self.stream = Some(stream);
+                self.backoff = TCP_BACKOFF_MIN;
+            }
+            Err(_) => {
+                self.next_attempt = now + self.backoff;
+                self.backoff = (self.backoff * 2).min(TCP_BACKOFF_MAX);
+            }
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+        while let Some(record) = self.pending.front() {
+            if let Err(e) = stream.write_all(record) {
+                eprintln!("memimpact warning: lost {} ({}), keeping up to {} records until it is back", self.address, e, TCP_PENDING_RECORDS);
+                self.stream = None;
+                self.next_attempt = Instant::now() + self.backoff;
+                return;
+            }
+            self.pending.pop_front();
+        }
+    }
+}
+
+impl Write for TcpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // the emitter writes one record per call, it is queued whole
+        self.queue(buf);
+        if self.stream.is_none() {
+            self.try_reconnect();
+        }
+        self.flush_pending();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_pending();
+        Ok(())
+    }
+}
+
+
+pub fn write_output<W: Write>(out: &mut W, text: &str){
+    match out.write_all(text.as_bytes()){
+		Ok(_) => (),
+		Err(e) => {eprintln!("Could not write output because {}", e);}
+    };
+}
+
+
+pub enum RecordFormat {
+    Template(Template),
+    Influx,
+    Json(Option<Columns>),   // the columns of --fields, or the default ones
+    Csv(Option<Columns>),
+    Prometheus,
+}
+
+
+pub struct Emitter {
+    format: RecordFormat,
+    buffer: String,          // reused between samples to avoid an allocation per record
+    null_terminated: bool,   // end records with \0 instead of \n, like find -print0
+}
+
+impl Emitter {
+    pub fn new(format: RecordFormat, null_terminated: bool) -> Self {
+        Emitter{format, buffer: String::new(), null_terminated}
+    }
+
+    pub fn header(&self) -> Option<String> {
+        // written once before the first sample, by the sinks which get every sample
+        let RecordFormat::Csv(columns) = &self.format else {
+            return None;
+        };
+        let mut header = csv_header(columns.as_ref());
+        if self.null_terminated {
+            terminate_with_null(&mut header);
+        }
+        Some(header)
+    }
+
+    pub fn emit_to(&mut self, sink: &mut Sink, sample: &MemorySample){
+        self.emit(&mut sink.output, sample, sink.template.as_ref(), sink.colorize);
+    }
+
+    pub fn emit<W: Write>(&mut self, out: &mut W, sample: &MemorySample, template: Option<&Template>, colorize: bool){
+        // the final summary goes through the same template as every other sample
+        let rendered = match (template, &self.format) {
+            (Some(template), _) | (None, RecordFormat::Template(template)) => template.render(sample, &mut self.buffer),
+            (None, RecordFormat::Influx) => render_influx(sample, &mut self.buffer),
+            (None, RecordFormat::Json(columns)) => render_json(sample, columns.as_ref(), &mut self.buffer),
+            (None, RecordFormat::Csv(columns)) => render_csv(sample, columns.as_ref(), &mut self.buffer),
+            (None, RecordFormat::Prometheus) => render_prometheus(sample, &mut self.buffer),
+        };
+        match rendered{
+            Ok(()) => {
+                if colorize && sample.is_final {
+                    bold(&mut self.buffer);
+                }
+                if self.null_terminated {
+                    terminate_with_null(&mut self.buffer);
+                }
+                write_output(out, &self.buffer)
+            }
+            Err(e) => eprintln!("error while writing ouput: {:?}", e)
+        };
+        self.buffer.clear();
+    }
+}
+
+
+fn terminate_with_null(record: &mut String) {
+    if record.ends_with('\n') {
+        record.pop();
+    }
+    record.push('\0');
+}
+
+
+pub fn setup_output(spec: OutputSpec) -> io::Result<Output> {
+    match spec {
+        OutputSpec::Stdout => Ok(Output::Stdout(io::stdout())),
+        OutputSpec::File(path) => {
+            let file = fs::File::create(path)?;
+            Ok(Output::File(file))
+        }
+        OutputSpec::NoFollowFile(path) => {
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .custom_flags(sys::O_NOFOLLOW)
+                .open(&path)
+                .map_err(|e| match e.raw_os_error() {
+                    Some(sys::ELOOP) => io::Error::other(format!("{} is a symlink, not following it (--no-follow)", path.display())),
+                    _ => e,
+                })?;
+            Ok(Output::File(file))
+        }
+        OutputSpec::Socket(path) => Ok(Output::Socket(SocketOutput::connect(path)?)),
+        OutputSpec::Tcp(address) => Ok(Output::Tcp(TcpOutput::connect(address)?)),
+        OutputSpec::Gzip(inner) => match setup_output(*inner)? {
+            Output::File(file) => Ok(Output::Gzip(GzipWriter::new(file)?)),
+            _ => Err(io::Error::other("--gzip only applies to --output-file")),
+        },
+        OutputSpec::Buffered(inner, capacity) => match setup_output(*inner)? {
+            Output::File(file) => Ok(Output::Buffered(io::BufWriter::with_capacity(capacity, file))),
+            _ => Err(io::Error::other("--buffer-size only applies to --output-file")),
+        },
+    }
+}
+
+
+impl Output {
+    pub fn finish(&mut self) -> io::Result<()> {
+        // a gzip output is only readable once its trailer is written
+        match self {
+            Output::Gzip(g) => g.finish(),
+            other => other.flush(),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum Verbosity {
+    EverySample,
+    SummaryOnly,   // only the final summary line is written
+}
+
+#[derive(Debug)]
+pub struct Sink {
+    pub output: Output,
+    pub verbosity: Verbosity,
+    pub colorize: bool,                 // only ever set for the terminal, see --color
+    pub template: Option<Template>,     // replaces the format of the emitter for this sink
+}
+
+
+pub fn setup_sinks(spec: OutputSpec, final_flag: bool, summary_only: bool) -> io::Result<Vec<Sink>> {
+    // --final silences every sink, --summary-only only silences the terminal
+    let mut sinks = Vec::new();
+    let to_file = !matches!(spec, OutputSpec::Stdout);
+    let verbosity = if final_flag || (summary_only && !to_file) {
+        Verbosity::SummaryOnly
+    } else {
+        Verbosity::EverySample
+    };
+    sinks.push(Sink{output: setup_output(spec)?, verbosity, colorize: false, template: None});
+    if summary_only && to_file {
+        sinks.push(Sink{output: Output::Stdout(io::stdout()), verbosity: Verbosity::SummaryOnly, colorize: false, template: None});
+    }
+    Ok(sinks)
+}
+
+
+pub fn finish_sinks(sinks: &mut [Sink], end_marker: Option<&str>) {
+    // --emit-end-marker: the marker is the last thing written, a consumer without it got a truncated run
+    for sink in sinks.iter_mut() {
+        if let Some(marker) = end_marker {
+            write_output(&mut sink.output, marker);
+        }
+        if let Err(e) = sink.output.finish() {
+            eprintln!("Could not complete the output because {}", e);
+        }
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_output_to_buffer() {
+        let mut buffer: Vec<u8> = Vec::new();
+        write_output(&mut buffer, "hello");
+        assert_eq!(buffer, b"hello");
+    }
+
+    #[test]
+    fn test_emit_final_sample_uses_template() {
+        let template = Template::parse("{Pid},{MaxBytes},{Final}\n").unwrap();
+        let sample = MemorySample{
+            pid: 42,
+            process_name: "(bash)",
+            max_bytes: 2048,
+            is_final: true,
+            ..Default::default()
+        };
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut emitter = Emitter::new(RecordFormat::Template(template), false);
+        emitter.emit(&mut buffer, &sample, None, false);
+        assert_eq!(buffer, b"42,2048,true\n");
+        assert!(emitter.buffer.is_empty());
+    }
+
+    #[test]
+    fn test_emit_null_terminated() {
+        let template = Template::parse("{ProcessName}\n").unwrap();
+        let sample = MemorySample{process_name: "(my\nproc)", ..Default::default()};
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut emitter = Emitter::new(RecordFormat::Template(template), true);
+        emitter.emit(&mut buffer, &sample, None, false);
+        emitter.emit(&mut buffer, &sample, None, false);
+        assert_eq!(buffer, b"(my\nproc)\0(my\nproc)\0");
+    }
+
+    #[test]
+    fn test_emit_influx() {
+        let sample = MemorySample{pid: 7, process_name: "sh", timestamp: 2, ..Default::default()};
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut emitter = Emitter::new(RecordFormat::Influx, false);
+        emitter.emit(&mut buffer, &sample, None, false);
+        assert_eq!(buffer, b"memimpact,pid=7,name=sh rss=0i,max=0i,private=0i,processes=0i,final=false 2000000000\n");
+    }
+
+    #[test]
+    fn test_emit_csv_header() {
+        assert_eq!(Emitter::new(RecordFormat::Influx, false).header(), None);
+        assert_eq!(Emitter::new(RecordFormat::Csv(None), false).header().as_deref(), Some("timestamp,pid,process_name,current_bytes,max_bytes\n"));
+        let mut emitter = Emitter::new(RecordFormat::Csv(None), true);
+        assert_eq!(emitter.header().as_deref(), Some("timestamp,pid,process_name,current_bytes,max_bytes\0"));
+        let mut buffer: Vec<u8> = Vec::new();
+        emitter.emit(&mut buffer, &MemorySample{pid: 7, process_name: "sh", timestamp: 2, ..Default::default()}, None, false);
+        assert_eq!(buffer, b"2,7,\"sh\",0,0\0");
+    }
+
+    #[test]
+    fn test_emit_colors_only_the_final_record() {
+        let template = Template::parse("{Pid}\n").unwrap();
+        let mut emitter = Emitter::new(RecordFormat::Template(template), true);
+        let mut buffer: Vec<u8> = Vec::new();
+        emitter.emit(&mut buffer, &MemorySample{pid: 1, ..Default::default()}, None, true);
+        emitter.emit(&mut buffer, &MemorySample{pid: 2, is_final: true, ..Default::default()}, None, true);
+        assert_eq!(buffer, b"1\0\x1b[1m2\x1b[0m\0");
+    }
+
+    #[test]
+    fn test_emit_with_its_own_template() {
+        let sample = MemorySample{pid: 7, max_bytes: 2048, ..Default::default()};
+        let template = Template::parse("{Pid} {MaxHuman}\n").unwrap();
+        let mut emitter = Emitter::new(RecordFormat::Influx, false);
+        let mut buffer: Vec<u8> = Vec::new();
+        emitter.emit(&mut buffer, &sample, Some(&template), false);
+        emitter.emit(&mut buffer, &sample, None, false);
+        assert_eq!(buffer, b"7 2KiB\nmemimpact,pid=7,name= rss=0i,max=2048i,private=0i,processes=0i,final=false 0\n");
+    }
+
+    #[test]
+    fn test_terminate_with_null_without_newline() {
+        let mut record = "42".to_string();
+        terminate_with_null(&mut record);
+        assert_eq!(record, "42\0");
+    }
+
+    #[test]
+    fn test_sinks_default_streams_to_stdout() {
+        let sinks = setup_sinks(OutputSpec::Stdout, false, false).unwrap();
+        assert_eq!(sinks.len(), 1);
+        assert!(matches!(sinks[0].output, Output::Stdout(_)));
+        assert_eq!(sinks[0].verbosity, Verbosity::EverySample);
+    }
+
+    #[test]
+    fn test_sinks_summary_only_on_stdout() {
+        let sinks = setup_sinks(OutputSpec::Stdout, false, true).unwrap();
+        assert_eq!(sinks.len(), 1);
+        assert_eq!(sinks[0].verbosity, Verbosity::SummaryOnly);
+    }
+
+    #[test]
+    fn test_sinks_summary_only_keeps_file_streaming() {
+        let path = std::env::temp_dir().join(format!("memimpact-sinks-{}.txt", std::process::id()));
+        let sinks = setup_sinks(OutputSpec::File(path.clone()), false, true).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(sinks.len(), 2);
+        assert!(matches!(sinks[0].output, Output::File(_)));
+        assert_eq!(sinks[0].verbosity, Verbosity::EverySample);
+        assert!(matches!(sinks[1].output, Output::Stdout(_)));
+        assert_eq!(sinks[1].verbosity, Verbosity::SummaryOnly);
+    }
+
+    #[test]
+    fn test_sinks_final_silences_file() {
+        let path = std::env::temp_dir().join(format!("memimpact-final-{}.txt", std::process::id()));
+        let sinks = setup_sinks(OutputSpec::File(path.clone()), true, false).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(sinks.len(), 1);
+        assert_eq!(sinks[0].verbosity, Verbosity::SummaryOnly);
+    }
+
+    #[test]
+    fn test_finish_sinks_writes_end_marker() {
+        let path = std::env::temp_dir().join(format!("memimpact-end-{}.txt", std::process::id()));
+        let mut sinks = setup_sinks(OutputSpec::File(path.clone()), false, false).unwrap();
+        write_output(&mut sinks[0].output, "1,2\n");
+        finish_sinks(&mut sinks, Some("# END\n"));
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(content, "1,2\n# END\n");
+    }
+
+    #[test]
+    fn test_no_follow_refuses_symlinks() {
+        let dir = std::env::temp_dir();
+        let target = dir.join(format!("memimpact-nofollow-target-{}.txt", std::process::id()));
+        let link = dir.join(format!("memimpact-nofollow-link-{}.txt", std::process::id()));
+        fs::write(&target, "precious").unwrap();
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let err = setup_output(OutputSpec::NoFollowFile(link.clone())).unwrap_err();
+        let content = fs::read_to_string(&target).unwrap();
+        let plain = dir.join(format!("memimpact-nofollow-plain-{}.txt", std::process::id()));
+        let opened = setup_output(OutputSpec::NoFollowFile(plain.clone()));
+        fs::remove_file(&link).unwrap();
+        fs::remove_file(&target).unwrap();
+        fs::remove_file(&plain).unwrap();
+
+        assert!(err.to_string().contains("is a symlink"));
+        assert_eq!(content, "precious");
+        assert!(matches!(opened, Ok(Output::File(_))));
+    }
+
+    #[test]
+    fn test_gzip_output_is_finished() {
+        let path = std::env::temp_dir().join(format!("memimpact_gzip_{}.gz", std::process::id()));
+        let mut output = setup_output(OutputSpec::Gzip(Box::new(OutputSpec::File(path.clone())))).unwrap();
+        write_output(&mut output, "1234,42\n");
+        output.finish().unwrap();
+        let decompressed = std::process::Command::new("gzip").arg("-dc").arg(&path).output().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(decompressed.status.success());
+        assert_eq!(decompressed.stdout, b"1234,42\n");
+    }
+
+    #[test]
+    fn test_buffered_output_is_flushed() {
+        let path = std::env::temp_dir().join(format!("memimpact_buffered_{}.txt", std::process::id()));
+        let mut output = setup_output(OutputSpec::Buffered(Box::new(OutputSpec::File(path.clone())), 4096)).unwrap();
+        write_output(&mut output, "1234,42\n");
+        let before_finish = fs::read(&path).unwrap();
+        output.finish().unwrap();
+        let after_finish = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(before_finish, b"");
+        assert_eq!(after_finish, b"1234,42\n");
+    }
+
+    #[test]
+    fn test_socket_output() {
+        use std::io::Read;
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("memimpact-socket-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut output = setup_output(OutputSpec::Socket(path.clone())).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        write_output(&mut output, "first\n");
+        drop(output);
+        let mut received = String::new();
+        peer.read_to_string(&mut received).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(received, "first\n");
+    }
+
+    #[test]
+    fn test_socket_output_reconnects() {
+        use std::io::Read;
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("memimpact-reconnect-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut output = SocketOutput::connect(path.clone()).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        drop(peer);
+
+        // the first write after the peer left may still succeed, the next ones fail and reconnect
+        for _ in 0..3 {
+            output.write_all(b"lost\n").unwrap();
+        }
+        let (mut peer, _) = listener.accept().unwrap();
+        output.write_all(b"back\n").unwrap();
+        drop(output);
+        let mut received = String::new();
+        peer.read_to_string(&mut received).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(received.ends_with("back\n"));
+    }
+
+    #[test]
+    fn test_socket_output_missing_peer() {
+        let path = std::env::temp_dir().join(format!("memimpact-missing-{}.sock", std::process::id()));
+        assert!(setup_output(OutputSpec::Socket(path)).is_err());
+    }
+
+    #[test]
+    fn test_tcp_output() {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut output = setup_output(OutputSpec::Tcp(address)).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        write_output(&mut output, "first\n");
+        write_output(&mut output, "second\n");
+        drop(output);
+        let mut received = String::new();
+        peer.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "first\nsecond\n");
+    }
+
+    #[test]
+    fn test_tcp_output_flushes_pending_on_reconnect() {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut output = TcpOutput::connect(listener.local_addr().unwrap().to_string()).unwrap();
+        let (peer, _) = listener.accept().unwrap();
+        drop(peer);
+
+        // writes to a closed peer only fail after the kernel got the reset
+        while output.stream.is_some() {
+            output.write_all(b"lost\n").unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        output.write_all(b"kept\n").unwrap();
+        assert!(output.stream.is_none());
+        output.next_attempt = Instant::now();
+        output.write_all(b"back\n").unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        drop(output);
+        let mut received = String::new();
+        peer.read_to_string(&mut received).unwrap();
+        assert!(received.ends_with("kept\nback\n"));
+    }
+
+    #[test]
+    fn test_tcp_output_drops_oldest_when_full() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut output = TcpOutput::connect(listener.local_addr().unwrap().to_string()).unwrap();
+        output.stream = None;
+        output.next_attempt = Instant::now() + Duration::from_secs(3600);
+        for i in 0..TCP_PENDING_RECORDS + 5 {
+            output.write_all(format!("{}\n", i).as_bytes()).unwrap();
+        }
+        assert_eq!(output.pending.len(), TCP_PENDING_RECORDS);
+        assert_eq!(output.pending.front().unwrap(), b"5\n");
+    }
+}
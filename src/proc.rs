@@ -0,0 +1,1236 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::error::MemimpactError;
+
+
+// Reads /proc files into a buffer kept from one read to the next: at 10Hz over
+// a large tree, a fresh String per file per sample was most of the allocations.
+#[derive(Debug)]
+pub struct ProcReader {
+    path: String,
+    buffer: Vec<u8>,
+    lossy: String,   // the buffer decoded, when it is not valid UTF-8
+}
+
+impl ProcReader {
+    pub fn new() -> Self {
+        // /proc/[pid]/status is the biggest file read, about 1.5KiB
+        ProcReader{path: String::with_capacity(32), buffer: Vec::with_capacity(4096), lossy: String::new()}
+    }
+
+    pub fn read_bytes(&mut self, pid: i32, file: &str) -> Option<&[u8]> {
+        // stat and statm are read at every sample for every process, their parsers take
+        // the bytes as they are: numbers are ASCII, no need to validate them as UTF-8
+        self.path.clear();
+        write!(self.path, "/proc/{}/{}", pid, file).ok()?;
+        let (path, buffer) = (&self.path, &mut self.buffer);
+        retry_transient(|| {
+            buffer.clear();
+            fs::File::open(path)?.read_to_end(buffer)
+        }).ok()?;
+        Some(&self.buffer)
+    }
+
+    pub fn read(&mut self, pid: i32, file: &str) -> Option<&str> {
+        self.read_bytes(pid, file)?;
+        Some(self.decoded())
+    }
+
+    fn decoded(&mut self) -> &str {
+        match std::str::from_utf8(&self.buffer) {
+            Ok(content) => content,
+            Err(_) => {
+                // a process name can hold any byte, it must not hide the process from the tree.
+                // Decoded apart, the buffer keeps its allocation
+                self.lossy.clear();
+                for chunk in self.buffer.utf8_chunks() {
+                    self.lossy.push_str(chunk.valid());
+                    if !chunk.invalid().is_empty() {
+                        self.lossy.push(char::REPLACEMENT_CHARACTER);
+                    }
+                }
+                &self.lossy
+            }
+        }
+    }
+
+    pub fn count_lines(&mut self, pid: i32, file: &str) -> Option<u64> {
+        // /proc/[pid]/maps can weigh megabytes, it is read by chunks and never decoded
+        self.path.clear();
+        write!(self.path, "/proc/{}/{}", pid, file).ok()?;
+        let mut file = retry_transient(|| fs::File::open(&self.path)).ok()?;
+        self.buffer.resize(self.buffer.capacity().max(4096), 0);
+        let mut lines = 0;
+        loop {
+            match file.read(&mut self.buffer) {
+                Ok(0) => return Some(lines),
+                Ok(n) => lines += self.buffer[..n].iter().filter(|&&byte| byte == b'\n').count() as u64,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+
+fn is_process_gone(e: &io::Error) -> bool {
+    // the directory of an exited process disappears (ENOENT), or its files stop answering (ESRCH)
+    e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(3)
+}
+
+fn retry_transient<T>(mut read: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    // a read failing for another reason (EACCES, EAGAIN under contention) is tried once more,
+    // otherwise a live process would count for nothing in the total
+    match read() {
+        Err(e) if !is_process_gone(&e) => read(),
+        result => result,
+    }
+}
+
+
+pub fn list_processes() -> Vec<i32> {
+    let mut pids = Vec::new();
+
+    if let Ok(entries) = fs::read_dir("/proc") {
+        for entry in entries.flatten() {              // ignore invalid directory entries
+            if let Ok(metadata) = entry.metadata() && metadata.is_dir() {  // ignore metadata errors
+               if let Some(name) = entry.file_name().to_str()
+                    && let Ok(pid) = name.parse::<i32>() {
+                        pids.push(pid);
+                    }
+            }
+        }
+    }
+    pids
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProcessState{
+	R,      //Running
+    S,      //Sleeping in an interruptible wait
+    D,      //Waiting in uninterruptible disk sleep
+    Z,      //Zombie
+    T,      //Stopped (on a signal) or (before Linux2.6.33) trace stopped or Tracing stop (Linux 2.6.33 onward)
+    W,      //Paging (only before Linux 2.6.0) or Waking (Linux 2.6.33 to 3.13 only)
+    X,      //Dead (from Linux 2.6.0 onward)
+    K,      //Wakekill (Linux 2.6.33 to 3.13 only)
+    P,      //Parked (Linux 3.9 to 3.13 only)
+    I,      //Idle (Linux 4.14 onward)
+}
+
+
+impl TryFrom<u8> for ProcessState {
+    type Error = ProcStatError;
+
+    fn try_from(c: u8) -> Result<Self, Self::Error> {
+        match c {
+            b'R' => Ok(ProcessState::R),
+            b'S' => Ok(ProcessState::S),
+            b'D' => Ok(ProcessState::D),
+            b'Z' => Ok(ProcessState::Z),
+            b'T' => Ok(ProcessState::T),
+            b't' => Ok(ProcessState::T),   // tracing stop since Linux 2.6.33, a stop all the same
+            b'W' => Ok(ProcessState::W),
+            b'X' => Ok(ProcessState::X),
+            b'K' => Ok(ProcessState::K),
+            b'P' => Ok(ProcessState::P),
+            b'I' => Ok(ProcessState::I),
+            _ => Err(ProcStatError::UnsupportedKernelLayout),
+        }
+    }
+}
+
+
+
+#[derive(Debug, PartialEq)]
+#[allow(dead_code)]
+pub struct ProcStat<'a>{
+    pub pid: i32,
+    pub comm: &'a [u8],   // with its parentheses, any byte the process set
+    pub state: ProcessState,
+    pub ppid: i32,
+    pub start_time: Option<u64>,   // in clock ticks since boot, tells apart two processes given the same pid
+}
+
+
+#[derive(Debug)]
+pub enum ProcStatError {
+    InvalidFormat,
+    UnsupportedKernelLayout,
+}
+
+
+fn parse_decimal(digits: &[u8]) -> Option<u64> {
+    // the numbers of /proc, without the UTF-8 validation of str::parse
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |n, &digit| {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        n.checked_mul(10)?.checked_add((digit - b'0') as u64)
+    })
+}
+
+
+pub fn parse_proc_stat_bytes(content: &[u8]) -> Result<ProcStat<'_>, ProcStatError> {
+	// because the 2nd colum is the process name and can contain whitespaces
+	// see https://man7.org/linux/man-pages/man5/proc_pid_stat.5.html
+    let open = content.iter().position(|&c| c == b'(').ok_or(ProcStatError::InvalidFormat)?;
+    let close = content[open + 1..]
+        .iter()
+        .rposition(|&c| c == b')')
+        .map(|i| open + 1 + i)
+        .ok_or(ProcStatError::InvalidFormat)?;
+
+    // pid
+    if open < 2 {
+        return Err(ProcStatError::InvalidFormat);
+    }
+    let pid = parse_decimal(&content[..open - 1])
+        .and_then(|pid| i32::try_from(pid).ok())
+        .ok_or(ProcStatError::InvalidFormat)?;
+
+	// comm
+    let comm = &content[open..=close];
+
+	// state
+    let after_comm = close + 2;
+    let state = content.get(after_comm).copied()
+        .ok_or(ProcStatError::UnsupportedKernelLayout)
+        .and_then(ProcessState::try_from)?;
+
+    // ppid
+    let rest = content.get(after_comm + 2..).ok_or(ProcStatError::InvalidFormat)?;
+    let next_space = rest.iter().position(|&c| c == b' ').ok_or(ProcStatError::InvalidFormat)?;
+    let ppid = parse_decimal(&rest[..next_space])
+        .and_then(|ppid| i32::try_from(ppid).ok())
+        .ok_or(ProcStatError::InvalidFormat)?;
+
+    // starttime, the 22nd field, 18 fields after ppid
+    let start_time = rest.split(|&c| c == b' ').nth(18).and_then(|field| parse_decimal(field.trim_ascii()));
+
+    Ok(ProcStat{pid, comm, state, ppid, start_time})
+}
+
+#[cfg(test)]
+pub fn parse_proc_stat(content: &str) -> Result<ProcStat<'_>, ProcStatError> {
+    parse_proc_stat_bytes(content.as_bytes())
+}
+
+pub fn get_process_name(pid: &i32) -> Result<String, MemimpactError> {
+    let path = format!("/proc/{}/stat", pid);
+    let contents = fs::read(&path)
+   	        .map_err(|_| MemimpactError::PidNotFound(*pid))?;
+    process_name_from_stat(pid, &contents)
+}
+
+
+fn process_name_from_stat(pid: &i32, contents: &[u8]) -> Result<String, MemimpactError> {
+    let proc_stat = parse_proc_stat_bytes(contents).map_err(|e| {
+        MemimpactError::UnsupportedKernel(format!(
+            "Unsupported /proc/{}/stat format ({:?}). \
+             Either the process name is or your system is currently not supported. \
+             Please open an issue with the complete /proc/pid/stat content and your kernel version.",
+            pid, e
+        ))
+    })?;
+
+    // the parentheses only delimit the name in the stat line, they are not part of it;
+    // comm is whatever the process set, not necessarily UTF-8: invalid bytes become U+FFFD
+    Ok(String::from_utf8_lossy(&proc_stat.comm[1..proc_stat.comm.len() - 1]).into_owned())
+}
+
+
+pub fn read_start_time(reader: &mut ProcReader, pid: i32) -> Option<u64> {
+    parse_proc_stat_bytes(reader.read_bytes(pid, "stat")?).ok()?.start_time
+}
+
+
+pub fn is_pid_reused(recorded: Option<u64>, current: Option<u64>) -> bool {
+    // once a target exits, the kernel can give its pid to an unrelated process, started later;
+    // without both start times nothing tells them apart, the pid is trusted
+    matches!((recorded, current), (Some(before), Some(now)) if before != now)
+}
+
+
+pub fn is_stopped(pid: &i32) -> bool {
+    // stopped by a signal or by a debugger, its memory cannot change until it resumes
+    fs::read(format!("/proc/{}/stat", pid)).ok()
+        .is_some_and(|content| parse_proc_stat_bytes(&content).is_ok_and(|stat| stat.state == ProcessState::T))
+}
+
+
+pub fn parse_cmdline(content: &[u8]) -> String {
+    // arguments are separated (and terminated) by NUL bytes
+    // see https://man7.org/linux/man-pages/man5/proc_pid_cmdline.5.html
+    let trimmed = content.strip_suffix(b"\0").unwrap_or(content);
+    String::from_utf8_lossy(trimmed).replace('\0', " ")
+}
+
+
+// the kernel allows command lines of several megabytes, a long classpath is enough
+// to reach them: only their beginning is read and matched
+pub const MAX_CMDLINE_LEN: u64 = 64 * 1024;
+
+
+pub fn read_cmdline(pid: &i32) -> Option<String> {
+    let mut contents = Vec::new();
+    fs::File::open(format!("/proc/{}/cmdline", pid)).ok()?
+        .take(MAX_CMDLINE_LEN)
+        .read_to_end(&mut contents).ok()?;
+    Some(parse_cmdline(&contents))
+}
+
+
+pub fn parse_nspid(status: &str) -> Option<Vec<i32>> {
+    // NSpid lists the pid of the process in every nested namespace, outermost first
+    let line = status.lines().find_map(|line| line.strip_prefix("NSpid:"))?;
+    line.split_whitespace().map(|pid| pid.parse().ok()).collect()
+}
+
+
+pub fn parse_namespace_id(link: &str) -> Option<u64> {
+    // the target of /proc/[pid]/ns/pid, e.g. pid:[4026531836]
+    link.strip_prefix("pid:[")?.strip_suffix(']')?.parse().ok()
+}
+
+
+pub fn pid_namespace(pid: &i32) -> Option<u64> {
+    let link = fs::read_link(format!("/proc/{}/ns/pid", pid)).ok()?;
+    parse_namespace_id(link.to_str()?)
+}
+
+
+pub fn translate_namespace_pid(inner_pid: i32, namespace: Option<u64>) -> Result<i32, String> {
+    // finds the host pid of the process seen as inner_pid inside a container;
+    // without a namespace, only processes of nested namespaces match and the match must be unique
+    let mut matches = Vec::new();
+    for pid in list_processes() {
+        let Some(nspid) = fs::read_to_string(format!("/proc/{}/status", pid)).ok().and_then(|s| parse_nspid(&s)) else {
+            continue;
+        };
+        if nspid.last() != Some(&inner_pid) {
+            continue;
+        }
+        let in_namespace = match namespace {
+            Some(namespace) => pid_namespace(&pid) == Some(namespace),
+            None => nspid.len() > 1,
+        };
+        if in_namespace {
+            matches.push(pid);
+        }
+    }
+    match matches.as_slice() {
+        [host_pid] => Ok(*host_pid),
+        [] => Err(format!("no process has pid {} in the requested namespace", inner_pid)),
+        _ => Err(format!(
+            "pid {} exists in several namespaces (host pids {:?}), give one with --pid-ns",
+            inner_pid, matches,
+        )),
+    }
+}
+
+
+pub fn get_map_pid_to_ppid(reader: &mut ProcReader) -> HashMap<i32, i32> {
+    // list directories insde /proc and foreach read its stat
+    // returns a map of i32 -> i32, each representing a pid to its ppid 
+    let mut map = HashMap::<i32, i32>::new();
+    for pid in list_processes(){
+    	let contents = match reader.read_bytes(pid, "stat"){
+    		Some(c) => {c},
+    		None => {continue} // probably the process exited	
+    	};
+    	let proc_stat = match parse_proc_stat_bytes(contents) {
+	        Ok(p) => p,
+	        Err(_) => continue, // unsupported or malformed stat for this PID
+	    };
+   	    map.insert(proc_stat.pid, proc_stat.ppid);
+    }
+    map
+}
+
+
+#[derive(Debug)]
+pub enum ProcStatmError {
+    InvalidFormat,
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct Statm {
+    // all values are in bytes, converted from pages by the parser
+    pub size: u64,      // total program size (VSZ)
+    pub resident: u64,  // resident set size (RSS)
+    pub shared: u64,    // resident shared pages, i.e. backed by a file
+}
+
+impl Statm {
+    pub fn private(&self) -> u64 {
+        // approximation of the USS without parsing smaps: what is resident and not shared
+        // saturating since the counters are not read atomically by the kernel
+        self.resident.saturating_sub(self.shared)
+    }
+}
+
+
+// --metric: what CurrentBytes and MaxBytes measure
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Rss,
+    Uss,   // from smaps_rollup, what would be freed if the process died
+    Pss,   // from smaps_rollup, the shared pages split between the processes mapping them
+    Vsz,   // the address space, mapped whether or not it is resident
+}
+
+impl std::str::FromStr for Metric {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Metric, Self::Err> {
+        match input {
+            "rss" => Ok(Metric::Rss),
+            "uss" => Ok(Metric::Uss),
+            "pss" => Ok(Metric::Pss),
+            "vsz" => Ok(Metric::Vsz),
+            _ => Err(format!("unknown metric {:?}", input)),
+        }
+    }
+}
+
+
+pub fn saturating_total(values: impl Iterator<Item = u64>) -> (u64, bool) {
+    // sums without wrapping, the flag tells if u64::MAX was reached
+    let mut total: u64 = 0;
+    let mut saturated = false;
+    for value in values {
+        total = total.checked_add(value).unwrap_or_else(|| {
+            saturated = true;
+            u64::MAX
+        });
+    }
+    (total, saturated)
+}
+
+
+pub fn parse_statm_bytes(content: &[u8], page_size: u64) -> Result<Statm, ProcStatmError> {
+    // statm counts pages: every field goes through the same page size, so that no metric
+    // derived from it can get its own, possibly wrong, multiplier
+    let mut fields = content.split(|&c| c == b' ').map(|f| parse_decimal(f.trim_ascii()));
+    let mut next = || match fields.next() {
+        Some(Some(n)) => Ok(n.saturating_mul(page_size)),
+        _ => Err(ProcStatmError::InvalidFormat),
+    };
+    Ok(Statm{size: next()?, resident: next()?, shared: next()?})
+}
+
+#[cfg(test)]
+pub fn parse_statm_fields(content: &str, page_size: u64) -> Result<Statm, ProcStatmError> {
+    parse_statm_bytes(content.as_bytes(), page_size)
+}
+
+
+pub fn read_statm(reader: &mut ProcReader, pid: &i32, page_size: u64) -> Option<Statm> {
+    // see https://man7.org/linux/man-pages/man5/proc_pid_statm.5.html
+    let contents = reader.read_bytes(*pid, "statm")?;
+    parse_statm_bytes(contents, page_size).ok()
+}
+
+
+pub fn build_children_index(parent_of: &HashMap<i32, i32>) -> HashMap<i32, Vec<i32>> {
+    // the reverse of the pid -> ppid map, built once per sample for every walk of the tree
+    let mut children_of = HashMap::<i32, Vec<i32>>::new();
+    for (&pid, &ppid) in parent_of.iter() {
+        children_of.entry(ppid).or_default().push(pid);
+    }
+    children_of
+}
+
+
+pub fn find_descendants(
+    children_of: &HashMap<i32, Vec<i32>>,
+    target_pids: &[i32],
+) -> HashSet<i32> {
+	// Given a mapping of ppid -> children and target pids,
+	// return all descendants of the targets (including the targets themself)
+    let mut descendants: HashSet<i32> = target_pids.iter().copied().collect();
+    let mut to_visit: Vec<i32> = descendants.iter().copied().collect();
+    while let Some(pid) = to_visit.pop() {
+        // each process is visited once, whatever the depth of the tree
+        for &child in children_of.get(&pid).into_iter().flatten() {
+            if descendants.insert(child) {
+                to_visit.push(child);
+            }
+        }
+    }
+    descendants
+}
+
+
+pub fn descendants_of_init(parent_of: &HashMap<i32, i32>, children_of: &HashMap<i32, Vec<i32>>) -> HashSet<i32> {
+    // what find_descendants returns for pid 1, without walking its deep tree: every process
+    // but the other roots and their small trees, kthreadd and, in a container, what
+    // docker exec or nsenter started (their parent is outside the namespace, so 0)
+    let other_roots: Vec<i32> = parent_of.iter()
+        .filter(|(pid, ppid)| **ppid == 0 && **pid != 1)
+        .map(|(pid, _)| *pid)
+        .collect();
+    let outside = find_descendants(children_of, &other_roots);
+    parent_of.keys().copied().filter(|pid| !outside.contains(pid)).collect()
+}
+
+
+pub fn exclude_subtree(descendants: &mut HashSet<i32>, children_of: &HashMap<i32, Vec<i32>>, root: i32) {
+    // used to leave memimpact out when it runs inside the monitored tree
+    if descendants.contains(&root) {
+        let subtree = find_descendants(children_of, &[root]);
+        descendants.retain(|pid| !subtree.contains(pid));
+    }
+}
+
+
+pub fn is_kernel_thread(pid: i32, parent_of: &HashMap<i32, i32>) -> bool {
+    // kernel threads are kthreadd (pid 2) and its direct children
+    // they have no userspace memory so they only add noise to diagnostics
+    pid == 2 || parent_of.get(&pid) == Some(&2)
+}
+
+
+pub fn targets_alive(parent_of: &HashMap<i32, i32>, target_pids: &[i32]) -> bool {
+    // the tree of a target which exited is still measured through its other targets,
+    // the run ends with the last of them
+    target_pids.iter().any(|pid| parent_of.contains_key(pid))
+}
+
+
+fn parse_kb_value(value: &str) -> Option<u64> {
+    // "   1200 kB" in /proc/[pid]/status and smaps, to bytes
+    value.trim().trim_end_matches("kB").trim().parse::<u64>().ok()?.checked_mul(1024)
+}
+
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RssBreakdown {
+    // in bytes, from the KiB of /proc/[pid]/status
+    pub anon: u64,    // heap, stacks and private mappings
+    pub file: u64,    // mmap'd files, binaries and libraries included
+    pub shmem: u64,   // shared memory, tmpfs and shared anonymous mappings
+}
+
+pub fn parse_status_rss(content: &str) -> Option<RssBreakdown> {
+    // the three lines exist since Linux 4.5, all of them are needed
+    let (mut anon, mut file, mut shmem) = (None, None, None);
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let slot = match key {
+            "RssAnon" => &mut anon,
+            "RssFile" => &mut file,
+            "RssShmem" => &mut shmem,
+            _ => continue,
+        };
+        *slot = parse_kb_value(value);
+    }
+    Some(RssBreakdown{anon: anon?, file: file?, shmem: shmem?})
+}
+
+pub fn parse_status_field(content: &str, name: &str) -> Option<u64> {
+    // a "Name:\t   1200 kB" line of /proc/[pid]/status, in bytes
+    content.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .and_then(parse_kb_value)
+}
+
+pub fn parse_status_hugetlb(content: &str) -> Option<u64> {
+    // hugetlbfs pages mapped by the process in bytes, since Linux 4.4; they are not in the RSS
+    parse_status_field(content, "HugetlbPages")
+}
+
+pub fn read_swap(reader: &mut ProcReader, pid: &i32) -> Option<u64> {
+    // the anonymous memory of the process moved to swap, in bytes; None when it exited.
+    // Kernel threads have no VmSwap line, nothing of theirs can be swapped
+    let content = reader.read(*pid, "status")?;
+    Some(parse_status_field(content, "VmSwap").unwrap_or(0))
+}
+
+pub fn read_hugetlb(reader: &mut ProcReader, pid: &i32) -> Option<u64> {
+    parse_status_hugetlb(reader.read(*pid, "status")?)
+}
+
+pub fn parse_smaps_rollup_uss(content: &str) -> Option<u64> {
+    // the pages only this process maps, in bytes; both lines are needed
+    let (mut clean, mut dirty) = (None, None);
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let slot = match key {
+            "Private_Clean" => &mut clean,
+            "Private_Dirty" => &mut dirty,
+            _ => continue,
+        };
+        *slot = parse_kb_value(value);
+    }
+    clean?.checked_add(dirty?)
+}
+
+pub fn read_uss(reader: &mut ProcReader, pid: &i32) -> Option<u64> {
+    // smaps_rollup exists since Linux 4.14 and needs the ptrace access of the process,
+    // it walks every mapping in the kernel: far more costly than statm
+    // see https://www.kernel.org/doc/Documentation/ABI/testing/procfs-smaps_rollup
+    parse_smaps_rollup_uss(reader.read(*pid, "smaps_rollup")?)
+}
+
+pub fn parse_smaps_pss(content: &str) -> Option<u64> {
+    // in bytes; smaps_rollup has a single Pss line, smaps one per mapping.
+    // Pss_Anon, Pss_File... split the same total, they are not added again
+    let mut values = content.lines()
+        .filter_map(|line| line.strip_prefix("Pss:"))
+        .map(parse_kb_value)
+        .peekable();
+    values.peek()?;
+    values.try_fold(0u64, |total, value| total.checked_add(value?))
+}
+
+pub fn read_pss(reader: &mut ProcReader, pid: &i32) -> Option<u64> {
+    // same cost and ptrace access as the USS; before Linux 4.14 the mappings of smaps
+    // are summed, slower still. None when neither can be read, the pid is then skipped
+    if let Some(content) = reader.read(*pid, "smaps_rollup") {
+        return parse_smaps_pss(content);
+    }
+    parse_smaps_pss(reader.read(*pid, "smaps")?)
+}
+
+pub fn read_rss_breakdown(reader: &mut ProcReader, pid: &i32) -> Option<RssBreakdown> {
+    // see https://man7.org/linux/man-pages/man5/proc_pid_status.5.html
+    parse_status_rss(reader.read(*pid, "status")?)
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct MemoryPressure {
+    pub some_avg10: f64,   // % of time at least one task stalled on memory over the last 10s
+    pub full_avg10: f64,   // % of time all non-idle tasks stalled on memory over the last 10s
+}
+
+
+pub fn parse_cgroup_v2_path(content: &str) -> Option<&str> {
+    // see https://man7.org/linux/man-pages/man7/cgroups.7.html
+    // the unified hierarchy is the line with hierarchy-ID 0 and no controller list: "0::/path"
+    content.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+
+pub fn parse_cgroup_procs(content: &str) -> Vec<i32> {
+    // one pid per line, see https://docs.kernel.org/admin-guide/cgroup-v2.html
+    content.lines().filter_map(|line| line.trim().parse().ok()).collect()
+}
+
+
+pub fn read_cgroup_procs(dir: &Path) -> io::Result<Vec<i32>> {
+    // processes of a slice live in its child cgroups, so every level is read
+    let mut pids = parse_cgroup_procs(&fs::read_to_string(dir.join("cgroup.procs"))?);
+    for entry in fs::read_dir(dir)?.flatten() {
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            // a child cgroup removed meanwhile has no process left anyway
+            pids.extend(read_cgroup_procs(&entry.path()).unwrap_or_default());
+        }
+    }
+    Ok(pids)
+}
+
+
+pub fn parse_memory_pressure(content: &str) -> Option<MemoryPressure> {
+    // see https://docs.kernel.org/accounting/psi.html
+    // some avg10=0.00 avg60=0.00 avg300=0.00 total=0
+    // full avg10=0.00 avg60=0.00 avg300=0.00 total=0
+    let avg10 = |prefix: &str| -> Option<f64> {
+        let line = content.lines().find(|line| line.starts_with(prefix))?;
+        line.split(' ')
+            .find_map(|item| item.strip_prefix("avg10="))?
+            .parse()
+            .ok()
+    };
+    Some(MemoryPressure{
+        some_avg10: avg10("some ")?,
+        full_avg10: avg10("full ")?,
+    })
+}
+
+
+pub fn parse_meminfo_total(content: &str) -> Option<u64> {
+    // in bytes, the RAM usable by the kernel, a bit below the installed memory
+    content.lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(parse_kb_value)
+}
+
+pub fn read_mem_total() -> Option<u64> {
+    // inside a container, the RAM of the host, whatever the limit of the cgroup
+    parse_meminfo_total(&fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+
+pub fn read_memory_pressure(pid: &i32) -> Option<MemoryPressure> {
+    // prefer the pressure of the cgroup holding the target, fallback on the system-wide one
+    let mut candidates = Vec::new();
+    if let Ok(cgroup) = fs::read_to_string(format!("/proc/{}/cgroup", pid))
+        && let Some(path) = parse_cgroup_v2_path(&cgroup) {
+            candidates.push(format!("/sys/fs/cgroup{}/memory.pressure", path));
+            candidates.push(format!("/sys/fs/cgroup/unified{}/memory.pressure", path));  // hybrid hierarchy
+        }
+    candidates.push("/proc/pressure/memory".to_string());
+    candidates.iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .find_map(|content| parse_memory_pressure(&content))
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_transient() {
+        let mut attempts = 0;
+        let result = retry_transient(|| {
+            attempts += 1;
+            if attempts == 1 { Err(io::Error::from(io::ErrorKind::PermissionDenied)) } else { Ok(attempts) }
+        });
+        assert_eq!(result.unwrap(), 2);
+        // an exited process is not retried
+        let mut attempts = 0;
+        let result: io::Result<()> = retry_transient(|| {
+            attempts += 1;
+            Err(io::Error::from_raw_os_error(3))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+        assert!(is_process_gone(&io::Error::from(io::ErrorKind::NotFound)));
+    }
+
+    #[test]
+    fn test_parse_proc_stat_basic() {
+        let input = "1234 (bash) R 1 2 3 4";
+        let actual = parse_proc_stat(input).unwrap();
+
+        let expected = ProcStat{pid: 1234, comm: b"(bash)", state: ProcessState::R, ppid: 1, start_time: None};
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_proc_stat_with_spaces_in_name() {
+        let input = "5678 (my fancy process) S 10 20 30";
+        let actual = parse_proc_stat(input).unwrap();
+
+        let expected = ProcStat{pid: 5678, comm: b"(my fancy process)", state: ProcessState::S, ppid: 10, start_time: None};
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_proc_stat_with_paranthesis_in_name() {
+    	// real world test case
+        let input = "3674 ((sd-pam)) S 3672 3672 3672 0 -1 4194624 49 0 0 0 0 0 0 0 20 0 1 0 4058 17170432 450 18446744073709551615 1 1 0 0 0 0 0 4096 0 0 0 0 17 8 0 0 0 0 0 0 0 0 0 0 0 0 0";
+        let actual = parse_proc_stat(input).unwrap();
+
+        let expected = ProcStat{pid: 3674, comm: b"((sd-pam))", state: ProcessState::S, ppid: 3672, start_time: Some(4058)};
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_proc_stat_start_time() {
+        // a name with spaces and parentheses does not shift the fields after it
+        let input = "812 (a (b) c) S 1 812 812 0 -1 4194560 120 0 0 0 3 1 0 0 20 0 1 0 987654321 1024 10 18446744073709551615\n";
+        assert_eq!(parse_proc_stat(input).unwrap().start_time, Some(987654321));
+        let input = "812 (sh) S 1 812 812 0 -1 4194560 120 0 0 0 3 1 0 0 20 0 1 0 42\n";
+        assert_eq!(parse_proc_stat(input).unwrap().start_time, Some(42));
+        let self_pid = std::process::id() as i32;
+        let mut reader = ProcReader::new();
+        let start_time = read_start_time(&mut reader, self_pid);
+        assert!(start_time.is_some());
+        assert_eq!(read_start_time(&mut reader, self_pid), start_time);
+    }
+
+    #[test]
+    fn test_is_pid_reused() {
+        assert!(!is_pid_reused(Some(4058), Some(4058)));
+        assert!(is_pid_reused(Some(4058), Some(91234)));
+        assert!(!is_pid_reused(None, Some(91234)));
+        assert!(!is_pid_reused(Some(4058), None));
+    }
+
+
+    #[test]
+    fn test_parse_smaps_rollup_uss() {
+        let content = "00400000-7ffd5f3fe000 ---p 00000000 00:00 0                      [rollup]\n\
+                       Rss:                3456 kB\n\
+                       Pss:                1200 kB\n\
+                       Shared_Clean:       2000 kB\n\
+                       Shared_Dirty:         56 kB\n\
+                       Private_Clean:       400 kB\n\
+                       Private_Dirty:      1000 kB\n\
+                       Referenced:         3456 kB\n";
+        assert_eq!(parse_smaps_rollup_uss(content), Some(1400 * 1024));
+        assert_eq!(parse_smaps_rollup_uss("Private_Clean: 400 kB\n"), None);
+        assert_eq!(parse_smaps_rollup_uss(""), None);
+    }
+
+    #[test]
+    fn test_parse_smaps_pss() {
+        let rollup = "00400000-7ffd5f3fe000 ---p 00000000 00:00 0                      [rollup]\n\
+                      Rss:                3456 kB\n\
+                      Pss:                1200 kB\n\
+                      Pss_Anon:            900 kB\n\
+                      Pss_File:            300 kB\n\
+                      Private_Dirty:      1000 kB\n";
+        assert_eq!(parse_smaps_pss(rollup), Some(1200 * 1024));
+        let smaps = "55d4c8a00000-55d4c8a21000 r--p 00000000 08:01 1234    /usr/bin/bash\n\
+                     Rss:                 132 kB\n\
+                     Pss:                  44 kB\n\
+                     7ffd5f3dd000-7ffd5f3fe000 rw-p 00000000 00:00 0       [stack]\n\
+                     Rss:                  24 kB\n\
+                     Pss:                  24 kB\n";
+        assert_eq!(parse_smaps_pss(smaps), Some(68 * 1024));
+        assert_eq!(parse_smaps_pss("Rss: 3456 kB\n"), None);
+        assert_eq!(parse_smaps_pss(""), None);
+    }
+
+    #[test]
+    fn test_read_pss_kb_self() {
+        let self_pid = std::process::id() as i32;
+        let mut reader = ProcReader::new();
+        let statm = read_statm(&mut reader, &self_pid, 4096).unwrap();
+        if let Some(pss) = read_pss(&mut reader, &self_pid) {
+            assert!(pss > 0);
+            assert!(pss <= statm.resident * 2);
+        }
+    }
+
+    #[test]
+    fn test_read_uss_kb_self() {
+        let self_pid = std::process::id() as i32;
+        let mut reader = ProcReader::new();
+        let statm = read_statm(&mut reader, &self_pid, 4096).unwrap();
+        // only on kernels with smaps_rollup
+        if let Some(uss) = read_uss(&mut reader, &self_pid) {
+            assert!(uss > 0);
+            assert!(uss <= statm.resident * 2);
+        }
+    }
+
+    #[test]
+    fn test_metric_from_str() {
+        assert_eq!("rss".parse::<Metric>(), Ok(Metric::Rss));
+        assert_eq!("uss".parse::<Metric>(), Ok(Metric::Uss));
+        assert_eq!("vsz".parse::<Metric>(), Ok(Metric::Vsz));
+        assert_eq!("pss".parse::<Metric>(), Ok(Metric::Pss));
+        assert!("heap".parse::<Metric>().is_err());
+    }
+
+    #[test]
+    fn test_descendants_of_init_matches_find_descendants() {
+        // 2 is kthreadd, 20 a shell started by docker exec
+        let parent_of = HashMap::from([(1, 0), (2, 0), (3, 2), (4, 2), (10, 1), (11, 10), (12, 1), (20, 0), (21, 20)]);
+        let children_of = build_children_index(&parent_of);
+        assert_eq!(descendants_of_init(&parent_of, &children_of), find_descendants(&children_of, &[1]));
+        let parent_of = get_map_pid_to_ppid(&mut ProcReader::new());
+        let children_of = build_children_index(&parent_of);
+        assert_eq!(descendants_of_init(&parent_of, &children_of), find_descendants(&children_of, &[1]));
+    }
+
+    #[test]
+    fn test_count_lines() {
+        let self_pid = std::process::id() as i32;
+        let mut reader = ProcReader::new();
+        let expected = fs::read_to_string(format!("/proc/{}/maps", self_pid)).unwrap().lines().count() as u64;
+        let counted = reader.count_lines(self_pid, "maps").unwrap();
+        // the test harness threads can map memory in between
+        assert!(counted.abs_diff(expected) < 16, "{} != {}", counted, expected);
+        assert!(reader.count_lines(i32::MAX, "maps").is_none());
+        // the reader still reads whole files afterwards
+        assert!(reader.read(self_pid, "status").unwrap().starts_with("Name:"));
+    }
+
+    #[test]
+    fn test_get_process_name_without_parens() {
+        let mut child = std::process::Command::new("sleep").arg("10").spawn().unwrap();
+        let name = get_process_name(&(child.id() as i32));
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(name.unwrap(), "sleep");
+    }
+
+    #[test]
+    fn test_process_name_not_utf8() {
+        let stat = b"4242 (caf\xe9 \xff) S 1 4242 4242 0 -1 4194304 100 0 0 0 0 0 0 0 20 0 1 0 100 1000 10";
+        assert_eq!(process_name_from_stat(&4242, stat).unwrap(), "caf\u{fffd} \u{fffd}");
+    }
+
+    #[test]
+    fn test_proc_reader_reuses_its_buffer() {
+        let self_pid = std::process::id() as i32;
+        let mut reader = ProcReader::new();
+        let status = reader.read(self_pid, "status").unwrap().to_string();
+        assert!(status.starts_with("Name:"));
+        let capacity = reader.buffer.capacity();
+        // a missing process leaves the reader usable
+        assert!(reader.read(i32::MAX, "statm").is_none());
+        let statm = reader.read(self_pid, "statm").unwrap();
+        assert!(parse_statm_fields(statm, 4096).is_ok());
+        assert_eq!(reader.buffer.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_proc_reader_decodes_invalid_utf8_apart() {
+        let mut reader = ProcReader::new();
+        let capacity = reader.buffer.capacity();
+        reader.buffer.extend_from_slice(b"Name:\tcaf\xe9 \xff\nUmask:\t0022\n");
+        assert_eq!(reader.decoded(), "Name:\tcaf\u{fffd} \u{fffd}\nUmask:\t0022\n");
+        assert_eq!(reader.buffer.capacity(), capacity);
+        // the next file is valid again
+        let self_pid = std::process::id() as i32;
+        assert!(reader.read(self_pid, "status").unwrap().starts_with("Name:"));
+        assert_eq!(reader.buffer.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_parse_proc_stat_tracing_stop() {
+        let input = "4321 (gdb target) t 4300 4321 4300 0 -1 4194304";
+        assert_eq!(parse_proc_stat(input).unwrap().state, ProcessState::T);
+    }
+
+    #[test]
+    fn test_is_stopped() {
+        use crate::sys::{send_signal, Signal};
+        let mut child = std::process::Command::new("sleep").arg("10").spawn().unwrap();
+        let pid = child.id() as i32;
+        assert!(!is_stopped(&pid));
+        send_signal(pid, Signal::Stop).unwrap();
+        // the state changes asynchronously
+        let mut stopped = false;
+        for _ in 0..100 {
+            stopped = is_stopped(&pid);
+            if stopped {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(stopped);
+    }
+
+    #[test]
+    fn test_parse_proc_stat_bytes() {
+        // a name which is not UTF-8 does not prevent reading the pids
+        let input = b"4321 (caf\xe9) S 77 4321 4321 0 -1";
+        let stat = parse_proc_stat_bytes(input).unwrap();
+        assert_eq!((stat.pid, stat.comm, stat.ppid), (4321, &b"(caf\xe9)"[..], 77));
+        assert!(parse_proc_stat_bytes(b"4321 (bash)").is_err());
+        assert!(parse_proc_stat_bytes(b"-1 (bash) S 1 ").is_err());
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        assert_eq!(parse_decimal(b"0"), Some(0));
+        assert_eq!(parse_decimal(b"18446744073709551615"), Some(u64::MAX));
+        assert_eq!(parse_decimal(b"18446744073709551616"), None);
+        assert_eq!(parse_decimal(b""), None);
+        assert_eq!(parse_decimal(b"12a"), None);
+    }
+
+    #[test]
+    fn test_parse_proc_stat_invalid_missing_parens() {
+        let input = "9999 bash R 1 2 3";
+        let parts = parse_proc_stat(input);
+
+        assert!(parts.is_err());
+    }
+
+    #[test]
+    fn test_find_descendants_simple_tree() {
+        let mut map = HashMap::new();
+        map.insert(2, 1);
+        map.insert(3, 1);
+        map.insert(4, 2);
+        map.insert(5, 4);
+
+        let descendants = find_descendants(&build_children_index(&map), &[1]);
+
+        let expected: HashSet<i32> = [1, 2, 3, 4, 5].into_iter().collect();
+        assert_eq!(descendants, expected);
+    }
+
+    #[test]
+    fn test_find_descendants_leaf() {
+        let mut map = HashMap::new();
+        map.insert(2, 1);
+        map.insert(3, 1);
+
+        let descendants = find_descendants(&build_children_index(&map), &[2]);
+
+        let expected: HashSet<i32> = [2].into_iter().collect();
+        assert_eq!(descendants, expected);
+    }
+
+    #[test]
+    fn test_find_descendants_several_targets() {
+        let map = HashMap::from([(2, 1), (3, 1), (4, 3), (5, 3), (6, 5), (7, 1)]);
+        let descendants = find_descendants(&build_children_index(&map), &[2, 5, 5]);
+        assert_eq!(descendants, HashSet::from([2, 5, 6]));
+    }
+
+    #[test]
+    fn test_find_descendants_deep_chain() {
+        // a chain of 100000 processes, each the parent of the next: one pass per level over
+        // the whole map took depth x size steps, the index walks each process once
+        let depth = 100_000;
+        let map: HashMap<i32, i32> = (2..=depth).map(|pid| (pid, pid - 1)).collect();
+        let children_of = build_children_index(&map);
+        assert_eq!(find_descendants(&children_of, &[1]).len(), depth as usize);
+        assert_eq!(find_descendants(&children_of, &[depth - 9]).len(), 10);
+    }
+
+    #[test]
+    fn test_exclude_subtree() {
+        let mut map = HashMap::new();
+        map.insert(2, 1);
+        map.insert(3, 1);
+        map.insert(4, 3);
+        let children_of = build_children_index(&map);
+        let mut descendants = find_descendants(&children_of, &[1]);
+
+        exclude_subtree(&mut descendants, &children_of, 3);
+        let expected: HashSet<i32> = [1, 2].into_iter().collect();
+        assert_eq!(descendants, expected);
+
+        exclude_subtree(&mut descendants, &children_of, 42);
+        assert_eq!(descendants, expected);
+    }
+
+    #[test]
+    fn test_targets_alive() {
+        let mut map = HashMap::new();
+        map.insert(2, 1);
+        map.insert(3, 1);
+
+        assert!(targets_alive(&map, &[2, 3]));
+        assert!(targets_alive(&map, &[2, 4]));
+        assert!(!targets_alive(&map, &[4, 5]));
+    }
+
+    #[test]
+    fn test_parse_statm_valid() {
+        let input = "100 50 0 0 0 0 0";
+        assert_eq!(parse_statm_fields(input, 1).ok().map(|statm| statm.resident), Some(50));
+    }
+
+    #[test]
+    fn test_parse_statm_invalid() {
+        assert!(parse_statm_fields("invalid", 4096).is_err());
+    }
+
+    #[test]
+    fn test_parse_statm_fields() {
+        let input = "2000 500 120 30 0 400 0\n";
+        let expected = Statm{size: 8192000, resident: 2048000, shared: 491520};
+        assert_eq!(parse_statm_fields(input, 4096).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_statm_fields_page_size() {
+        // every field, and what is derived from them, scales with the page size
+        let input = "2000 500 120 30 0 400 0\n";
+        let small = parse_statm_fields(input, 4096).unwrap();
+        let big = parse_statm_fields(input, 65536).unwrap();
+        assert_eq!(big.size, small.size * 16);
+        assert_eq!(big.resident, small.resident * 16);
+        assert_eq!(big.shared, small.shared * 16);
+        assert_eq!(big.private(), small.private() * 16);
+    }
+
+    #[test]
+    fn test_parse_statm_fields_rss_per_page_size() {
+        // 500 resident pages
+        let input = "2000 500 120 30 0 400 0\n";
+        for (page_size, rss) in [(4096, 2000 * 1024), (16384, 8000 * 1024), (65536, 32000 * 1024)] {
+            assert_eq!(parse_statm_fields(input, page_size).unwrap().resident, rss);
+        }
+    }
+
+    #[test]
+    fn test_parse_statm_fields_truncated() {
+        assert!(parse_statm_fields("2000 500", 4096).is_err());
+    }
+
+    #[test]
+    fn test_saturating_total() {
+        assert_eq!(saturating_total([1, 2, 3].into_iter()), (6, false));
+        assert_eq!(saturating_total(std::iter::empty()), (0, false));
+        assert_eq!(saturating_total([u64::MAX - 1, 1].into_iter()), (u64::MAX, false));
+        assert_eq!(saturating_total([u64::MAX, 1, 5].into_iter()), (u64::MAX, true));
+    }
+
+    #[test]
+    fn test_statm_private() {
+        assert_eq!(Statm{size: 2000, resident: 500, shared: 120}.private(), 380);
+        // inconsistent read, clamped instead of underflowing
+        assert_eq!(Statm{size: 2000, resident: 100, shared: 120}.private(), 0);
+    }
+
+    #[test]
+    fn test_parse_cgroup_v2_path() {
+        let input = "12:memory:/legacy\n0::/user.slice/user-1000.slice/session-2.scope\n";
+        assert_eq!(parse_cgroup_v2_path(input), Some("/user.slice/user-1000.slice/session-2.scope"));
+    }
+
+    #[test]
+    fn test_parse_cgroup_procs() {
+        assert_eq!(parse_cgroup_procs("812\n813\n\n"), vec![812, 813]);
+        assert_eq!(parse_cgroup_procs(""), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_read_cgroup_procs_recurses() {
+        let root = std::env::temp_dir().join(format!("memimpact-cgroup-{}", std::process::id()));
+        fs::create_dir_all(root.join("worker.service")).unwrap();
+        fs::write(root.join("cgroup.procs"), "").unwrap();
+        fs::write(root.join("worker.service/cgroup.procs"), "42\n43\n").unwrap();
+        let pids = read_cgroup_procs(&root);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(pids.unwrap(), vec![42, 43]);
+    }
+
+    #[test]
+    fn test_parse_cgroup_v2_path_missing() {
+        assert_eq!(parse_cgroup_v2_path("4:memory:/docker/abc\n"), None);
+    }
+
+    #[test]
+    fn test_parse_nspid() {
+        let status = "Name:\tnginx\nTgid:\t51234\nNSpid:\t51234\t7\nPPid:\t51200\n";
+        assert_eq!(parse_nspid(status), Some(vec![51234, 7]));
+        assert_eq!(parse_nspid("Name:\tbash\n"), None);
+        assert_eq!(parse_nspid("NSpid:\t12\tx\n"), None);
+    }
+
+    #[test]
+    fn test_parse_namespace_id() {
+        assert_eq!(parse_namespace_id("pid:[4026531836]"), Some(4026531836));
+        assert_eq!(parse_namespace_id("net:[4026531836]"), None);
+        assert_eq!(parse_namespace_id("pid:[abc]"), None);
+    }
+
+    #[test]
+    fn test_translate_namespace_pid_in_own_namespace() {
+        let own = std::process::id() as i32;
+        let namespace = pid_namespace(&own).unwrap();
+        assert_eq!(translate_namespace_pid(own, Some(namespace)), Ok(own));
+    }
+
+    #[test]
+    fn test_parse_status_rss() {
+        let content = "Name:\tbash\nVmRSS:\t    4100 kB\nRssAnon:\t    1200 kB\nRssFile:\t    2800 kB\nRssShmem:\t     100 kB\nThreads:\t1\n";
+        assert_eq!(parse_status_rss(content), Some(RssBreakdown{anon: 1200 * 1024, file: 2800 * 1024, shmem: 100 * 1024}));
+    }
+
+    #[test]
+    fn test_parse_status_rss_old_kernel() {
+        let content = "Name:\tbash\nVmRSS:\t    4100 kB\nThreads:\t1\n";
+        assert_eq!(parse_status_rss(content), None);
+    }
+
+    #[test]
+    fn test_parse_meminfo_total() {
+        let content = "MemTotal:       16315060 kB\nMemFree:         1436344 kB\nMemAvailable:    9912132 kB\n";
+        assert_eq!(parse_meminfo_total(content), Some(16315060 * 1024));
+        assert_eq!(parse_meminfo_total("MemFree:         1436344 kB\n"), None);
+        assert!(read_mem_total().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_parse_status_hugetlb() {
+        let content = "Name:\tpostgres\nVmRSS:\t  120400 kB\nHugetlbPages:\t 2097152 kB\nThreads:\t1\n";
+        assert_eq!(parse_status_hugetlb(content), Some(2097152 * 1024));
+        assert_eq!(parse_status_hugetlb("Name:\tbash\nVmRSS:\t    4100 kB\n"), None);
+    }
+
+    #[test]
+    fn test_parse_status_field() {
+        // captured from a shell, tabs after the colons and values padded with spaces
+        let content = "Name:\tbash\nUmask:\t0022\nState:\tS (sleeping)\nPid:\t2817\nPPid:\t2816\n\
+                       VmPeak:\t   10204 kB\nVmSize:\t   10204 kB\nVmLck:\t       0 kB\nVmPin:\t       0 kB\n\
+                       VmHWM:\t    5436 kB\nVmRSS:\t    5436 kB\nRssAnon:\t    1808 kB\nRssFile:\t    3628 kB\n\
+                       RssShmem:\t       0 kB\nVmData:\t    2264 kB\nVmStk:\t     132 kB\nVmExe:\t     892 kB\n\
+                       VmLib:\t    1900 kB\nVmPTE:\t      56 kB\nVmSwap:\t     348 kB\nHugetlbPages:\t       0 kB\n\
+                       CoreDumping:\t0\nThreads:\t1\n";
+        assert_eq!(parse_status_field(content, "VmSwap"), Some(348 * 1024));
+        assert_eq!(parse_status_field(content, "VmRSS"), Some(5436 * 1024));
+        assert_eq!(parse_status_field(content, "HugetlbPages"), Some(0));
+        // a name is matched whole, VmPeak is no Vm line
+        assert_eq!(parse_status_field(content, "Vm"), None);
+        assert_eq!(parse_status_field(content, "VmSwapped"), None);
+        // no swap line, e.g. a kernel thread
+        assert_eq!(parse_status_field("Name:\tkthreadd\nThreads:\t1\n", "VmSwap"), None);
+        assert_eq!(parse_status_field("VmSwap: 12 kB", "VmSwap"), Some(12 * 1024));
+    }
+
+    #[test]
+    fn test_read_swap() {
+        let mut reader = ProcReader::new();
+        assert!(read_swap(&mut reader, &(std::process::id() as i32)).is_some());
+        assert_eq!(read_swap(&mut reader, &i32::MAX), None);
+    }
+
+    #[test]
+    fn test_read_rss_breakdown_self() {
+        let rss = read_rss_breakdown(&mut ProcReader::new(), &(std::process::id() as i32)).unwrap();
+        assert!(rss.anon > 0);
+    }
+
+    #[test]
+    fn test_parse_memory_pressure() {
+        let input = "some avg10=1.53 avg60=0.20 avg300=0.05 total=123456\nfull avg10=0.75 avg60=0.10 avg300=0.02 total=6543\n";
+        let expected = MemoryPressure{some_avg10: 1.53, full_avg10: 0.75};
+        assert_eq!(parse_memory_pressure(input), Some(expected));
+    }
+
+    #[test]
+    fn test_parse_memory_pressure_invalid() {
+        assert_eq!(parse_memory_pressure("some avg10=1.53 avg60=0.20\n"), None);
+        assert_eq!(parse_memory_pressure("garbage"), None);
+    }
+
+    #[test]
+    fn test_is_kernel_thread() {
+        let mut map = HashMap::new();
+        map.insert(1, 0);
+        map.insert(2, 0);
+        map.insert(10, 2);
+        map.insert(11, 1);
+
+        assert!(is_kernel_thread(2, &map));
+        assert!(is_kernel_thread(10, &map));
+        assert!(!is_kernel_thread(1, &map));
+        assert!(!is_kernel_thread(11, &map));
+        assert!(!is_kernel_thread(12, &map));  // unknown pid
+    }
+
+    #[test]
+    fn test_parse_cmdline() {
+        assert_eq!(parse_cmdline(b"java\0-jar\0foo.jar\0"), "java -jar foo.jar");
+        assert_eq!(parse_cmdline(b""), "");  // kernel threads have an empty cmdline
+    }
+
+    #[test]
+    fn test_parse_cmdline_invalid_utf8() {
+        assert_eq!(parse_cmdline(b"app\0\xff\0"), "app \u{FFFD}");
+    }
+}
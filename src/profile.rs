@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+
+
+#[derive(Debug)]
+pub struct SelfProfile {
+    started: Instant,
+    samples: u64,
+    work: Duration,   // time spent scanning /proc and writing output, sleeping excluded
+}
+
+impl SelfProfile {
+    pub fn new() -> Self {
+        SelfProfile{started: Instant::now(), samples: 0, work: Duration::ZERO}
+    }
+
+    pub fn record(&mut self, work: Duration) {
+        self.samples += 1;
+        self.work += work;
+    }
+
+    pub fn report(&self) -> String {
+        format_report(self.samples, self.work, self.started.elapsed())
+    }
+}
+
+
+fn format_report(samples: u64, work: Duration, wall: Duration) -> String {
+    let average_us = if samples == 0 { 0 } else { work.as_micros() / samples as u128 };
+    let active_percent = if wall.is_zero() { 0.0 } else { work.as_secs_f64() / wall.as_secs_f64() * 100.0 };
+    format!(
+        "memimpact self-profile: {} samples, {}us of work per sample on average, active {:.2}% of the wall time\n",
+        samples, average_us, active_percent
+    )
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_report() {
+        let report = format_report(4, Duration::from_millis(2), Duration::from_millis(400));
+        assert_eq!(report, "memimpact self-profile: 4 samples, 500us of work per sample on average, active 0.50% of the wall time\n");
+    }
+
+    #[test]
+    fn test_format_report_without_samples() {
+        let report = format_report(0, Duration::ZERO, Duration::ZERO);
+        assert_eq!(report, "memimpact self-profile: 0 samples, 0us of work per sample on average, active 0.00% of the wall time\n");
+    }
+
+    #[test]
+    fn test_record_accumulates() {
+        let mut profile = SelfProfile::new();
+        profile.record(Duration::from_micros(300));
+        profile.record(Duration::from_micros(100));
+        assert_eq!(profile.samples, 2);
+        assert_eq!(profile.work, Duration::from_micros(400));
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::template_engine::MemorySample;
+
+
+// --pushgateway: batch jobs end before any scrape, their metrics are pushed instead.
+// A plain HTTP/1.1 POST is all the Pushgateway needs, no client crate for that.
+// see https://github.com/prometheus/pushgateway#api
+
+const PUSH_TIMEOUT: Duration = Duration::from_secs(2);   // a push stalls the sampling at most this long per step
+
+
+#[derive(Debug, PartialEq)]
+pub struct Pushgateway {
+    host: String,   // host:port, also sent as the Host header
+    path: String,   // up to and including /metrics/job/<job>
+}
+
+impl Pushgateway {
+    pub fn new(url: &str, job: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| format!("{:?} is not an http:// url, https is not supported", url))?;
+        let (host, prefix) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+        if host.is_empty() {
+            return Err(format!("{:?} has no host", url));
+        }
+        let host = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+        Ok(Pushgateway{host, path: format!("{}/metrics/job/{}", prefix.trim_end_matches('/'), job)})
+    }
+
+    pub fn push(&self, body: &str) -> io::Result<()> {
+        // POST replaces the metrics of the same names in the group, the others are kept
+        let mut stream = self.connect()?;
+        stream.set_write_timeout(Some(PUSH_TIMEOUT))?;
+        stream.set_read_timeout(Some(PUSH_TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path, self.host, body.len(), body,
+        )?;
+        // only the status line matters, e.g. "HTTP/1.1 200 OK"
+        let mut response = [0u8; 64];
+        let mut read = 0;
+        while read < response.len() {
+            match stream.read(&mut response[read..])? {
+                0 => break,
+                n => read += n,
+            }
+            if response[..read].contains(&b'\n') {
+                break;
+            }
+        }
+        let status_line = String::from_utf8_lossy(&response[..read]);
+        let status_line = status_line.lines().next().unwrap_or("");
+        match status_line.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!("the pushgateway answered {:?}", status_line))),
+        }
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        // like the tcp output: a host can resolve to an IPv6 address first and listen on IPv4
+        let mut last_error = io::Error::other(format!("{} resolves to no address", self.host));
+        for addr in self.host.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, PUSH_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+
+pub fn is_valid_job(job: &str) -> bool {
+    // the job is a segment of the url path, no escaping needed for these characters
+    !job.is_empty() && job.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+
+pub fn render_metrics(sample: &MemorySample) -> String {
+    // Prometheus text format, in bytes as the Prometheus conventions want
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, bytes: u64| {
+        let _ = write!(out, "# HELP {0} {1}\n# TYPE {0} gauge\n{0} {2}\n", name, help, bytes);
+    };
+    gauge("memimpact_max_bytes", "Peak resident memory of the process tree.", sample.max_bytes);
+    gauge("memimpact_current_bytes", "Resident memory of the process tree at the last sample.", sample.current_bytes);
+    if let Some(avg) = sample.time_weighted_avg {
+        gauge("memimpact_time_weighted_avg_bytes", "Time-weighted average resident memory of the process tree.", avg);
+    }
+    out
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_new_builds_the_job_path() {
+        let gateway = Pushgateway::new("http://gateway:9091", "nightly").unwrap();
+        assert_eq!(gateway, Pushgateway{host: "gateway:9091".to_string(), path: "/metrics/job/nightly".to_string()});
+        let gateway = Pushgateway::new("http://gateway/prefix/", "nightly").unwrap();
+        assert_eq!(gateway, Pushgateway{host: "gateway:80".to_string(), path: "/prefix/metrics/job/nightly".to_string()});
+        assert!(Pushgateway::new("https://gateway", "nightly").is_err());
+        assert!(Pushgateway::new("http:///metrics", "nightly").is_err());
+    }
+
+    #[test]
+    fn test_is_valid_job() {
+        assert!(is_valid_job("build-42.linux_x86"));
+        assert!(!is_valid_job(""));
+        assert!(!is_valid_job("a/b"));
+        assert!(!is_valid_job("a b"));
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let sample = MemorySample{current_bytes: 1024, max_bytes: 2048, time_weighted_avg: Some(3072), ..MemorySample::default()};
+        let rendered = render_metrics(&sample);
+        assert!(rendered.starts_with("# HELP memimpact_max_bytes "));
+        assert!(rendered.contains("# TYPE memimpact_max_bytes gauge\nmemimpact_max_bytes 2048\n"));
+        assert!(rendered.contains("\nmemimpact_current_bytes 1024\n"));
+        assert!(rendered.ends_with("\nmemimpact_time_weighted_avg_bytes 3072\n"));
+    }
+
+    fn serve_once(status_line: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut peer, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            // the body is the last part of the request, it ends with the last metric
+            while !request.ends_with(b"\n") || !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+                let n = peer.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..n]);
+            }
+            peer.write_all(status_line.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_push() {
+        let (url, server) = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        let gateway = Pushgateway::new(&url, "ci").unwrap();
+        gateway.push("memimpact_max_bytes 2048\n").unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /metrics/job/ci HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Length: 25\r\n"));
+        assert!(request.ends_with("\r\n\r\nmemimpact_max_bytes 2048\n"));
+    }
+
+    #[test]
+    fn test_push_tries_every_address() {
+        // localhost can resolve to ::1 before 127.0.0.1, where the server listens
+        let (url, server) = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        let gateway = Pushgateway::new(&url.replace("127.0.0.1", "localhost"), "ci").unwrap();
+        gateway.push("memimpact_max_bytes 2048\n").unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_push_rejected() {
+        let (url, server) = serve_once("HTTP/1.1 400 Bad Request\r\n\r\n");
+        let gateway = Pushgateway::new(&url, "ci").unwrap();
+        let err = gateway.push("memimpact_max_bytes 2048\n").unwrap_err();
+        server.join().unwrap();
+        assert_eq!(err.to_string(), "the pushgateway answered \"HTTP/1.1 400 Bad Request\"");
+    }
+}
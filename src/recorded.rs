@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+
+use crate::template_engine::format_memory;
+
+
+// Reader for runs previously written by memimpact, either as JSON lines
+// (one flat object per sample) or as CSV with a header row.
+// Only current_bytes is required, timestamp, pid, process_name, max_bytes, process_count
+// and final are optional.
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RecordedSample {
+    pub timestamp: Option<u64>,
+    pub pid: Option<i32>,
+    pub process_name: Option<String>,
+    pub current_bytes: u64,
+    pub max_bytes: Option<u64>,
+    pub process_count: Option<u64>,
+}
+
+
+fn parse_json_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    // the opening quote is already consumed
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'r' => out.push('\r'),
+                'b' => out.push('\u{8}'),
+                'f' => out.push('\u{c}'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&code, 16).ok()?;
+                    out.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                }
+                other => out.push(other),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+
+pub fn parse_flat_json_object(line: &str) -> Option<HashMap<String, String>> {
+    // objects nested in a sample are not supported, and not produced by memimpact
+    let mut fields = HashMap::new();
+    let mut chars = line.trim().chars().peekable();
+    if chars.next()? != '{' {
+        return None;
+    }
+    loop {
+        while chars.peek()?.is_whitespace() {
+            chars.next();
+        }
+        match chars.next()? {
+            '}' => return Some(fields),
+            ',' => continue,
+            '"' => {}
+            _ => return None,
+        }
+        let key = parse_json_string(&mut chars)?;
+        while chars.peek()?.is_whitespace() {
+            chars.next();
+        }
+        if chars.next()? != ':' {
+            return None;
+        }
+        while chars.peek()?.is_whitespace() {
+            chars.next();
+        }
+        let value = if chars.peek() == Some(&'"') {
+            chars.next();
+            parse_json_string(&mut chars)?
+        } else {
+            let mut raw = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c == '}' || c.is_whitespace() {
+                    break;
+                }
+                raw.push(c);
+                chars.next();
+            }
+            raw
+        };
+        fields.insert(key, value);
+    }
+}
+
+
+pub fn split_csv_line(line: &str) -> Vec<String> {
+    // RFC 4180: fields may be quoted, a quote inside a quoted field is doubled
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+
+fn sample_from_fields(fields: &HashMap<String, String>, line_number: usize) -> Result<Option<RecordedSample>, String> {
+    if fields.get("final").map(String::as_str) == Some("true") {
+        // the summary repeats the last sample, counting it would bias the average
+        return Ok(None);
+    }
+    fn number<T: std::str::FromStr>(fields: &HashMap<String, String>, key: &str, line_number: usize) -> Result<Option<T>, String> {
+        match fields.get(key) {
+            None => Ok(None),
+            Some(value) => value.trim().parse().map(Some)
+                .map_err(|_| format!("line {}: invalid {} {:?}", line_number, key, value)),
+        }
+    }
+    let number_u64 = |key: &str| number::<u64>(fields, key, line_number);
+    let current_bytes = number_u64("current_bytes")?
+        .ok_or(format!("line {}: missing current_bytes", line_number))?;
+    Ok(Some(RecordedSample{
+        timestamp: number_u64("timestamp")?,
+        pid: number(fields, "pid", line_number)?,
+        process_name: fields.get("process_name").cloned(),
+        current_bytes,
+        max_bytes: number_u64("max_bytes")?,
+        process_count: number_u64("process_count")?,
+    }))
+}
+
+
+pub fn parse_recorded_run(content: &str) -> Result<Vec<RecordedSample>, String> {
+    let mut lines = content.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+    let mut samples = Vec::new();
+    let Some((first_index, first_line)) = lines.next() else {
+        return Ok(samples);
+    };
+    if first_line.trim_start().starts_with('{') {
+        for (index, line) in std::iter::once((first_index, first_line)).chain(lines) {
+            let fields = parse_flat_json_object(line)
+                .ok_or(format!("line {}: invalid JSON object", index + 1))?;
+            samples.extend(sample_from_fields(&fields, index + 1)?);
+        }
+    } else {
+        let header = split_csv_line(first_line);
+        for (index, line) in lines {
+            let fields: HashMap<String, String> = header.iter().cloned().zip(split_csv_line(line)).collect();
+            samples.extend(sample_from_fields(&fields, index + 1)?);
+        }
+    }
+    Ok(samples)
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct RunSummary {
+    pub samples: u64,
+    pub max: u64,
+    pub average: u64,
+    pub peak_process_count: Option<u64>,
+}
+
+pub fn summarize(samples: &[RecordedSample]) -> Option<RunSummary> {
+    if samples.is_empty() {
+        return None;
+    }
+    let sum: u128 = samples.iter().map(|s| s.current_bytes as u128).sum();
+    Some(RunSummary{
+        samples: samples.len() as u64,
+        max: samples.iter().map(|s| s.current_bytes.max(s.max_bytes.unwrap_or(0))).max().unwrap_or(0),
+        average: (sum / samples.len() as u128) as u64,
+        peak_process_count: samples.iter().filter_map(|s| s.process_count).max(),
+    })
+}
+
+
+fn percent_change(baseline: u64, candidate: u64) -> String {
+    if baseline == 0 {
+        return "n/a".to_string();
+    }
+    let change = (candidate as f64 - baseline as f64) / baseline as f64 * 100.0;
+    format!("{:+.1}%", change)
+}
+
+
+pub fn format_diff(baseline: &RunSummary, candidate: &RunSummary, precision: usize) -> String {
+    let mut out = format!(
+        "max RSS: {} → {} ({})\navg RSS: {} → {} ({})\n",
+        format_memory(baseline.max, precision),
+        format_memory(candidate.max, precision),
+        percent_change(baseline.max, candidate.max),
+        format_memory(baseline.average, precision),
+        format_memory(candidate.average, precision),
+        percent_change(baseline.average, candidate.average),
+    );
+    if let (Some(before), Some(after)) = (baseline.peak_process_count, candidate.peak_process_count) {
+        out.push_str(&format!("peak process count: {} → {} ({})\n", before, after, percent_change(before, after)));
+    }
+    out.push_str(&format!("samples: {} → {}\n", baseline.samples, candidate.samples));
+    out
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flat_json_object() {
+        let fields = parse_flat_json_object(r#"{"pid": 42, "process_name": "(my \"weird\" proc)", "final": false}"#).unwrap();
+        assert_eq!(fields["pid"], "42");
+        assert_eq!(fields["process_name"], "(my \"weird\" proc)");
+        assert_eq!(fields["final"], "false");
+    }
+
+    #[test]
+    fn test_parse_flat_json_object_invalid() {
+        assert!(parse_flat_json_object("pid=42").is_none());
+        assert!(parse_flat_json_object(r#"{"pid": 42"#).is_none());
+    }
+
+    #[test]
+    fn test_split_csv_line_quoted() {
+        assert_eq!(split_csv_line(r#"1,"a, ""b""",3"#), vec!["1", r#"a, "b""#, "3"]);
+        assert_eq!(split_csv_line("1,,3"), vec!["1", "", "3"]);
+    }
+
+    #[test]
+    fn test_parse_recorded_json_lines() {
+        let content = "{\"timestamp\":10,\"current_bytes\":100,\"max_bytes\":100,\"process_count\":2}\n\
+                       {\"timestamp\":11,\"current_bytes\":300,\"max_bytes\":300,\"process_count\":3}\n\
+                       {\"timestamp\":12,\"current_bytes\":300,\"max_bytes\":300,\"final\":true}\n";
+        let samples = parse_recorded_run(content).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1], RecordedSample{timestamp: Some(11), current_bytes: 300, max_bytes: Some(300), process_count: Some(3), ..Default::default()});
+    }
+
+    #[test]
+    fn test_parse_recorded_csv() {
+        let content = "timestamp,pid,process_name,current_bytes,max_bytes\n\
+                       10,42,\"(bash)\",100,100\n\
+                       11,42,\"(bash)\",50,100\n";
+        let samples = parse_recorded_run(content).unwrap();
+        assert_eq!(samples.len(), 2);
+        let expected = RecordedSample{
+            timestamp: Some(11),
+            pid: Some(42),
+            process_name: Some("(bash)".to_string()),
+            current_bytes: 50,
+            max_bytes: Some(100),
+            process_count: None,
+        };
+        assert_eq!(samples[1], expected);
+    }
+
+    #[test]
+    fn test_parse_recorded_missing_column() {
+        let err = parse_recorded_run("timestamp,pid\n10,42\n").unwrap_err();
+        assert!(err.contains("missing current_bytes"));
+    }
+
+    #[test]
+    fn test_summarize() {
+        let samples = vec![
+            RecordedSample{current_bytes: 100, process_count: Some(1), ..Default::default()},
+            RecordedSample{current_bytes: 300, process_count: Some(4), ..Default::default()},
+        ];
+        let expected = RunSummary{samples: 2, max: 300, average: 200, peak_process_count: Some(4)};
+        assert_eq!(summarize(&samples), Some(expected));
+        assert_eq!(summarize(&[]), None);
+    }
+
+    #[test]
+    fn test_format_diff() {
+        let baseline = RunSummary{samples: 10, max: 2 << 20, average: 1 << 20, peak_process_count: Some(4)};
+        let candidate = RunSummary{samples: 12, max: 1 << 20, average: 1 << 20, peak_process_count: Some(5)};
+        assert_eq!(
+            format_diff(&baseline, &candidate, 0),
+            "max RSS: 2MiB → 1MiB (-50.0%)\navg RSS: 1MiB → 1MiB (+0.0%)\npeak process count: 4 → 5 (+25.0%)\nsamples: 10 → 12\n"
+        );
+    }
+}
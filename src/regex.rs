@@ -0,0 +1,463 @@
+// A small regular expression matcher, compiled to a Thompson NFA, to avoid pulling a crate
+// for matching process names and command lines.
+// Supported syntax:
+//   literals, `.`, `^`, `$`, groups `(...)`, alternation `|`,
+//   quantifiers `*`, `+`, `?`, `{n}`, `{n,}`, `{n,m}` (greedy),
+//   classes `[abc]`, `[a-z]`, `[^...]` and the escapes `\d \w \s \D \W \S`.
+// Matching is unanchored, like `grep -E`: use `^` and `$` to anchor.
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),  // the boolean is true for the negated form (\D)
+    Word(bool),
+    Space(bool),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Range(low, high) => *low <= c && c <= *high,
+            ClassItem::Digit(negated) => c.is_ascii_digit() != *negated,
+            ClassItem::Word(negated) => (c.is_alphanumeric() || c == '_') != *negated,
+            ClassItem::Space(negated) => c.is_whitespace() != *negated,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Char(char),
+    Any,
+    Class{items: Vec<ClassItem>, negated: bool},
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat{node: Box<Node>, min: u32, max: Option<u32>},
+}
+
+
+#[derive(Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+}
+
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn parse_alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            return Ok(branches.remove(0));
+        }
+        Ok(Node::Alternation(branches))
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        match self.chars.next() {
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('(') => {
+                let inner = self.parse_alternation()?;
+                match self.chars.next() {
+                    Some(')') => Ok(inner),
+                    _ => Err("Unclosed group".into()),
+                }
+            }
+            Some('[') => self.parse_class(),
+            Some('\\') => match self.parse_escape()? {
+                ClassItem::Range(c, _) => Ok(Node::Char(c)),
+                item => Ok(Node::Class{items: vec![item], negated: false}),
+            },
+            Some(c @ ('*' | '+' | '?' | '{')) => Err(format!("Nothing to repeat before '{}'", c)),
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("Unexpected end of pattern".into()),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<ClassItem, String> {
+        match self.chars.next() {
+            Some('d') => Ok(ClassItem::Digit(false)),
+            Some('D') => Ok(ClassItem::Digit(true)),
+            Some('w') => Ok(ClassItem::Word(false)),
+            Some('W') => Ok(ClassItem::Word(true)),
+            Some('s') => Ok(ClassItem::Space(false)),
+            Some('S') => Ok(ClassItem::Space(true)),
+            Some('n') => Ok(ClassItem::Range('\n', '\n')),
+            Some('t') => Ok(ClassItem::Range('\t', '\t')),
+            Some(c) if c.is_ascii_alphanumeric() => Err(format!("Unknown escape: \\{}", c)),
+            Some(c) => Ok(ClassItem::Range(c, c)),
+            None => Err("Trailing backslash".into()),
+        }
+    }
+
+    fn parse_class(&mut self) -> Result<Node, String> {
+        let mut items = Vec::new();
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.chars.next();
+        }
+        let mut first = true;
+        loop {
+            let item = match self.chars.next() {
+                None => return Err("Unclosed character class".into()),
+                Some(']') if !first => break,
+                Some('\\') => self.parse_escape()?,
+                Some(c) => ClassItem::Range(c, c),
+            };
+            first = false;
+            // a '-' between two single characters makes a range, elsewhere it is a literal
+            if let ClassItem::Range(low, _) = item
+                && self.chars.peek() == Some(&'-') {
+                    self.chars.next();
+                    match self.chars.peek() {
+                        Some(']') | None => {
+                            items.push(item);
+                            items.push(ClassItem::Range('-', '-'));
+                        }
+                        Some(_) => {
+                            let high = match self.chars.next() {
+                                Some('\\') => match self.parse_escape()? {
+                                    ClassItem::Range(c, _) => c,
+                                    _ => return Err("Invalid range in character class".into()),
+                                },
+                                Some(c) => c,
+                                None => return Err("Unclosed character class".into()),
+                            };
+                            if high < low {
+                                return Err(format!("Invalid range {}-{}", low, high));
+                            }
+                            items.push(ClassItem::Range(low, high));
+                        }
+                    }
+                    continue;
+                }
+            items.push(item);
+        }
+        Ok(Node::Class{items, negated})
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let mut digits = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            digits.push(c);
+            self.chars.next();
+        }
+        digits.parse().ok()
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.chars.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.chars.next();
+                let min = self.parse_number().ok_or("Invalid repetition")?;
+                let max = match self.chars.next() {
+                    Some('}') => return Ok(Node::Repeat{node: Box::new(atom), min, max: Some(min)}),
+                    Some(',') => self.parse_number(),
+                    _ => return Err("Invalid repetition".into()),
+                };
+                if self.chars.next() != Some('}') {
+                    return Err("Invalid repetition".into());
+                }
+                if let Some(max) = max && max < min {
+                    return Err("Invalid repetition".into());
+                }
+                return Ok(Node::Repeat{node: Box::new(atom), min, max});
+            }
+            _ => return Ok(atom),
+        };
+        self.chars.next();
+        Ok(Node::Repeat{node: Box::new(atom), min, max})
+    }
+}
+
+
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class{items: Vec<ClassItem>, negated: bool},
+    Start,
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+
+// counted repetitions are copied into the program, a bound keeps `(a{1000}){1000}` out of memory
+const MAX_PROGRAM_LEN: usize = 100_000;
+
+
+fn emit(program: &mut Vec<Inst>, inst: Inst) -> Result<usize, String> {
+    if program.len() >= MAX_PROGRAM_LEN {
+        return Err("Pattern too large".into());
+    }
+    program.push(inst);
+    Ok(program.len() - 1)
+}
+
+
+fn compile(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    match node {
+        Node::Char(c) => { emit(program, Inst::Char(*c))?; }
+        Node::Any => { emit(program, Inst::Any)?; }
+        Node::Class{items, negated} => { emit(program, Inst::Class{items: items.clone(), negated: *negated})?; }
+        Node::Start => { emit(program, Inst::Start)?; }
+        Node::End => { emit(program, Inst::End)?; }
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program)?;
+            }
+        }
+        Node::Alternation(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    compile(branch, program)?;
+                    break;
+                }
+                let split = emit(program, Inst::Split(0, 0))?;
+                compile(branch, program)?;
+                jumps.push(emit(program, Inst::Jump(0))?);
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            for jump in jumps {
+                program[jump] = Inst::Jump(program.len());
+            }
+        }
+        Node::Repeat{node, min, max} => {
+            for _ in 0..*min {
+                compile(node, program)?;
+            }
+            match max {
+                Some(max) => for _ in *min..*max {
+                    let split = emit(program, Inst::Split(0, 0))?;
+                    compile(node, program)?;
+                    program[split] = Inst::Split(split + 1, program.len());
+                },
+                None => {
+                    let split = emit(program, Inst::Split(0, 0))?;
+                    compile(node, program)?;
+                    emit(program, Inst::Jump(split))?;
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+
+fn add_thread(program: &[Inst], threads: &mut Vec<usize>, seen: &mut [usize], step: usize, pc: usize, pos: usize, len: usize) {
+    // follows jumps, splits and anchors with an explicit stack: nothing here recurses
+    // on the input, and `seen` stops the empty loops of patterns like (a*)*
+    let mut stack = vec![pc];
+    while let Some(pc) = stack.pop() {
+        if seen[pc] == step {
+            continue;
+        }
+        seen[pc] = step;
+        match program[pc] {
+            Inst::Jump(target) => stack.push(target),
+            Inst::Split(first, second) => {
+                stack.push(second);
+                stack.push(first);
+            }
+            Inst::Start => if pos == 0 { stack.push(pc + 1) },
+            Inst::End => if pos == len { stack.push(pc + 1) },
+            _ => threads.push(pc),
+        }
+    }
+}
+
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let mut parser = Parser{chars: pattern.chars().peekable()};
+        let root = parser.parse_alternation()?;
+        if parser.chars.next().is_some() {
+            return Err("Unmatched ')'".into());
+        }
+        let mut program = Vec::new();
+        compile(&root, &mut program)?;
+        emit(&mut program, Inst::Match)?;
+        Ok(Regex{program})
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        // a Thompson simulation of the pattern: all the threads advance together one
+        // character at a time, linear in the text, where backtracking was quadratic
+        let input: Vec<char> = text.chars().collect();
+        let len = input.len();
+        // seen[pc] holds the last step that reached pc, step 0 is never used
+        let mut seen = vec![0; self.program.len()];
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        for pos in 0..=len {
+            // unanchored: a new attempt starts at every position
+            add_thread(&self.program, &mut current, &mut seen, pos + 1, 0, pos, len);
+            if current.iter().any(|&pc| matches!(self.program[pc], Inst::Match)) {
+                return true;
+            }
+            let Some(&c) = input.get(pos) else {
+                break;
+            };
+            for &pc in &current {
+                let accepted = match &self.program[pc] {
+                    Inst::Char(expected) => *expected == c,
+                    Inst::Any => true,
+                    Inst::Class{items, negated} => items.iter().any(|item| item.matches(c)) != *negated,
+                    _ => false,
+                };
+                if accepted {
+                    add_thread(&self.program, &mut next, &mut seen, pos + 2, pc + 1, pos + 1, len);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        false
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn literal_is_unanchored() {
+        assert!(is_match("foo", "java -jar foo.jar"));
+        assert!(!is_match("bar", "java -jar foo.jar"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(is_match("^java", "java -jar foo.jar"));
+        assert!(!is_match("^jar", "java -jar foo.jar"));
+        assert!(is_match("jar$", "java -jar foo.jar"));
+        assert!(is_match("^$", ""));
+    }
+
+    #[test]
+    fn dot_and_escaped_dot() {
+        assert!(is_match("foo.jar", "fooXjar"));
+        assert!(!is_match("foo\\.jar", "fooXjar"));
+        assert!(is_match("foo\\.jar", "foo.jar"));
+    }
+
+    #[test]
+    fn quantifiers() {
+        assert!(is_match("^ab*c$", "ac"));
+        assert!(is_match("^ab*c$", "abbbc"));
+        assert!(!is_match("^ab+c$", "ac"));
+        assert!(is_match("^ab?c$", "abc"));
+        assert!(!is_match("^ab?c$", "abbc"));
+        assert!(is_match("^a{2}$", "aa"));
+        assert!(!is_match("^a{2}$", "aaa"));
+        assert!(is_match("^a{2,}$", "aaaa"));
+        assert!(is_match("^a{1,2}b$", "aab"));
+        assert!(!is_match("^a{1,2}b$", "aaab"));
+    }
+
+    #[test]
+    fn greedy_star_backtracks() {
+        assert!(is_match("^.*\\.jar$", "java -jar worker.jar"));
+        assert!(is_match("^(a|ab)c$", "abc"));
+    }
+
+    #[test]
+    fn groups_and_alternation() {
+        assert!(is_match("^python3(\\.\\d+)?$", "python3"));
+        assert!(is_match("^python3(\\.\\d+)?$", "python3.12"));
+        assert!(!is_match("^python3(\\.\\d+)?$", "python3.x"));
+        assert!(is_match("^(nginx|httpd)$", "httpd"));
+        assert!(!is_match("^(nginx|httpd)$", "apache"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(is_match("^[a-c]+$", "abcabc"));
+        assert!(!is_match("^[a-c]+$", "abd"));
+        assert!(is_match("^[^0-9]+$", "worker"));
+        assert!(!is_match("^[^0-9]+$", "worker1"));
+        assert!(is_match("^[-a]+$", "a-a"));
+        assert!(is_match("^[a-]+$", "a-a"));
+        assert!(is_match("^[\\d_]+$", "12_3"));
+        assert!(is_match("^[]]$", "]"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(is_match("^\\w+\\s\\d+$", "worker_1 42"));
+        assert!(!is_match("^\\S+$", "a b"));
+    }
+
+    #[test]
+    fn empty_repetition_terminates() {
+        assert!(is_match("^(a*)*b$", "aaab"));
+        assert!(!is_match("^(a*)*b$", "aaac"));
+    }
+
+    #[test]
+    fn long_text_is_linear() {
+        // a Java classpath can make a command line of several hundred kilobytes
+        let text = format!("java -cp {} Main", "a".repeat(200_000));
+        assert!(is_match(".*Main$", &text));
+        assert!(!is_match(".*Worker$", &text));
+        assert!(!is_match("(a|b)*z", &text));
+    }
+
+    #[test]
+    fn too_large_pattern() {
+        assert!(Regex::new("(a{1000}){1000}").is_err());
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(Regex::new("(abc").is_err());
+        assert!(Regex::new("abc)").is_err());
+        assert!(Regex::new("[abc").is_err());
+        assert!(Regex::new("*abc").is_err());
+        assert!(Regex::new("a{3,1}").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new("abc\\").is_err());
+        assert!(Regex::new("\\q").is_err());
+    }
+}
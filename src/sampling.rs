@@ -0,0 +1,275 @@
+use std::time::{Duration, Instant};
+
+
+// Back-off policy of --adaptive:
+// while two consecutive samples differ by at most ADAPTIVE_CHANGE_PERCENT,
+// the interval doubles, up to ADAPTIVE_MAX_FACTOR times the requested one.
+// Any bigger change snaps the interval back to the requested one.
+pub const ADAPTIVE_MAX_FACTOR: u64 = 8;
+pub const ADAPTIVE_CHANGE_PERCENT: u64 = 1;
+
+
+pub fn is_stable(previous: u64, current: u64) -> bool {
+    let change = previous.abs_diff(current) as u128;
+    change * 100 <= previous as u128 * ADAPTIVE_CHANGE_PERCENT as u128
+}
+
+
+pub fn next_adaptive_interval(base_ms: u64, current_ms: u64, previous: u64, current: u64) -> u64 {
+    if is_stable(previous, current) {
+        current_ms.saturating_mul(2).min(base_ms.saturating_mul(ADAPTIVE_MAX_FACTOR))
+    } else {
+        base_ms
+    }
+}
+
+
+// --report-every: samples are still taken at the --hertz rate to track the max,
+// but a line is only printed once per report interval, the first sample included.
+pub struct ReportClock {
+    interval: Duration,
+    last_report: Option<Instant>,
+}
+
+impl ReportClock {
+    pub fn new(interval: Duration) -> Self {
+        ReportClock{interval, last_report: None}
+    }
+
+    pub fn due(&mut self, now: Instant) -> bool {
+        match self.last_report {
+            Some(last) if now.duration_since(last) < self.interval => false,
+            _ => {
+                self.last_report = Some(now);
+                true
+            }
+        }
+    }
+}
+
+
+// --on-change: a sample is only written when it moved away from the last written one
+// by more than the threshold, so a stable process produces almost no output.
+#[derive(Debug)]
+pub struct ChangeFilter {
+    threshold: u64,
+    last_written: Option<u64>,
+}
+
+impl ChangeFilter {
+    pub fn new(threshold: u64) -> Self {
+        ChangeFilter{threshold, last_written: None}
+    }
+
+    pub fn changed(&mut self, value: u64) -> bool {
+        // compared to the last written value, not the last sample, so a slow drift is still written
+        if self.last_written.is_some_and(|last| last.abs_diff(value) <= self.threshold) {
+            return false;
+        }
+        self.last_written = Some(value);
+        true
+    }
+}
+
+
+// --coalesce: of all the samples taken during a window, only the one with the
+// most memory is written, the peak is what matters when thinning the output.
+pub struct Coalescer<T> {
+    window: Duration,
+    started: Option<Instant>,
+    best: Option<(u64, T)>,
+}
+
+impl<T> Coalescer<T> {
+    pub fn new(window: Duration) -> Self {
+        Coalescer{window, started: None, best: None}
+    }
+
+    pub fn offer(&mut self, item: T, memory: u64, now: Instant) -> Option<T> {
+        // returns the best item of the previous window once it is over
+        let mut closed = None;
+        match self.started {
+            Some(started) if now.duration_since(started) < self.window => (),
+            _ => {
+                closed = self.flush();
+                self.started = Some(now);
+            }
+        }
+        if self.best.as_ref().is_none_or(|(best, _)| memory > *best) {
+            self.best = Some((memory, item));
+        }
+        closed
+    }
+
+    pub fn flush(&mut self) -> Option<T> {
+        self.best.take().map(|(_, item)| item)
+    }
+}
+
+
+// --align-to-clock: sleep until the next multiple of the interval since the epoch,
+// so that at 1Hz every sample is taken on a round second, like node_exporter scrapes.
+pub fn aligned_sleep(interval_ms: u64, since_epoch: Duration) -> Duration {
+    let interval_ms = interval_ms.max(1);
+    let into_interval = (since_epoch.as_millis() % interval_ms as u128) as u64;
+    Duration::from_millis(interval_ms - into_interval)
+}
+
+
+// On an overloaded host the sleep between two samples overruns and fewer samples are
+// taken than requested, missing short peaks. After LAG_LATE_SAMPLES consecutive
+// intervals longer than LAG_FACTOR times the requested one, the lag is reported once.
+const LAG_FACTOR: f64 = 1.5;
+const LAG_LATE_SAMPLES: u32 = 5;
+
+#[derive(Debug, Default)]
+pub struct LagDetector {
+    last: Option<Instant>,
+    late: u32,            // consecutive late intervals
+    late_total: Duration, // their total length
+    warned: bool,
+    intervals: u32,
+    total: Duration,
+}
+
+impl LagDetector {
+    pub fn record(&mut self, now: Instant, requested: Duration) -> Option<Duration> {
+        // the average interval of the late streak, the first time it is long enough
+        let last = self.last.replace(now)?;
+        let actual = now.duration_since(last);
+        self.intervals += 1;
+        self.total += actual;
+        if actual.as_secs_f64() <= requested.as_secs_f64() * LAG_FACTOR {
+            self.late = 0;
+            self.late_total = Duration::ZERO;
+            return None;
+        }
+        self.late += 1;
+        self.late_total += actual;
+        if self.late < LAG_LATE_SAMPLES || self.warned {
+            return None;
+        }
+        self.warned = true;
+        Some(self.late_total / self.late)
+    }
+
+    pub fn effective_interval(&self) -> Option<Duration> {
+        // over the whole run, only worth showing once the lag was reported
+        (self.warned && self.intervals > 0).then(|| self.total / self.intervals)
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_stable() {
+        assert!(is_stable(1000, 1000));
+        assert!(is_stable(1000, 1010));
+        assert!(is_stable(1000, 990));
+        assert!(!is_stable(1000, 1011));
+        assert!(!is_stable(0, 1));
+        assert!(is_stable(0, 0));
+    }
+
+    #[test]
+    fn test_adaptive_interval_backs_off_up_to_cap() {
+        let mut interval = 100;
+        for expected in [200, 400, 800, 800] {
+            interval = next_adaptive_interval(100, interval, 1000, 1000);
+            assert_eq!(interval, expected);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_interval_snaps_back() {
+        assert_eq!(next_adaptive_interval(100, 800, 1000, 2000), 100);
+    }
+
+    #[test]
+    fn test_report_clock() {
+        let start = Instant::now();
+        let mut clock = ReportClock::new(Duration::from_millis(1000));
+        assert!(clock.due(start));
+        assert!(!clock.due(start + Duration::from_millis(50)));
+        assert!(!clock.due(start + Duration::from_millis(999)));
+        assert!(clock.due(start + Duration::from_millis(1000)));
+        assert!(!clock.due(start + Duration::from_millis(1500)));
+        assert!(clock.due(start + Duration::from_millis(2100)));
+    }
+
+    #[test]
+    fn test_coalescer_keeps_the_peak_of_each_window() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut coalescer = Coalescer::new(Duration::from_millis(100));
+        assert_eq!(coalescer.offer("a", 10, at(0)), None);
+        assert_eq!(coalescer.offer("b", 30, at(40)), None);
+        assert_eq!(coalescer.offer("c", 20, at(80)), None);
+        assert_eq!(coalescer.offer("d", 5, at(100)), Some("b"));
+        assert_eq!(coalescer.offer("e", 5, at(150)), None);
+        assert_eq!(coalescer.flush(), Some("d"));
+        assert_eq!(coalescer.flush(), None);
+    }
+
+    #[test]
+    fn test_aligned_sleep() {
+        let since_epoch = Duration::from_millis(1_700_000_000_250);
+        assert_eq!(aligned_sleep(1000, since_epoch), Duration::from_millis(750));
+        assert_eq!(aligned_sleep(100, since_epoch), Duration::from_millis(50));
+        // right on a boundary, the next one is a whole interval away
+        assert_eq!(aligned_sleep(1000, Duration::from_secs(1_700_000_000)), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_lag_detector_warns_once() {
+        let mut lag = LagDetector::default();
+        let requested = Duration::from_millis(100);
+        let start = Instant::now();
+        assert_eq!(lag.record(start, requested), None);
+        // a single late sample is not a trend
+        let mut now = start + Duration::from_millis(400);
+        assert_eq!(lag.record(now, requested), None);
+        now += Duration::from_millis(100);
+        assert_eq!(lag.record(now, requested), None);
+        let mut warnings = Vec::new();
+        for _ in 0..10 {
+            now += Duration::from_millis(340);
+            warnings.extend(lag.record(now, requested));
+        }
+        assert_eq!(warnings, vec![Duration::from_millis(340)]);
+        assert_eq!(lag.effective_interval(), Some(Duration::from_millis(3900 / 12)));
+    }
+
+    #[test]
+    fn test_lag_detector_on_time() {
+        let mut lag = LagDetector::default();
+        let requested = Duration::from_millis(100);
+        let mut now = Instant::now();
+        for _ in 0..20 {
+            assert_eq!(lag.record(now, requested), None);
+            now += Duration::from_millis(120);
+        }
+        assert_eq!(lag.effective_interval(), None);
+    }
+
+    #[test]
+    fn test_change_filter() {
+        let mut filter = ChangeFilter::new(0);
+        assert!(filter.changed(100));
+        assert!(!filter.changed(100));
+        assert!(filter.changed(101));
+        let mut filter = ChangeFilter::new(10);
+        assert!(filter.changed(100));
+        assert!(!filter.changed(105));
+        assert!(!filter.changed(110));
+        // the drift adds up against the last written value
+        assert!(filter.changed(111));
+        assert!(!filter.changed(101));
+        assert!(filter.changed(100));
+    }
+}
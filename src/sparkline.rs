@@ -0,0 +1,102 @@
+// A line of block characters showing the shape of the memory curve, printed after
+// the final line on a terminal. The samples are kept in at most MAX_POINTS points:
+// once full, neighbours are merged two by two, keeping the max so that no peak is
+// lost, and a long run costs a bounded amount of memory.
+
+const MAX_POINTS: usize = 4096;
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+
+#[derive(Debug)]
+pub struct Sparkline {
+    points: Vec<u64>,
+    per_point: usize,              // samples merged in each point
+    pending: Option<(u64, usize)>, // max and count of the point being filled
+}
+
+impl Sparkline {
+    pub fn new() -> Self {
+        Sparkline{points: Vec::new(), per_point: 1, pending: None}
+    }
+
+    pub fn record(&mut self, value: u64) {
+        let (max, count) = match self.pending {
+            Some((max, count)) => (max.max(value), count + 1),
+            None => (value, 1),
+        };
+        if count < self.per_point {
+            self.pending = Some((max, count));
+            return;
+        }
+        self.pending = None;
+        self.points.push(max);
+        if self.points.len() == MAX_POINTS {
+            self.points = merge_max(&self.points, 2);
+            self.per_point *= 2;
+        }
+    }
+
+    pub fn render(&self, width: usize) -> String {
+        let mut values = self.points.clone();
+        values.extend(self.pending.map(|(max, _)| max));
+        if values.is_empty() || width == 0 {
+            return String::new();
+        }
+        let values = merge_max(&values, values.len().div_ceil(width));
+        let low = values.iter().min().copied().unwrap_or(0);
+        let high = values.iter().max().copied().unwrap_or(0);
+        values.iter().map(|value| {
+            // a flat curve is drawn at the bottom
+            let index = match high - low {
+                0 => 0,
+                range => ((value - low) as u128 * (BLOCKS.len() - 1) as u128 / range as u128) as usize,
+            };
+            BLOCKS[index]
+        }).collect()
+    }
+}
+
+
+fn merge_max(values: &[u64], by: usize) -> Vec<u64> {
+    values.chunks(by.max(1)).map(|chunk| chunk.iter().copied().max().unwrap_or(0)).collect()
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_scales_to_the_range() {
+        let mut sparkline = Sparkline::new();
+        for value in [100, 200, 300, 400, 500, 600, 700, 800] {
+            sparkline.record(value);
+        }
+        assert_eq!(sparkline.render(80), "▁▂▃▄▅▆▇█");
+        // downsampled to the width, each character keeps the max of its samples
+        assert_eq!(sparkline.render(4), "▁▃▅█");
+    }
+
+    #[test]
+    fn test_render_flat_and_empty() {
+        let mut sparkline = Sparkline::new();
+        assert_eq!(sparkline.render(80), "");
+        sparkline.record(42);
+        sparkline.record(42);
+        assert_eq!(sparkline.render(80), "▁▁");
+    }
+
+    #[test]
+    fn test_memory_is_bounded() {
+        let mut sparkline = Sparkline::new();
+        for value in 0..(3 * MAX_POINTS as u64) {
+            sparkline.record(if value == 5000 { 1_000_000 } else { value % 10 });
+        }
+        assert!(sparkline.points.len() < MAX_POINTS);
+        assert_eq!(sparkline.per_point, 4);
+        // the peak survived the merges
+        assert!(sparkline.render(10).contains('█'));
+    }
+}
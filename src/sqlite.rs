@@ -0,0 +1,148 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use crate::template_engine::MemorySample;
+
+
+// SQLite output without linking the SQLite library: the statements are piped
+// to the sqlite3 command line shell, which must be installed.
+// Inserts are grouped in transactions of BATCH_SIZE samples, a transaction per
+// insert would fsync the database file on every sample.
+
+const BATCH_SIZE: usize = 100;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS samples (
+    timestamp INTEGER NOT NULL,
+    pid INTEGER NOT NULL,
+    process_name TEXT NOT NULL,
+    current_bytes INTEGER NOT NULL,
+    max_bytes INTEGER NOT NULL,
+    private_bytes INTEGER NOT NULL,
+    process_count INTEGER NOT NULL,
+    mem_pressure_some REAL,
+    mem_pressure_full REAL,
+    final INTEGER NOT NULL
+);
+";
+
+
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+
+fn sql_optional_real(value: Option<f64>) -> String {
+    match value {
+        Some(v) => v.to_string(),
+        None => "NULL".to_string(),
+    }
+}
+
+
+pub fn insert_statement(sample: &MemorySample) -> String {
+    format!(
+        "INSERT INTO samples VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {});\n",
+        sample.timestamp,
+        sample.pid,
+        sql_string(sample.process_name),
+        sample.current_bytes,
+        sample.max_bytes,
+        sample.private_bytes,
+        sample.process_count,
+        sql_optional_real(sample.mem_pressure_some),
+        sql_optional_real(sample.mem_pressure_full),
+        sample.is_final as u8,
+    )
+}
+
+
+pub struct SqliteSink {
+    child: Child,
+    stdin: Option<ChildStdin>,   // None once finished
+    pending: usize,   // inserts in the current transaction
+}
+
+impl SqliteSink {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut child = Command::new("sqlite3")
+            .arg("-batch")
+            .arg("-bail")
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        let mut stdin = child.stdin.take().ok_or(io::Error::other("sqlite3 stdin is not piped"))?;
+        stdin.write_all(CREATE_TABLE.as_bytes())?;
+        stdin.write_all(b"BEGIN;\n")?;
+        Ok(SqliteSink{child, stdin: Some(stdin), pending: 0})
+    }
+
+    pub fn insert(&mut self, sample: &MemorySample) -> io::Result<()> {
+        let Some(stdin) = self.stdin.as_mut() else {
+            return Err(io::Error::other("insert after the end of the sqlite output"));
+        };
+        stdin.write_all(insert_statement(sample).as_bytes())?;
+        self.pending += 1;
+        if self.pending >= BATCH_SIZE {
+            stdin.write_all(b"COMMIT;\nBEGIN;\n")?;
+            stdin.flush()?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        // without the COMMIT, sqlite3 reaches EOF inside the transaction and rolls it back
+        let Some(mut stdin) = self.stdin.take() else {
+            return Ok(());
+        };
+        stdin.write_all(b"COMMIT;\n")?;
+        drop(stdin);   // EOF makes sqlite3 exit
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("sqlite3 exited with {}", status)));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        // like GzipWriter, errors on drop are ignored, call finish to see them
+        let _ = self.finish();
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_statement() {
+        let sample = MemorySample{
+            pid: 42,
+            process_name: "(it's)",
+            current_bytes: 1024,
+            max_bytes: 2048,
+            timestamp: 1_700_000_000,
+            mem_pressure_some: Some(1.5),
+            is_final: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            insert_statement(&sample),
+            "INSERT INTO samples VALUES (1700000000, 42, '(it''s)', 1024, 2048, 0, 0, 1.5, NULL, 1);\n"
+        );
+    }
+
+    #[test]
+    fn test_create_table_matches_insert_arity() {
+        let columns = CREATE_TABLE.lines().filter(|line| line.starts_with("    ")).count();
+        let values = insert_statement(&MemorySample::default()).matches(", ").count() + 1;
+        assert_eq!(columns, values);
+    }
+}
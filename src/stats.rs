@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use crate::histogram::Histogram;
+use crate::template_engine::{format_memory, MemorySample};
+
+
+// --time-weighted-avg: every sample weighs the time elapsed since the previous one,
+// so that the mean is the area under the memory curve divided by its duration,
+// whatever the jitter of the interval or the --adaptive back-off.
+#[derive(Debug, Default)]
+pub struct TimeWeightedAverage {
+    area: u128,       // bytes x microseconds
+    duration: u128,   // microseconds
+    last: Option<Instant>,   // None at the start and while paused
+    latest: Option<u64>,
+}
+
+impl TimeWeightedAverage {
+    pub fn new() -> Self {
+        TimeWeightedAverage::default()
+    }
+
+    pub fn record(&mut self, memory: u64, now: Instant) {
+        if let Some(previous) = self.last {
+            let elapsed = now.saturating_duration_since(previous).as_micros();
+            self.area += memory as u128 * elapsed;
+            self.duration += elapsed;
+        }
+        self.last = Some(now);
+        self.latest = Some(memory);
+    }
+
+    pub fn pause(&mut self) {
+        // the time until the next record is not counted, see --skip-stopped
+        self.last = None;
+    }
+
+    pub fn average(&self) -> Option<u64> {
+        // a single sample has no duration, it is its own average
+        match (self.duration, self.latest) {
+            (_, None) => None,
+            (0, Some(memory)) => Some(memory),
+            (duration, Some(_)) => Some((self.area / duration) as u64),
+        }
+    }
+}
+
+
+// The summary printed under the final line: every sample counts the same,
+// unlike --time-weighted-avg, which is what a capacity plan wants to compare runs.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub count: u64,
+    pub sum: u128,   // bytes, cannot overflow before 2^64 samples of u64::MAX
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    pub fn observe(&mut self, value: u64) {
+        self.count += 1;
+        self.sum += value as u128;
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+    }
+
+    pub fn mean(&self) -> Option<u64> {
+        (self.count > 0).then(|| (self.sum / self.count as u128) as u64)
+    }
+
+    pub fn format(&self, precision: usize) -> String {
+        let (Some(min), Some(mean), Some(max)) = (self.min, self.mean(), self.max) else {
+            return "stats: no samples\n".to_string();
+        };
+        format!(
+            "stats: samples {}, min {}, mean {}, max {}\n",
+            self.count,
+            format_memory(min, precision),
+            format_memory(mean, precision),
+            format_memory(max, precision),
+        )
+    }
+}
+
+
+// --summary-json: the statistics of the whole run for a CI step to assert against.
+// The samples are counted per distinct value, memory sizes repeat a lot, so that the
+// percentile is exact without keeping every sample of a long run.
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    samples: u64,
+    total: u128,
+    counts: BTreeMap<u64, u64>,
+    peak_process_count: u64,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl RunSummary {
+    pub fn new() -> Self {
+        RunSummary::default()
+    }
+
+    pub fn record(&mut self, memory: u64, process_count: u64, now: Instant) {
+        self.samples += 1;
+        self.total += memory as u128;
+        *self.counts.entry(memory).or_insert(0) += 1;
+        self.peak_process_count = self.peak_process_count.max(process_count);
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+
+    pub fn average(&self) -> Option<u64> {
+        (self.samples > 0).then(|| (self.total / self.samples as u128) as u64)
+    }
+
+    pub fn percentile(&self, percent: u64) -> Option<u64> {
+        // nearest rank: the smallest value with at least percent% of the samples at or below it
+        let rank = (self.samples * percent).div_ceil(100).max(1);
+        let mut seen = 0;
+        self.counts.iter().find_map(|(memory, count)| {
+            seen += count;
+            (seen >= rank).then_some(*memory)
+        })
+    }
+
+    pub fn to_json(&self, max: u64) -> String {
+        // in bytes, the unit a dashboard expects
+        let bytes = |value: Option<u64>| value.map_or("null".to_string(), |value| value.to_string());
+        let duration = match (self.first, self.last) {
+            (Some(first), Some(last)) => last.saturating_duration_since(first).as_secs_f64(),
+            _ => 0.0,
+        };
+        format!(
+            "{{\"max_bytes\":{},\"avg_bytes\":{},\"p95_bytes\":{},\"duration_seconds\":{:.3},\"samples\":{},\"peak_process_count\":{}}}\n",
+            max, bytes(self.average()), bytes(self.percentile(95)), duration, self.samples, self.peak_process_count,
+        )
+    }
+}
+
+
+// The statistics every sample goes through, whether it is read from /proc
+// or from a recording with --replay, so that both give the same summary.
+#[derive(Debug)]
+pub struct RunStats {
+    pub histogram: Option<Histogram>,
+    time_weighted_avg: Option<TimeWeightedAverage>,
+    pub summary: Option<RunSummary>,
+    pub stats: Stats,
+}
+
+impl RunStats {
+    pub fn new(histogram_bucket: Option<u64>, time_weighted_avg: bool, summary: bool) -> Self {
+        RunStats{
+            histogram: histogram_bucket.map(Histogram::new),
+            time_weighted_avg: time_weighted_avg.then(TimeWeightedAverage::new),
+            summary: summary.then(RunSummary::new),
+            stats: Stats::new(),
+        }
+    }
+
+    pub fn record(&mut self, sample: &mut MemorySample, now: Instant, stopped: bool) {
+        // a stopped target still sets the max, see --skip-stopped
+        sample.max_bytes = sample.max_bytes.max(sample.current_bytes);
+        if let Some(histogram) = self.histogram.as_mut()
+            && !stopped {
+                histogram.record(sample.current_bytes);
+            }
+        if let Some(summary) = self.summary.as_mut()
+            && !stopped {
+                summary.record(sample.current_bytes, sample.process_count, now);
+            }
+        if !stopped {
+            self.stats.observe(sample.current_bytes);
+        }
+        if let Some(average) = self.time_weighted_avg.as_mut() {
+            if stopped {
+                average.pause();
+            } else {
+                average.record(sample.current_bytes, now);
+            }
+            sample.time_weighted_avg = average.average();
+        }
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_time_weighted_average() {
+        let start = Instant::now();
+        let mut average = TimeWeightedAverage::new();
+        assert_eq!(average.average(), None);
+        average.record(100, start);
+        assert_eq!(average.average(), Some(100));
+        // 1s at 1000 then 3s at 200: the plain mean of the samples would be 433
+        average.record(1000, start + Duration::from_secs(1));
+        average.record(200, start + Duration::from_secs(4));
+        assert_eq!(average.average(), Some(400));
+    }
+
+    #[test]
+    fn test_time_weighted_average_pause() {
+        let start = Instant::now();
+        let mut average = TimeWeightedAverage::new();
+        average.record(100, start);
+        average.record(100, start + Duration::from_secs(1));
+        average.pause();
+        // the 10s spent stopped do not weigh on the average
+        average.record(400, start + Duration::from_secs(11));
+        average.record(400, start + Duration::from_secs(12));
+        assert_eq!(average.average(), Some(250));
+    }
+
+    #[test]
+    fn test_time_weighted_average_same_instant() {
+        let start = Instant::now();
+        let mut average = TimeWeightedAverage::new();
+        average.record(100, start);
+        average.record(300, start);
+        assert_eq!(average.average(), Some(300));
+    }
+
+    #[test]
+    fn test_stats_empty() {
+        let stats = Stats::new();
+        assert_eq!((stats.count, stats.min, stats.max, stats.mean()), (0, None, None, None));
+        assert_eq!(stats.format(2), "stats: no samples\n");
+    }
+
+    #[test]
+    fn test_stats_single_sample() {
+        let mut stats = Stats::new();
+        stats.observe(2048);
+        assert_eq!((stats.count, stats.min, stats.max, stats.mean()), (1, Some(2048), Some(2048), Some(2048)));
+        assert_eq!(stats.format(0), "stats: samples 1, min 2KiB, mean 2KiB, max 2KiB\n");
+    }
+
+    #[test]
+    fn test_stats_increasing() {
+        let mut stats = Stats::new();
+        for value in [100, 200, 300, 401] {
+            stats.observe(value);
+        }
+        // the mean is rounded down
+        assert_eq!((stats.count, stats.sum), (4, 1001));
+        assert_eq!((stats.min, stats.max, stats.mean()), (Some(100), Some(401), Some(250)));
+        stats.observe(u64::MAX);
+        stats.observe(u64::MAX);
+        assert_eq!(stats.max, Some(u64::MAX));
+        assert_eq!(stats.mean(), Some(((1001 + 2 * u64::MAX as u128) / 6) as u64));
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let start = Instant::now();
+        let mut summary = RunSummary::new();
+        assert_eq!(summary.percentile(95), None);
+        assert_eq!(
+            summary.to_json(0),
+            "{\"max_bytes\":0,\"avg_bytes\":null,\"p95_bytes\":null,\"duration_seconds\":0.000,\"samples\":0,\"peak_process_count\":0}\n",
+        );
+        for second in 0..20 {
+            // 100KiB for 19 samples, one 1000KiB spike
+            let memory = if second == 7 { 1000 * 1024 } else { 100 * 1024 };
+            summary.record(memory, 1 + second % 3, start + Duration::from_millis(second * 500));
+        }
+        assert_eq!(summary.average(), Some(145 * 1024));
+        assert_eq!(summary.percentile(95), Some(100 * 1024));
+        assert_eq!(summary.percentile(100), Some(1000 * 1024));
+        assert_eq!(
+            summary.to_json(1000 * 1024),
+            "{\"max_bytes\":1024000,\"avg_bytes\":148480,\"p95_bytes\":102400,\"duration_seconds\":9.500,\"samples\":20,\"peak_process_count\":3}\n",
+        );
+    }
+
+    #[test]
+    fn test_run_stats_record() {
+        let start = Instant::now();
+        let mut stats = RunStats::new(Some(100), true, false);
+        let mut sample = MemorySample::default();
+        for (seconds, memory, stopped) in [(0, 150, false), (1, 150, false), (2, 900, true), (3, 50, false)] {
+            sample.current_bytes = memory;
+            stats.record(&mut sample, start + Duration::from_secs(seconds), stopped);
+        }
+        assert_eq!(sample.max_bytes, 900);
+        assert_eq!(sample.time_weighted_avg, Some(150));
+        // the max of the line keeps the stopped sample, the stats do not
+        assert_eq!((stats.stats.count, stats.stats.max), (3, Some(150)));
+        // the stopped sample is left out, the last one in the first bucket
+        let histogram = stats.histogram.unwrap().format(0);
+        assert!(histogram.contains("0B - 100B    #################### 1\n"));
+        assert!(histogram.contains("100B - 200B  ######################################## 2\n"));
+        assert!(!histogram.contains("900B"));
+    }
+}
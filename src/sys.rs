@@ -0,0 +1,203 @@
+// Minimal bindings to the few libc functions std does not expose.
+// std already links against the libc, so this does not add a dependency.
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod ffi {
+    unsafe extern "C" {
+        pub fn kill(pid: i32, sig: i32) -> i32;
+        pub fn sysconf(name: i32) -> i64;
+        pub fn signal(signum: i32, handler: usize) -> usize;
+        pub fn ioctl(fd: i32, request: u64, ...) -> i32;
+        pub fn _exit(status: i32) -> !;
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Winsize {
+        pub rows: u16,
+        pub columns: u16,
+        pub x_pixels: u16,
+        pub y_pixels: u16,
+    }
+
+    pub const SC_PAGESIZE: i32 = 30;   // same value on every Linux architecture
+    pub const SIGINT: i32 = 2;
+    pub const SIGTERM: i32 = 15;
+    pub const SIGUSR1: i32 = 10;   // x86, arm and most architectures, not mips nor sparc
+    pub const TIOCGWINSZ: u64 = 0x5413;   // x86 and arm, not mips, powerpc nor sparc
+    pub const STDOUT: i32 = 1;
+}
+
+
+// open(2) flags std does not define, they differ between architectures
+#[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
+pub const O_NOFOLLOW: i32 = 0o100000;
+#[cfg(not(any(target_arch = "aarch64", target_arch = "arm")))]
+pub const O_NOFOLLOW: i32 = 0o400000;
+
+pub const O_NONBLOCK: i32 = 0o4000;   // x86 and arm, not mips, alpha nor sparc
+pub const ELOOP: i32 = 40;   // what open returns for a symlink with O_NOFOLLOW
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    Term = 15,
+    Kill = 9,
+    #[cfg(test)]
+    Stop = 19,
+}
+
+
+pub fn send_signal(pid: i32, signal: Signal) -> io::Result<()> {
+    if pid <= 0 {
+        // 0 and negative values target process groups, never what we want here
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+    // SAFETY: kill has no memory safety requirement, pid is checked to be a single process
+    match unsafe { ffi::kill(pid, signal as i32) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+
+pub fn terminal_width() -> Option<usize> {
+    // None when stdout is not a terminal
+    let mut size = ffi::Winsize::default();
+    // SAFETY: TIOCGWINSZ writes a struct winsize, which Winsize matches
+    let result = unsafe { ffi::ioctl(ffi::STDOUT, ffi::TIOCGWINSZ, &mut size as *mut ffi::Winsize) };
+    (result == 0 && size.columns > 0).then_some(size.columns as usize)
+}
+
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn record_request(requested: &AtomicBool) -> bool {
+    // true when a request was already pending: a second Ctrl-C is from someone
+    // who does not want to wait for the summary
+    requested.swap(true, Ordering::SeqCst)
+}
+
+extern "C" fn request_shutdown(signum: i32) {
+    // only async-signal-safe work here, the main loop does the rest
+    if record_request(&SHUTDOWN_REQUESTED) {
+        // SAFETY: _exit is async-signal-safe, unlike process::exit which runs the atexit handlers
+        unsafe { ffi::_exit(128 + signum) };
+    }
+}
+
+pub fn catch_shutdown_signals() {
+    // SIGINT and SIGTERM stop the sampling loop instead of the process, see shutdown_requested
+    for signum in [ffi::SIGINT, ffi::SIGTERM] {
+        // SAFETY: the handler only uses an atomic and _exit; glibc's signal keeps it installed (BSD semantics)
+        unsafe { ffi::signal(signum, request_shutdown as extern "C" fn(i32) as usize) };
+    }
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+pub fn sleep_unless_shutdown(duration: Duration) {
+    // thread::sleep goes on after a signal, Ctrl-C would wait for the end of the interval
+    let deadline = Instant::now() + duration;
+    while !shutdown_requested() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        thread::sleep(left.min(Duration::from_millis(50)));
+    }
+}
+
+
+static SNAPSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_snapshot(_signum: i32) {
+    SNAPSHOT_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub fn catch_snapshot_signal() {
+    // SIGUSR1 would otherwise terminate memimpact
+    // SAFETY: the handler only stores to an atomic, as in catch_shutdown_signals
+    unsafe { ffi::signal(ffi::SIGUSR1, request_snapshot as extern "C" fn(i32) as usize) };
+}
+
+pub fn take_snapshot_request() -> bool {
+    // several signals between two samples give a single snapshot
+    SNAPSHOT_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+
+pub fn page_size() -> Option<u64> {
+    // in bytes, SAFETY: sysconf only reads a system constant
+    let bytes = unsafe { ffi::sysconf(ffi::SC_PAGESIZE) };
+    if bytes < 1024 {
+        return None;
+    }
+    Some(bytes as u64)
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_send_signal_terminates_child() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        send_signal(child.id() as i32, Signal::Term).unwrap();
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+    }
+
+    #[test]
+    fn test_send_signal_rejects_groups() {
+        assert!(send_signal(0, Signal::Term).is_err());
+        assert!(send_signal(-1, Signal::Kill).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_signal() {
+        catch_snapshot_signal();
+        assert!(!take_snapshot_request());
+        // SAFETY: kill has no memory safety requirement
+        assert_eq!(unsafe { ffi::kill(std::process::id() as i32, ffi::SIGUSR1) }, 0);
+        // the handler runs before kill returns when the signal targets the calling thread,
+        // otherwise on another thread of this process shortly after
+        let mut requested = false;
+        for _ in 0..100 {
+            requested = take_snapshot_request();
+            if requested {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(requested);
+        assert!(!take_snapshot_request());
+    }
+
+    #[test]
+    fn test_record_request() {
+        // not through the signal and the static: tests run the sampling loop of main
+        let requested = AtomicBool::new(false);
+        assert!(!record_request(&requested));
+        assert!(requested.load(Ordering::SeqCst));
+        assert!(record_request(&requested));
+        assert!(!shutdown_requested());
+    }
+
+    #[test]
+    fn test_page_size() {
+        // 4KiB on x86, up to 64KiB on some arm64 and ppc64 kernels
+        let page_size = page_size().unwrap();
+        assert!(page_size.is_power_of_two());
+        assert!((4096..=65536).contains(&page_size));
+    }
+}
@@ -2,16 +2,62 @@ pub mod template_engine{
 	use std::str::FromStr;
 	use std::fmt::Write;
 
-	pub fn format_memory_from_kib(value: u64) -> String{
-		// every possible u64 values are handled, it is impossible to be stuck in an infinite loop
-		const UNITS: [&str; 7] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB"];
+	pub const MAX_PRECISION: usize = 9;
+
+	pub fn format_memory(value: u64, precision: usize) -> String{
+		// value in bytes, every possible u64 values are handled, it is impossible to be stuck in an infinite loop
+		// decimals are rounded, and a value rounded up to 1024 moves to the next unit:
+		// 1048575 bytes are 1.00MiB, never 1024.00KiB
+		const UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
 	    let mut current = value;
 	    let mut unit_index = 0;
 	    while current >= 1024 && unit_index < UNITS.len() - 1 {
 	        current >>= 10;
 	        unit_index += 1;
 	    }
-	    format!("{}{}", current, UNITS[unit_index])
+	    let precision = precision.min(MAX_PRECISION);
+	    // u128 since the value uses 64 bits and 10^9 needs 30 more
+	    let scale = 10u128.pow(precision as u32);
+	    loop {
+	        let shift = 10 * unit_index as u32;
+	        let half = if shift == 0 { 0 } else { 1u128 << (shift - 1) };
+	        let scaled = ((value as u128) * scale + half) >> shift;
+	        if scaled >= 1024 * scale && unit_index < UNITS.len() - 1 {
+	            unit_index += 1;
+	            continue;
+	        }
+	        let (whole, fraction) = (scaled / scale, scaled % scale);
+	        if precision == 0 {
+	            return format!("{}{}", whole, UNITS[unit_index]);
+	        }
+	        return format!("{}.{:0width$}{}", whole, fraction, UNITS[unit_index], width = precision);
+	    }
+	}
+
+	fn civil_from_days(days: i64) -> (i64, u32, u32) {
+	    // proleptic Gregorian calendar, from days since 1970-01-01 to year, month and day
+	    // see https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+	    let z = days + 719_468;
+	    let era = z.div_euclid(146_097);
+	    let day_of_era = z.rem_euclid(146_097);   // [0, 146096]
+	    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;   // [0, 399]
+	    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);   // [0, 365], from March 1st
+	    let shifted_month = (5 * day_of_year + 2) / 153;   // [0, 11], March is 0
+	    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+	    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+	    let year = year_of_era + era * 400 + i64::from(month <= 2);
+	    (year, month, day)
+	}
+
+	pub fn format_iso8601(timestamp: u64, out: &mut String) -> std::fmt::Result {
+	    // UTC, to the second: 2023-11-14T22:13:20Z
+	    let (days, seconds) = (timestamp / 86_400, timestamp % 86_400);
+	    let (year, month, day) = civil_from_days(days as i64);
+	    write!(
+	        out,
+	        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+	        year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60,
+	    )
 	}
 
 	pub fn unescape(input: &str) -> Result<String, String> {
@@ -35,13 +81,145 @@ pub mod template_engine{
 	    Ok(out)
 	}
 
+	fn write_optional_number(out: &mut String, value: Option<u64>) -> std::fmt::Result {
+		match value {
+			Some(v) => write!(out, "{}", v),
+			None => {
+				out.push_str("n/a");
+				Ok(())
+			}
+		}
+	}
+
+	fn write_delta(out: &mut String, delta: i128, human: Option<usize>) -> std::fmt::Result {
+		// always signed, +0 when nothing changed
+		let sign = if delta < 0 { '-' } else { '+' };
+		match human {
+			Some(precision) => write!(out, "{}{}", sign, format_memory(delta.unsigned_abs() as u64, precision)),
+			None => write!(out, "{}{}", sign, delta.unsigned_abs()),
+		}
+	}
+
+	fn write_share_of_ram(out: &mut String, value: Option<f64>) -> std::fmt::Result {
+		match value {
+			Some(v) => write!(out, "{:.1}%", v),
+			None => {
+				out.push_str("n/a");
+				Ok(())
+			}
+		}
+	}
+
+	fn write_optional_percent(out: &mut String, value: Option<f64>) -> std::fmt::Result {
+		match value {
+			Some(v) => write!(out, "{:.2}", v),
+			None => {
+				out.push_str("n/a");
+				Ok(())
+			}
+		}
+	}
+
 	
+	#[derive(Debug, Default, Clone)]
 	pub struct MemorySample<'a> {
 	    pub pid: i32,
 	    pub process_name: &'a str,
-	    pub current_bytes: u64,
+	    pub current_bytes: u64, // in bytes, as every size of the sample
+	    pub previous_bytes: Option<u64>, // current_bytes of the previous measurement, None for the first
 	    pub max_bytes: u64,
+	    pub private_bytes: u64, // resident minus shared, summed per process
+	    pub process_count: u64, // number of processes in the tracked tree
 	    pub timestamp: u64, // seconds since epoch
+	    pub is_final: bool, // true for the summary sample rendered once the targets are gone
+	    pub mem_pressure_some: Option<f64>, // PSI avg10 percentages, None when not measured
+	    pub mem_pressure_full: Option<f64>,
+	    pub rss_anon: Option<u64>, // /proc/[pid]/status breakdown of the RSS, None when not read
+	    pub rss_file: Option<u64>,
+	    pub rss_shmem: Option<u64>,
+	    pub time_weighted_avg: Option<u64>, // with --time-weighted-avg
+	    pub sample_number: u64, // 1 for the first sample, the final line repeats the last one
+	    pub map_count: Option<u64>, // with --map-count
+	    pub uss: Option<u64>, // from smaps_rollup, None when not read
+	    pub pss: Option<u64>, // from smaps_rollup or smaps, None when not read
+	    pub huge_pages: Option<u64>, // with --hugepages
+	    pub swap: Option<u64>, // with --swap
+	    pub vsz: u64, // virtual size summed over the tree, first field of statm
+	    pub mem_total: Option<u64>, // MemTotal of /proc/meminfo, read once, None when unknown
+	    pub peaks: Peaks,
+	}
+
+	// the metrics besides the RSS peak at their own time, each keeps its maximum
+	#[derive(Debug, Default, Clone, Copy, PartialEq)]
+	pub struct Peaks {
+	    pub private_bytes: u64,
+	    pub rss_anon: Option<u64>, // None while the metric is not measured
+	    pub rss_file: Option<u64>,
+	    pub rss_shmem: Option<u64>,
+	    pub map_count: Option<u64>,
+	    pub uss: Option<u64>,
+	    pub vsz: u64,
+	    pub pss: Option<u64>,
+	    pub swap: Option<u64>,
+	    pub huge_pages: Option<u64>,
+	}
+
+	fn max_measured(peak: Option<u64>, value: Option<u64>) -> Option<u64> {
+	    match (peak, value) {
+	        (Some(peak), Some(value)) => Some(peak.max(value)),
+	        (peak, value) => peak.or(value),
+	    }
+	}
+
+	impl MemorySample<'_> {
+	    pub fn percent_of_max(&self) -> Option<f64> {
+	        // None until something was measured; capped since a replayed max can lag behind
+	        match self.max_bytes {
+	            0 => None,
+	            max => Some((self.current_bytes as f64 * 100.0 / max as f64).min(100.0)),
+	        }
+	    }
+
+	    fn share_of_ram(&self, memory: u64) -> Option<f64> {
+	        // capped: the VSZ, or a PSS summed over a whole system, can go above the RAM
+	        match self.mem_total {
+	            None | Some(0) => None,
+	            Some(total) => Some((memory as f64 * 100.0 / total as f64).min(100.0)),
+	        }
+	    }
+
+	    pub fn current_percent(&self) -> Option<f64> {
+	        self.share_of_ram(self.current_bytes)
+	    }
+
+	    pub fn max_percent(&self) -> Option<f64> {
+	        self.share_of_ram(self.max_bytes)
+	    }
+
+	    pub fn delta(&self) -> i128 {
+	        // the first sample has nothing to compare with, it did not change
+	        self.current_bytes as i128 - self.previous_bytes.unwrap_or(self.current_bytes) as i128
+	    }
+
+	    pub fn next_sample(&mut self) {
+	        // called once per measurement, before the values are updated
+	        self.previous_bytes = (self.sample_number > 0).then_some(self.current_bytes);
+	        self.sample_number += 1;
+	    }
+
+	    pub fn update_peaks(&mut self) {
+	        // called once per measurement, after the values are updated
+	        self.peaks.private_bytes = self.peaks.private_bytes.max(self.private_bytes);
+	        self.peaks.rss_anon = max_measured(self.peaks.rss_anon, self.rss_anon);
+	        self.peaks.rss_file = max_measured(self.peaks.rss_file, self.rss_file);
+	        self.peaks.rss_shmem = max_measured(self.peaks.rss_shmem, self.rss_shmem);
+	        self.peaks.map_count = max_measured(self.peaks.map_count, self.map_count);
+	        self.peaks.uss = max_measured(self.peaks.uss, self.uss);
+	        self.peaks.vsz = self.peaks.vsz.max(self.vsz);
+	        self.peaks.pss = max_measured(self.peaks.pss, self.pss);
+	        self.peaks.swap = max_measured(self.peaks.swap, self.swap);
+	        self.peaks.huge_pages = max_measured(self.peaks.huge_pages, self.huge_pages);
+	    }
 	}
 
 	#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -53,6 +231,237 @@ pub mod template_engine{
 	    CurrentHuman,
 	    MaxHuman,
 	    Timestamp,
+	    Final,
+	    MemPressure,
+	    MemPressureFull,
+	    Private,
+	    PrivateHuman,
+	    ProcessCount,
+	    RssAnon,
+	    RssFile,
+	    RssShmem,
+	    TimeWeightedAvg,
+	    SampleNumber,
+	    MapCount,
+	    MaxPrivate,
+	    MaxRssAnon,
+	    MaxRssFile,
+	    MaxRssShmem,
+	    MaxMapCount,
+	    Uss,
+	    UssHuman,
+	    Pss,
+	    PssHuman,
+	    PercentOfMax,
+	    CurrentPercent,
+	    MaxPercent,
+	    Delta,
+	    DeltaHuman,
+	    Iso8601,
+	    HugePages,
+	    Swap,
+	    SwapHuman,
+	    Vsz,
+	    VszHuman,
+	    MaxUss,
+	    MaxVsz,
+	    MaxPss,
+	    MaxSwap,
+	    MaxHugePages,
+	}
+
+	#[derive(Debug, Clone, Copy, PartialEq)]
+	pub enum FieldKind {
+	    Number,
+	    String,
+	    Bytes,
+	    Bool,
+	}
+
+	impl FieldKind {
+	    pub fn name(&self) -> &'static str {
+	        match self {
+	            FieldKind::Number => "number",
+	            FieldKind::String => "string",
+	            FieldKind::Bytes => "bytes",
+	            FieldKind::Bool => "bool",
+	        }
+	    }
+	}
+
+	impl Field {
+	    // every field, in the order of --list-fields; a new variant must be added here
+	    pub const ALL: [Field; 44] = [
+	        Field::Pid,
+	        Field::ProcessName,
+	        Field::CurrentBytes,
+	        Field::MaxBytes,
+	        Field::CurrentHuman,
+	        Field::MaxHuman,
+	        Field::PercentOfMax,
+	        Field::CurrentPercent,
+	        Field::MaxPercent,
+	        Field::Delta,
+	        Field::DeltaHuman,
+	        Field::Private,
+	        Field::PrivateHuman,
+	        Field::MaxPrivate,
+	        Field::Uss,
+	        Field::UssHuman,
+	        Field::MaxUss,
+	        Field::Pss,
+	        Field::PssHuman,
+	        Field::MaxPss,
+	        Field::Vsz,
+	        Field::VszHuman,
+	        Field::MaxVsz,
+	        Field::RssAnon,
+	        Field::RssFile,
+	        Field::RssShmem,
+	        Field::MaxRssAnon,
+	        Field::MaxRssFile,
+	        Field::MaxRssShmem,
+	        Field::HugePages,
+	        Field::MaxHugePages,
+	        Field::Swap,
+	        Field::SwapHuman,
+	        Field::MaxSwap,
+	        Field::TimeWeightedAvg,
+	        Field::ProcessCount,
+	        Field::SampleNumber,
+	        Field::MapCount,
+	        Field::MaxMapCount,
+	        Field::Timestamp,
+	        Field::Iso8601,
+	        Field::Final,
+	        Field::MemPressure,
+	        Field::MemPressureFull,
+	    ];
+
+	    pub fn name(&self) -> &'static str {
+	        match self {
+	            Field::Pid => "Pid",
+	            Field::ProcessName => "ProcessName",
+	            Field::CurrentBytes => "CurrentBytes",
+	            Field::MaxBytes => "MaxBytes",
+	            Field::CurrentHuman => "CurrentHuman",
+	            Field::MaxHuman => "MaxHuman",
+	            Field::Timestamp => "Timestamp",
+	            Field::Final => "Final",
+	            Field::MemPressure => "MemPressure",
+	            Field::MemPressureFull => "MemPressureFull",
+	            Field::Private => "Private",
+	            Field::PrivateHuman => "PrivateHuman",
+	            Field::ProcessCount => "ProcessCount",
+	            Field::RssAnon => "RssAnon",
+	            Field::RssFile => "RssFile",
+	            Field::RssShmem => "RssShmem",
+	            Field::TimeWeightedAvg => "TimeWeightedAvg",
+	            Field::SampleNumber => "SampleNumber",
+	            Field::MapCount => "MapCount",
+	            Field::MaxPrivate => "MaxPrivate",
+	            Field::MaxRssAnon => "MaxRssAnon",
+	            Field::MaxRssFile => "MaxRssFile",
+	            Field::MaxRssShmem => "MaxRssShmem",
+	            Field::MaxMapCount => "MaxMapCount",
+	            Field::Uss => "Uss",
+	            Field::UssHuman => "UssHuman",
+	            Field::MaxUss => "MaxUss",
+	            Field::Pss => "Pss",
+	            Field::PssHuman => "PssHuman",
+	            Field::MaxPss => "MaxPss",
+	            Field::PercentOfMax => "PercentOfMax",
+	            Field::CurrentPercent => "CurrentPercent",
+	            Field::MaxPercent => "MaxPercent",
+	            Field::Delta => "Delta",
+	            Field::DeltaHuman => "DeltaHuman",
+	            Field::Iso8601 => "Iso8601",
+	            Field::HugePages => "HugePages",
+	            Field::MaxHugePages => "MaxHugePages",
+	            Field::Swap => "Swap",
+	            Field::SwapHuman => "SwapHuman",
+	            Field::MaxSwap => "MaxSwap",
+	            Field::Vsz => "Vsz",
+	            Field::VszHuman => "VszHuman",
+	            Field::MaxVsz => "MaxVsz",
+	        }
+	    }
+
+	    pub fn description(&self) -> &'static str {
+	        match self {
+	            Field::Pid => "PID of the first target",
+	            Field::ProcessName => "command name of the first target",
+	            Field::CurrentBytes => "current RSS of the tree",
+	            Field::MaxBytes => "maximum RSS of the tree observed so far",
+	            Field::CurrentHuman => "CurrentBytes in IEC units",
+	            Field::MaxHuman => "MaxBytes in IEC units",
+	            Field::Timestamp => "seconds since the Unix epoch",
+	            Field::Final => "true on the trailing summary line",
+	            Field::MemPressure => "PSI some avg10 percentage, n/a without --psi",
+	            Field::MemPressureFull => "PSI full avg10 percentage, n/a without --psi",
+	            Field::Private => "resident memory not shared, an approximation of the USS",
+	            Field::PrivateHuman => "Private in IEC units",
+	            Field::ProcessCount => "number of processes in the tree",
+	            Field::RssAnon => "anonymous resident memory (heap, stacks), n/a before Linux 4.5",
+	            Field::RssFile => "file-backed resident memory, n/a before Linux 4.5",
+	            Field::RssShmem => "resident shared memory, n/a before Linux 4.5",
+	            Field::TimeWeightedAvg => "RSS averaged over time so far, n/a without --time-weighted-avg",
+	            Field::SampleNumber => "number of the sample, from 1, to detect dropped lines",
+	            Field::MapCount => "memory mappings of the tree, n/a without --map-count",
+	            Field::MaxPrivate => "maximum Private observed so far",
+	            Field::MaxRssAnon => "maximum RssAnon observed so far",
+	            Field::MaxRssFile => "maximum RssFile observed so far",
+	            Field::MaxRssShmem => "maximum RssShmem observed so far",
+	            Field::MaxMapCount => "maximum MapCount observed so far",
+	            Field::Uss => "unique set size from smaps_rollup, RSS where it cannot be read",
+	            Field::UssHuman => "Uss in IEC units",
+	            Field::MaxUss => "maximum Uss observed so far",
+	            Field::Pss => "proportional set size, shared pages split between their users, n/a where unreadable",
+	            Field::PssHuman => "Pss in IEC units",
+	            Field::MaxPss => "maximum Pss observed so far",
+	            Field::PercentOfMax => "CurrentBytes as a percentage of MaxBytes, n/a before any memory",
+	            Field::CurrentPercent => "CurrentBytes as a percentage of the RAM, like 3.2%",
+	            Field::MaxPercent => "MaxBytes as a percentage of the RAM, like 3.2%",
+	            Field::Delta => "signed change of CurrentBytes since the previous sample, +0 first",
+	            Field::DeltaHuman => "Delta in IEC units, like -512KiB",
+	            Field::Iso8601 => "Timestamp as a UTC date and time, like 2023-11-14T22:13:20Z",
+	            Field::HugePages => "hugetlbfs pages of the tree, not in the RSS, n/a without --hugepages",
+	            Field::MaxHugePages => "maximum HugePages observed so far",
+	            Field::Swap => "memory of the tree moved to swap, not in the RSS, n/a without --swap",
+	            Field::SwapHuman => "Swap in IEC units",
+	            Field::MaxSwap => "maximum Swap observed so far",
+	            Field::Vsz => "virtual memory size of the tree, resident or not",
+	            Field::VszHuman => "Vsz in IEC units",
+	            Field::MaxVsz => "maximum Vsz observed so far",
+	        }
+	    }
+
+	    pub fn kind(&self) -> FieldKind {
+	        match self {
+	            Field::Pid | Field::ProcessCount | Field::Timestamp | Field::SampleNumber | Field::MapCount => FieldKind::Number,
+	            Field::MaxMapCount => FieldKind::Number,
+	            Field::MemPressure | Field::MemPressureFull | Field::PercentOfMax => FieldKind::Number,
+	            Field::ProcessName | Field::CurrentHuman | Field::MaxHuman | Field::PrivateHuman => FieldKind::String,
+	            Field::UssHuman | Field::PssHuman | Field::VszHuman | Field::SwapHuman => FieldKind::String,
+	            Field::CurrentPercent | Field::MaxPercent | Field::DeltaHuman | Field::Iso8601 => FieldKind::String,
+	            Field::Delta => FieldKind::Number,
+	            Field::CurrentBytes | Field::MaxBytes | Field::Private => FieldKind::Bytes,
+	            Field::RssAnon | Field::RssFile | Field::RssShmem | Field::TimeWeightedAvg => FieldKind::Bytes,
+	            Field::MaxPrivate | Field::MaxRssAnon | Field::MaxRssFile | Field::MaxRssShmem => FieldKind::Bytes,
+	            Field::MaxUss | Field::MaxVsz | Field::MaxPss | Field::MaxSwap | Field::MaxHugePages => FieldKind::Bytes,
+	            Field::Uss | Field::Pss | Field::HugePages | Field::Swap | Field::Vsz => FieldKind::Bytes,
+	            Field::Final => FieldKind::Bool,
+	        }
+	    }
+	}
+
+	pub fn list_fields() -> String {
+	    let width = Field::ALL.iter().map(|field| field.name().len()).max().unwrap_or(0);
+	    let mut out = String::new();
+	    for field in Field::ALL {
+	        out.push_str(&format!("{:<width$}  {:<6}  {}\n", field.name(), field.kind().name(), field.description(), width = width));
+	    }
+	    out
 	}
 
 	impl FromStr for Field {
@@ -60,17 +469,70 @@ pub mod template_engine{
 	    type Err = String;
 	
 	    fn from_str(input: &str) -> Result<Field, Self::Err> {
-	        match input {
-	            "Pid"  => Ok(Field::Pid),
-	            "ProcessName"  => Ok(Field::ProcessName),
-	            "CurrentBytes"  => Ok(Field::CurrentBytes),
-	            "MaxBytes" => Ok(Field::MaxBytes),
-	            "CurrentHuman" => Ok(Field::CurrentHuman),
-	            "MaxHuman" => Ok(Field::MaxHuman),
-	            "Timestamp" => Ok(Field::Timestamp),
-	            _      => Err(format!("unknow field {:?}", input)),
-	        }
+	        Field::ALL.into_iter()
+	            .find(|field| field.name() == input)
+	            .ok_or(format!("unknow field {:?}", input))
+	    }
+	}
+
+	pub fn render_field(field: Field, sample: &MemorySample, precision: usize, out: &mut String) -> std::fmt::Result {
+	    // the value of one field, as a template shows it; --fields renders them one by one
+	    match field {
+	        Field::Pid => write!(out, "{}", sample.pid)?,
+	        Field::ProcessName => out.push_str(sample.process_name),
+	        Field::CurrentBytes => write!(out, "{}", sample.current_bytes)?,
+	        Field::MaxBytes => write!(out, "{}", sample.max_bytes)?,
+	        Field::CurrentHuman => write!(out, "{}",format_memory(sample.current_bytes, precision))?,
+	        Field::MaxHuman => write!(out, "{}", format_memory(sample.max_bytes, precision))?,
+	        Field::Timestamp => write!(out, "{}", sample.timestamp)?,
+	        Field::Iso8601 => format_iso8601(sample.timestamp, out)?,
+	        Field::Final => write!(out, "{}", sample.is_final)?,
+	        Field::MemPressure => write_optional_percent(out, sample.mem_pressure_some)?,
+	        Field::MemPressureFull => write_optional_percent(out, sample.mem_pressure_full)?,
+	        Field::Private => write!(out, "{}", sample.private_bytes)?,
+	        Field::PrivateHuman => write!(out, "{}", format_memory(sample.private_bytes, precision))?,
+	        Field::ProcessCount => write!(out, "{}", sample.process_count)?,
+	        Field::RssAnon => write_optional_number(out, sample.rss_anon)?,
+	        Field::RssFile => write_optional_number(out, sample.rss_file)?,
+	        Field::RssShmem => write_optional_number(out, sample.rss_shmem)?,
+	        Field::TimeWeightedAvg => write_optional_number(out, sample.time_weighted_avg)?,
+	        Field::SampleNumber => write!(out, "{}", sample.sample_number)?,
+	        Field::MapCount => write_optional_number(out, sample.map_count)?,
+	        Field::MaxPrivate => write!(out, "{}", sample.peaks.private_bytes)?,
+	        Field::MaxRssAnon => write_optional_number(out, sample.peaks.rss_anon)?,
+	        Field::MaxRssFile => write_optional_number(out, sample.peaks.rss_file)?,
+	        Field::MaxRssShmem => write_optional_number(out, sample.peaks.rss_shmem)?,
+	        Field::MaxMapCount => write_optional_number(out, sample.peaks.map_count)?,
+	        Field::MaxUss => write_optional_number(out, sample.peaks.uss)?,
+	        Field::MaxVsz => write!(out, "{}", sample.peaks.vsz)?,
+	        Field::MaxPss => write_optional_number(out, sample.peaks.pss)?,
+	        Field::MaxSwap => write_optional_number(out, sample.peaks.swap)?,
+	        Field::MaxHugePages => write_optional_number(out, sample.peaks.huge_pages)?,
+	        Field::Uss => write_optional_number(out, sample.uss)?,
+	        Field::Pss => write_optional_number(out, sample.pss)?,
+	        Field::HugePages => write_optional_number(out, sample.huge_pages)?,
+	        Field::Swap => write_optional_number(out, sample.swap)?,
+	        Field::SwapHuman => match sample.swap {
+	            Some(swap) => out.push_str(&format_memory(swap, precision)),
+	            None => out.push_str("n/a"),
+	        },
+	        Field::PercentOfMax => write_optional_percent(out, sample.percent_of_max())?,
+	        Field::CurrentPercent => write_share_of_ram(out, sample.current_percent())?,
+	        Field::MaxPercent => write_share_of_ram(out, sample.max_percent())?,
+	        Field::Delta => write_delta(out, sample.delta(), None)?,
+	        Field::DeltaHuman => write_delta(out, sample.delta(), Some(precision))?,
+	        Field::Vsz => write!(out, "{}", sample.vsz)?,
+	        Field::VszHuman => write!(out, "{}", format_memory(sample.vsz, precision))?,
+	        Field::UssHuman => match sample.uss {
+	            Some(uss) => out.push_str(&format_memory(uss, precision)),
+	            None => out.push_str("n/a"),
+	        },
+	        Field::PssHuman => match sample.pss {
+	            Some(pss) => out.push_str(&format_memory(pss, precision)),
+	            None => out.push_str("n/a"),
+	        },
 	    }
+	    Ok(())
 	}
 
 	#[derive(Debug)]
@@ -87,6 +549,7 @@ pub mod template_engine{
 	#[derive(Debug)]
 	pub struct Template {
 	    pub tokens: Vec<Token>,
+	    pub precision: usize,   // decimals of the human fields
 	}
 	
 	impl Template {
@@ -154,33 +617,28 @@ pub mod template_engine{
    	            tokens.push(Token::Literal(literal));
    	        }
    	
-   	        Ok(Self { tokens })
+   	        Ok(Self { tokens, precision: 0 })
 		}
 
 	    pub fn render(&self, sample: &MemorySample, out: &mut String) -> std::fmt::Result{
             for token in &self.tokens {
                 match token {
                     Token::Literal(s) => out.push_str(s),
-                    Token::Placeholder(placeholder) => {
-                    	match placeholder.field {
-	                        Field::Pid => write!(out, "{}", sample.pid)?,
-	                        Field::ProcessName => out.push_str(sample.process_name),
-	                        Field::CurrentBytes => write!(out, "{}", sample.current_bytes)?,
-	                        Field::MaxBytes => write!(out, "{}", sample.max_bytes)?,
-	                        Field::CurrentHuman => write!(out, "{}",format_memory_from_kib(sample.current_bytes))?,
-	                        Field::MaxHuman => write!(out, "{}", format_memory_from_kib(sample.max_bytes))?,
-	                        Field::Timestamp => write!(out, "{}", sample.timestamp)?,
-	                    }
-                    }
+                    Token::Placeholder(placeholder) => render_field(placeholder.field, sample, self.precision, out)?,
                 }
             }
             Ok(())
         }
+
+	    pub fn uses_any(&self, fields: &[Field]) -> bool {
+	        // lets the sampling loop skip reading what no placeholder shows
+	        self.tokens.iter().any(|token| matches!(token, Token::Placeholder(p) if fields.contains(&p.field)))
+	    }
 	}
 }
 
 
-/// tests
+// tests
 
 #[cfg(test)]
 mod tests {
@@ -192,7 +650,37 @@ mod tests {
             process_name: "firefox",
             current_bytes: 10 * 1024 * 1024, // 10 MB
             max_bytes: 2 * 1024 * 1024 * 1024, // 2 GB
+            private_bytes: 3 * 1024,
+            process_count: 3,
             timestamp: 1_700_000_000,
+            is_final: false,
+            mem_pressure_some: Some(1.5),
+            mem_pressure_full: None,
+            rss_anon: Some(6 * 1024),
+            rss_file: Some(4 * 1024),
+            rss_shmem: None,
+            time_weighted_avg: Some(5 * 1024),
+            sample_number: 12,
+            map_count: Some(731),
+            uss: Some(2 * 1024),
+            pss: Some(3 * 1024),
+            huge_pages: None,
+            swap: Some(512 * 1024),
+            vsz: 64 * 1024 * 1024,
+            mem_total: None,
+            previous_bytes: Some(9 * 1024 * 1024),
+            peaks: Peaks{
+                private_bytes: 4 * 1024,
+                rss_anon: Some(8 * 1024),
+                rss_file: Some(4 * 1024),
+                rss_shmem: None,
+                map_count: Some(802),
+                swap: Some(1024 * 1024),
+                pss: Some(5 * 1024),
+                vsz: 96 * 1024 * 1024,
+                uss: Some(6 * 1024),
+                huge_pages: None,
+            },
         }
     }
 
@@ -202,16 +690,97 @@ mod tests {
 
     #[test]
     fn format_memory_basic_units() {
-        assert_eq!(format_memory_from_kib(0), "0KiB");
-        assert_eq!(format_memory_from_kib(1023), "1023KiB");
-        assert_eq!(format_memory_from_kib(1024), "1MiB");
-        assert_eq!(format_memory_from_kib(1024 * 1024), "1GiB");
+        assert_eq!(format_memory(0, 0), "0B");
+        assert_eq!(format_memory(1023, 0), "1023B");
+        assert_eq!(format_memory(1024, 0), "1KiB");
+        assert_eq!(format_memory(1024 * 1024, 0), "1MiB");
+        assert_eq!(format_memory(1024 * 1024 * 1024, 0), "1GiB");
     }
 
     #[test]
     fn format_memory_large_values() {
-        assert_eq!(format_memory_from_kib(1024u64.pow(4)), "1PiB");
-        assert_eq!(format_memory_from_kib(1024u64.pow(5)), "1EiB");
+        assert_eq!(format_memory(1024u64.pow(5), 0), "1PiB");
+        assert_eq!(format_memory(1024u64.pow(6), 0), "1EiB");
+    }
+
+    #[test]
+    fn format_memory_precision() {
+        // 1.4567 GiB
+        let value = 1527500 * 1024;
+        assert_eq!(format_memory(value, 0), "1GiB");
+        assert_eq!(format_memory(value, 1), "1.5GiB");
+        assert_eq!(format_memory(value, 3), "1.457GiB");
+    }
+
+    #[test]
+    fn format_memory_unit_boundaries() {
+        const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+        for (index, unit) in UNITS.iter().enumerate() {
+            let one = 1u64 << (10 * index);
+            let next = UNITS.get(index + 1).copied().unwrap_or("EiB");
+            assert_eq!(format_memory(1023 * one, 0), format!("1023{}", unit));
+            assert_eq!(format_memory(1024 * one, 0), format!("1{}", next));
+            assert_eq!(format_memory(1025 * one, 0), format!("1{}", next));
+            if index == 0 {
+                assert_eq!(format_memory(1024 * one - 1, 0), format!("1023{}", unit));
+            } else {
+                // just below the next unit rounds up to it, never to 1024
+                assert_eq!(format_memory(1024 * one - 1, 0), format!("1{}", next));
+                assert_eq!(format_memory(1024 * one - 1, 2), format!("1.00{}", next));
+            }
+            assert_eq!(format_memory(1024 * one, 3), format!("1.000{}", next));
+            assert_eq!(format_memory(1025 * one, 3), format!("1.001{}", next));
+        }
+    }
+
+    #[test]
+    fn format_memory_rounding_carries_to_next_unit() {
+        // KiB to MiB: 1023.994KiB stays, 1023.995KiB rounds to 1024.00 and carries
+        assert_eq!(format_memory(1_048_570, 2), "1023.99KiB");
+        assert_eq!(format_memory(1_048_571, 2), "1.00MiB");
+        assert_eq!(format_memory(1023 * 1024 + 511, 0), "1023KiB");
+        assert_eq!(format_memory(1023 * 1024 + 512, 0), "1MiB");
+        // MiB to GiB, the same at 1024^3
+        assert_eq!(format_memory(1_073_735_900, 2), "1023.99MiB");
+        assert_eq!(format_memory(1_073_737_000, 2), "1.00GiB");
+        assert_eq!(format_memory((1023 * 1024 + 512) * 1024, 0), "1GiB");
+        // B to KiB has no decimals to round
+        assert_eq!(format_memory(1023, 2), "1023.00B");
+    }
+
+    #[test]
+    fn format_memory_never_shows_1024() {
+        for precision in [0, 1, 3, MAX_PRECISION] {
+            for shift in 0..64 {
+                for value in [(1u64 << shift).saturating_sub(1), 1u64 << shift, (1u64 << shift) + 1] {
+                    let formatted = format_memory(value, precision);
+                    assert!(!formatted.starts_with("1024"), "{} -> {}", value, formatted);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn format_memory_max_value_with_decimals() {
+        // computed in u128, the biggest value does not overflow, and EiB has no next unit
+        assert_eq!(format_memory(u64::MAX, 3), "16.000EiB");
+        assert_eq!(format_memory(u64::MAX, MAX_PRECISION), "16.000000000EiB");
+    }
+
+    #[test]
+    fn format_memory_precision_pads_and_caps() {
+        assert_eq!(format_memory(1024 + 10, 3), "1.010KiB");
+        assert_eq!(format_memory(512, 2), "512.00B");
+        assert_eq!(format_memory(1536 * 1024, 20), "1.500000000MiB");
+    }
+
+    #[test]
+    fn render_human_fields_with_precision() {
+        let mut t = Template::parse("{CurrentHuman} {MaxHuman}").unwrap();
+        t.precision = 2;
+        let mut out = String::new();
+        t.render(&MemorySample{current_bytes: 1536, max_bytes: 2048, ..Default::default()}, &mut out).unwrap();
+        assert_eq!(out, "1.50KiB 2.00KiB");
     }
 
     // ---------------------------
@@ -227,6 +796,15 @@ mod tests {
         assert_eq!("CurrentHuman".parse::<Field>().unwrap(), Field::CurrentHuman);
         assert_eq!("MaxHuman".parse::<Field>().unwrap(), Field::MaxHuman);
         assert_eq!("Timestamp".parse::<Field>().unwrap(), Field::Timestamp);
+        assert_eq!("Final".parse::<Field>().unwrap(), Field::Final);
+        assert_eq!("MemPressure".parse::<Field>().unwrap(), Field::MemPressure);
+        assert_eq!("MemPressureFull".parse::<Field>().unwrap(), Field::MemPressureFull);
+        assert_eq!("Private".parse::<Field>().unwrap(), Field::Private);
+        assert_eq!("PrivateHuman".parse::<Field>().unwrap(), Field::PrivateHuman);
+        assert_eq!("ProcessCount".parse::<Field>().unwrap(), Field::ProcessCount);
+        assert_eq!("RssAnon".parse::<Field>().unwrap(), Field::RssAnon);
+        assert_eq!("RssFile".parse::<Field>().unwrap(), Field::RssFile);
+        assert_eq!("RssShmem".parse::<Field>().unwrap(), Field::RssShmem);
     }
 
     #[test]
@@ -297,8 +875,7 @@ mod tests {
         let mut out = String::new();
         t.render(&sample(), &mut out).unwrap();
 
-        assert_eq!(out, "10GiB 2TiB"); 
-        // NOTE: This reflects your bitshift logic, not real-world units.
+        assert_eq!(out, "10MiB 2GiB");
     }
 
     #[test]
@@ -310,6 +887,46 @@ mod tests {
         assert_eq!(out, sample().timestamp.to_string());
     }
 
+    #[test]
+    fn render_final_flag() {
+        let t = Template::parse("{Final}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "false");
+
+        out.clear();
+        let final_sample = MemorySample { is_final: true, ..sample() };
+        t.render(&final_sample, &mut out).unwrap();
+        assert_eq!(out, "true");
+    }
+
+    #[test]
+    fn render_memory_pressure() {
+        let t = Template::parse("{MemPressure} {MemPressureFull}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+
+        assert_eq!(out, "1.50 n/a");
+    }
+
+    #[test]
+    fn render_private_fields() {
+        let t = Template::parse("{Private} {PrivateHuman}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+
+        assert_eq!(out, "3072 3KiB");
+    }
+
+    #[test]
+    fn render_process_count() {
+        let t = Template::parse("{ProcessCount}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+
+        assert_eq!(out, "3");
+    }
+
     // ---------------------------
     // Edge behavior
     // ---------------------------
@@ -366,5 +983,309 @@ mod tests {
     fn error_if_empty_placeholder() {
         assert!(Template::parse("{}").is_err());
     }
-    
+
+    #[test]
+    fn render_rss_breakdown() {
+        let t = Template::parse("{RssAnon} {RssFile} {RssShmem}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "6144 4096 n/a");
+    }
+
+    #[test]
+    fn render_time_weighted_avg() {
+        let t = Template::parse("{TimeWeightedAvg}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "5120");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a");
+    }
+
+    #[test]
+    fn render_map_count() {
+        let t = Template::parse("{MapCount}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "731");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a");
+    }
+
+    #[test]
+    fn render_vsz() {
+        let t = Template::parse("{Vsz} {VszHuman}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "67108864 64MiB");
+    }
+
+    #[test]
+    fn render_swap() {
+        let t = Template::parse("{Swap} {SwapHuman}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "524288 512KiB");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a n/a");
+    }
+
+    #[test]
+    fn render_huge_pages() {
+        let t = Template::parse("{HugePages}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "n/a");
+        out.clear();
+        t.render(&MemorySample{huge_pages: Some(2 * 1024 * 1024), ..Default::default()}, &mut out).unwrap();
+        assert_eq!(out, "2097152");
+    }
+
+    #[test]
+    fn render_max_huge_pages() {
+        let t = Template::parse("{HugePages} {MaxHugePages}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "n/a n/a");
+        out.clear();
+        let peaks = Peaks{huge_pages: Some(4 * 1024 * 1024), ..Default::default()};
+        t.render(&MemorySample{huge_pages: Some(2 * 1024 * 1024), peaks, ..Default::default()}, &mut out).unwrap();
+        assert_eq!(out, "2097152 4194304");
+    }
+
+    #[test]
+    fn render_share_of_ram() {
+        let t = Template::parse("{CurrentPercent} {MaxPercent}").unwrap();
+        let mut out = String::new();
+        let ram = 16 * 1024 * 1024 * 1024;
+        t.render(&MemorySample{current_bytes: 0, max_bytes: ram / 8, mem_total: Some(ram), ..Default::default()}, &mut out).unwrap();
+        assert_eq!(out, "0.0% 12.5%");
+        out.clear();
+        // 524MiB of 16GiB
+        t.render(&MemorySample{current_bytes: 524 << 20, max_bytes: 524 << 20, mem_total: Some(ram), ..Default::default()}, &mut out).unwrap();
+        assert_eq!(out, "3.2% 3.2%");
+        out.clear();
+        // a VSZ above the RAM
+        t.render(&MemorySample{current_bytes: 4 * ram, max_bytes: 4 * ram, mem_total: Some(ram), ..Default::default()}, &mut out).unwrap();
+        assert_eq!(out, "100.0% 100.0%");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a n/a");
+    }
+
+    #[test]
+    fn format_iso8601_known_dates() {
+        let iso = |timestamp| {
+            let mut out = String::new();
+            format_iso8601(timestamp, &mut out).unwrap();
+            out
+        };
+        assert_eq!(iso(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso(1_700_000_000), "2023-11-14T22:13:20Z");
+        assert_eq!(iso(951_782_400), "2000-02-29T00:00:00Z");   // leap day of a year divisible by 400
+        assert_eq!(iso(1_709_251_199), "2024-02-29T23:59:59Z");
+        assert_eq!(iso(1_709_251_200), "2024-03-01T00:00:00Z");
+        assert_eq!(iso(4_107_542_400), "2100-03-01T00:00:00Z");   // 2100 is not a leap year
+        assert_eq!(iso(253_402_300_799), "9999-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn render_iso8601() {
+        let t = Template::parse("{Timestamp} {Iso8601}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "1700000000 2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn render_delta_increase() {
+        let t = Template::parse("{Delta} {DeltaHuman}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "+1048576 +1MiB");
+    }
+
+    #[test]
+    fn render_delta_decrease() {
+        let mut t = Template::parse("{Delta} {DeltaHuman}").unwrap();
+        t.precision = 1;
+        let mut out = String::new();
+        t.render(&MemorySample{current_bytes: 1024, previous_bytes: Some(2560), ..Default::default()}, &mut out).unwrap();
+        assert_eq!(out, "-1536 -1.5KiB");
+    }
+
+    #[test]
+    fn render_delta_first_sample() {
+        let t = Template::parse("{Delta} {DeltaHuman}").unwrap();
+        let mut sample = MemorySample::default();
+        sample.next_sample();
+        sample.current_bytes = 4096;
+        let mut out = String::new();
+        t.render(&sample, &mut out).unwrap();
+        assert_eq!(out, "+0 +0B");
+        sample.next_sample();
+        sample.current_bytes = 1024;
+        out.clear();
+        t.render(&sample, &mut out).unwrap();
+        assert_eq!(out, "-3072 -3KiB");
+    }
+
+    #[test]
+    fn render_percent_of_max() {
+        let t = Template::parse("{PercentOfMax}").unwrap();
+        let mut out = String::new();
+        t.render(&MemorySample{current_bytes: 512, max_bytes: 2048, ..Default::default()}, &mut out).unwrap();
+        assert_eq!(out, "25.00");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a");
+    }
+
+    #[test]
+    fn percent_of_max_first_sample() {
+        // the max is updated before the sample is rendered, the first measure is the peak
+        let mut s = MemorySample::default();
+        assert_eq!(s.percent_of_max(), None);
+        s.current_bytes = 1536;
+        s.max_bytes = s.max_bytes.max(s.current_bytes);
+        assert_eq!(s.percent_of_max(), Some(100.0));
+    }
+
+    #[test]
+    fn render_uss() {
+        let t = Template::parse("{Uss} {UssHuman}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "2048 2KiB");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a n/a");
+    }
+
+    #[test]
+    fn render_pss() {
+        let t = Template::parse("{Pss} {PssHuman}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "3072 3KiB");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a n/a");
+    }
+
+    #[test]
+    fn render_peaks() {
+        let t = Template::parse("{MaxPrivate} {MaxRssAnon} {MaxRssFile} {MaxRssShmem} {MaxMapCount}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "4096 8192 4096 n/a 802");
+    }
+
+    #[test]
+    fn render_max_uss() {
+        let t = Template::parse("{Uss} {MaxUss}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "2048 6144");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a n/a");
+    }
+
+    #[test]
+    fn render_max_vsz() {
+        let t = Template::parse("{Vsz} {MaxVsz}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "67108864 100663296");
+    }
+
+    #[test]
+    fn render_max_pss() {
+        let t = Template::parse("{Pss} {MaxPss}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "3072 5120");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a n/a");
+    }
+
+    #[test]
+    fn render_max_swap() {
+        let t = Template::parse("{Swap} {MaxSwap}").unwrap();
+        let mut out = String::new();
+        t.render(&sample(), &mut out).unwrap();
+        assert_eq!(out, "524288 1048576");
+        out.clear();
+        t.render(&MemorySample::default(), &mut out).unwrap();
+        assert_eq!(out, "n/a n/a");
+    }
+
+    #[test]
+    fn update_peaks_keeps_each_maximum() {
+        let mut s = MemorySample{private_bytes: 10, rss_anon: Some(5), map_count: None, ..Default::default()};
+        s.update_peaks();
+        s.private_bytes = 3;
+        s.rss_anon = Some(7);
+        s.map_count = Some(100);
+        s.update_peaks();
+        s.uss = Some(40);
+        s.vsz = 900;
+        s.pss = Some(30);
+        s.swap = Some(0);
+        s.update_peaks();
+        s.rss_anon = None;
+        s.uss = Some(20);
+        s.vsz = 800;
+        s.pss = None;   // unreadable for a sample, the peak stays
+        s.swap = Some(4096);
+        s.huge_pages = Some(2048);
+        s.update_peaks();
+        assert_eq!(
+            s.peaks,
+            Peaks{private_bytes: 10, rss_anon: Some(7), map_count: Some(100), uss: Some(40), vsz: 900, pss: Some(30), swap: Some(4096), huge_pages: Some(2048), ..Default::default()},
+        );
+    }
+
+    #[test]
+    fn template_uses_any() {
+        let t = Template::parse("{Pid} {RssFile}\n").unwrap();
+        assert!(t.uses_any(&[Field::RssAnon, Field::RssFile]));
+        assert!(!t.uses_any(&[Field::RssShmem]));
+    }
+
+    #[test]
+    fn every_field_is_listed_once() {
+        let mut names: Vec<&str> = Field::ALL.iter().map(|field| field.name()).collect();
+        for name in &names {
+            assert_eq!(name.parse::<Field>().unwrap().name(), *name);
+        }
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), Field::ALL.len());
+    }
+
+    #[test]
+    fn list_fields_rows() {
+        let listing = list_fields();
+        assert_eq!(listing.lines().count(), Field::ALL.len());
+        assert!(listing.starts_with("Pid              number  PID of the first target\n"));
+        assert!(listing.contains("\nCurrentBytes     bytes   current RSS of the tree\n"));
+    }
+
+    #[test]
+    fn first_sample_number_is_one() {
+        let mut sample = MemorySample::default();
+        sample.next_sample();
+        let t = Template::parse("#{SampleNumber}").unwrap();
+        let mut out = String::new();
+        t.render(&sample, &mut out).unwrap();
+        assert_eq!(out, "#1");
+        sample.next_sample();
+        assert_eq!(sample.sample_number, 2);
+    }
 }
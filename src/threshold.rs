@@ -0,0 +1,233 @@
+use std::fs;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::proc::{parse_proc_stat_bytes, ProcessState};
+use crate::sys::{send_signal, Signal};
+
+
+pub fn parse_memory_arg(value: &str) -> Result<u64, String> {
+    // human-readable size to bytes: "1048576", "512K", "500MB", "1.5GiB"
+    // SI suffixes (KB, MB, ...) are powers of 1000, IEC ones (KiB, MiB, ...) and
+    // single letters (K, M, ...) are powers of 1024, like ulimit or sort -S
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid memory size {:?}", value))?;
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "K" | "KiB" => 1 << 10,
+        "M" | "MiB" => 1 << 20,
+        "G" | "GiB" => 1 << 30,
+        "T" | "TiB" => 1 << 40,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        other => return Err(format!("unknown memory unit {:?}", other)),
+    };
+    let bytes = number * multiplier as f64;
+    if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+        return Err(format!("memory size {:?} is too large", value));
+    }
+    Ok(bytes.round() as u64)
+}
+
+
+pub fn baseline_limit(baseline: u64, tolerance_percent: f64) -> u64 {
+    // --baseline with --tolerance: the threshold allowing a regression of tolerance_percent
+    // the float to integer cast saturates, a huge tolerance gives an unreachable limit
+    (baseline as f64 * (1.0 + tolerance_percent / 100.0)).floor() as u64
+}
+
+
+pub fn breached(max_bytes: u64, limit: Option<u64>) -> bool {
+    // --threshold and --baseline: decided once the run ends, reaching the limit is still within it
+    limit.is_some_and(|limit| max_bytes > limit)
+}
+
+
+#[derive(Debug)]
+pub struct ThresholdWatch {
+    limit: u64,
+    repeat: bool,   // fire again each time memory goes back above the limit
+    armed: bool,
+}
+
+impl ThresholdWatch {
+    pub fn new(limit: u64, repeat: bool) -> Self {
+        ThresholdWatch{limit, repeat, armed: true}
+    }
+
+    pub fn crossed(&mut self, current: u64) -> bool {
+        // true once when current goes above the limit
+        if current > self.limit {
+            let fire = self.armed;
+            self.armed = false;
+            fire
+        } else {
+            if self.repeat {
+                self.armed = true;
+            }
+            false
+        }
+    }
+}
+
+
+pub fn spawn_hook(command: &str, pid: i32, current_bytes: u64, max_bytes: u64, threshold_bytes: u64) -> Option<Child> {
+    // the hook runs in the background so a slow command does not stall the sampling
+    match Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("MEMIMPACT_PID", pid.to_string())
+        .env("MEMIMPACT_CURRENT_BYTES", current_bytes.to_string())
+        .env("MEMIMPACT_MAX_BYTES", max_bytes.to_string())
+        .env("MEMIMPACT_THRESHOLD_BYTES", threshold_bytes.to_string())
+        .spawn()
+    {
+        Ok(child) => Some(child),
+        Err(e) => {
+            eprintln!("memimpact warning: could not run the threshold hook: {}", e);
+            None
+        }
+    }
+}
+
+
+pub fn reap_hooks(children: &mut Vec<Child>) {
+    // collect finished hooks so they do not stay as zombies during long runs
+    children.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+}
+
+
+fn is_running(pid: &i32) -> bool {
+    // a zombie already released its memory, it only waits for its parent
+    match fs::read(format!("/proc/{}/stat", pid)) {
+        Ok(content) => match parse_proc_stat_bytes(&content) {
+            Ok(stat) => !matches!(stat.state, ProcessState::Z | ProcessState::X),
+            Err(_) => true,
+        },
+        Err(_) => false,
+    }
+}
+
+
+pub fn terminate(pids: &[i32], grace: Duration) -> Vec<i32> {
+    // SIGTERM everything, then SIGKILL what is still running after the grace period
+    // returns the pids which had to be killed
+    for pid in pids {
+        if let Err(e) = send_signal(*pid, Signal::Term) {
+            eprintln!("memimpact warning: could not send SIGTERM to {}: {}", pid, e);
+        }
+    }
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline && pids.iter().any(is_running) {
+        thread::sleep(Duration::from_millis(50));
+    }
+    let survivors: Vec<i32> = pids.iter().filter(|pid| is_running(pid)).copied().collect();
+    for pid in &survivors {
+        if let Err(e) = send_signal(*pid, Signal::Kill) {
+            eprintln!("memimpact warning: could not send SIGKILL to {}: {}", pid, e);
+        }
+    }
+    survivors
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory_arg_plain_bytes() {
+        assert_eq!(parse_memory_arg("1048576"), Ok(1048576));
+        assert_eq!(parse_memory_arg("42B"), Ok(42));
+    }
+
+    #[test]
+    fn test_parse_memory_arg_units() {
+        assert_eq!(parse_memory_arg("512K"), Ok(512 * 1024));
+        assert_eq!(parse_memory_arg("512KiB"), Ok(512 * 1024));
+        assert_eq!(parse_memory_arg("500MB"), Ok(500_000_000));
+        assert_eq!(parse_memory_arg("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_memory_arg("1TB"), Ok(1_000_000_000_000));
+        assert_eq!(parse_memory_arg("1.5G"), Ok(3 * 512 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_parse_memory_arg_invalid() {
+        assert!(parse_memory_arg("").is_err());
+        assert!(parse_memory_arg("MB").is_err());
+        assert!(parse_memory_arg("12 parsecs").is_err());
+        assert!(parse_memory_arg("-1G").is_err());
+        assert!(parse_memory_arg("99999999999TiB").is_err());
+    }
+
+    #[test]
+    fn test_baseline_limit() {
+        assert_eq!(baseline_limit(1000, 10.0), 1100);
+        assert_eq!(baseline_limit(1000, 0.0), 1000);
+        assert_eq!(baseline_limit(1000, 2.55), 1025);
+        assert_eq!(baseline_limit(u64::MAX / 2, 1e9), u64::MAX);
+    }
+
+    #[test]
+    fn test_breached() {
+        let limit = parse_memory_arg("500MB").ok();
+        assert!(!breached(499_999_999, limit));
+        assert!(!breached(500_000_000, limit));
+        assert!(breached(500_000_001, limit));
+        assert!(!breached(u64::MAX, None));
+    }
+
+    #[test]
+    fn test_threshold_fires_once() {
+        let mut watch = ThresholdWatch::new(100, false);
+        assert!(!watch.crossed(50));
+        assert!(watch.crossed(150));
+        assert!(!watch.crossed(200));
+        assert!(!watch.crossed(50));
+        assert!(!watch.crossed(150));
+    }
+
+    #[test]
+    fn test_threshold_repeat_fires_on_each_crossing() {
+        let mut watch = ThresholdWatch::new(100, true);
+        assert!(watch.crossed(150));
+        assert!(!watch.crossed(200));
+        assert!(!watch.crossed(100));
+        assert!(watch.crossed(101));
+    }
+
+    #[test]
+    fn test_hook_receives_environment() {
+        let mut child = spawn_hook("test \"$MEMIMPACT_PID:$MEMIMPACT_CURRENT_BYTES\" = 42:2048", 42, 2048, 4096, 1024).unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_terminate_with_sigterm() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let pid = child.id() as i32;
+        // the child is reaped by wait below, until then it is a zombie which is_running ignores
+        let killed = terminate(&[pid], Duration::from_secs(5));
+        assert!(killed.is_empty());
+        assert!(!child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_terminate_escalates_to_sigkill() {
+        let mut child = Command::new("sh").arg("-c").arg("trap '' TERM; sleep 10").spawn().unwrap();
+        let pid = child.id() as i32;
+        thread::sleep(Duration::from_millis(100));  // let sh install the trap
+        let killed = terminate(&[pid], Duration::from_millis(200));
+        assert_eq!(killed, vec![pid]);
+        assert!(!child.wait().unwrap().success());
+    }
+}
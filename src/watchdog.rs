@@ -0,0 +1,72 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::MemimpactError;
+
+
+// --watchdog: a thread checks that the sampling loop keeps going. A read of /proc
+// can block forever (stuck NFS, kernel bug), the job around memimpact must not.
+
+#[derive(Debug)]
+pub struct Watchdog {
+    started: Instant,
+    last_feed_ms: Arc<AtomicU64>,   // since started, of the last completed iteration
+}
+
+
+pub fn is_hung(last_feed_ms: u64, now_ms: u64, timeout: Duration) -> bool {
+    now_ms.saturating_sub(last_feed_ms) > timeout.as_millis() as u64
+}
+
+
+impl Watchdog {
+    pub fn spawn(timeout: Duration) -> Self {
+        let started = Instant::now();
+        let last_feed_ms = Arc::new(AtomicU64::new(0));
+        let watched = Arc::clone(&last_feed_ms);
+        // checking 10 times per timeout bounds how late the hang is noticed
+        let check_every = (timeout / 10).max(Duration::from_millis(10));
+        thread::spawn(move || loop {
+            thread::sleep(check_every);
+            let now_ms = started.elapsed().as_millis() as u64;
+            if is_hung(watched.load(Ordering::Relaxed), now_ms, timeout) {
+                // the main thread is stuck, it cannot unwind: exit from here
+                let e = MemimpactError::Hung(timeout.as_secs());
+                eprintln!("memimpact error: {}", e);
+                std::process::exit(e.exit_code() as i32);
+            }
+        });
+        Watchdog{started, last_feed_ms}
+    }
+
+    pub fn feed(&self) {
+        self.last_feed_ms.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+
+// tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_hung() {
+        let timeout = Duration::from_secs(5);
+        assert!(!is_hung(1_000, 6_000, timeout));
+        assert!(is_hung(1_000, 6_001, timeout));
+        // a feed racing the check is never a hang
+        assert!(!is_hung(7_000, 6_000, timeout));
+    }
+
+    #[test]
+    fn test_feed_records_progress() {
+        let watchdog = Watchdog::spawn(Duration::from_secs(3600));
+        thread::sleep(Duration::from_millis(20));
+        watchdog.feed();
+        assert!(watchdog.last_feed_ms.load(Ordering::Relaxed) >= 20);
+    }
+}